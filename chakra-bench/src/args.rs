@@ -0,0 +1,201 @@
+use std::{env, fmt, path::PathBuf, time::Duration};
+
+pub const USAGE: &str = "\
+usage: chakra-bench [options] <file>
+
+  --mode MODE          read, write, randread, randwrite, rw or randrw [randread]
+  --read-percent N     share of reads in the rw and randrw modes [50]
+  --size BYTES         bytes of the file to run over, laid out if missing [256m]
+  --block-size BYTES   bytes per operation [4k]
+  --depth N            operations kept in flight [32]
+  --batch N            completions reaped, and submissions made, at once [1]
+  --runtime SECS       how long to run for [10]
+  --ios N              stop after this many operations instead
+  --direct             open the file with O_DIRECT
+  --sqpoll             poll the submission queue from a kernel thread
+  --iopoll             busy-poll for completions, implies --direct
+  --fixed-buffers      use registered buffers
+  --fixed-files        use a registered file
+
+Sizes take a k, m or g suffix.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Sequential,
+    Random,
+}
+
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub path: PathBuf,
+    pub pattern: Pattern,
+    /// Percentage of operations that are reads
+    pub read_percent: u32,
+    pub size: u64,
+    pub block_size: usize,
+    pub depth: usize,
+    pub batch: usize,
+    pub runtime: Duration,
+    pub ios: Option<u64>,
+    pub direct: bool,
+    pub sqpoll: bool,
+    pub iopoll: bool,
+    pub fixed_buffers: bool,
+    pub fixed_files: bool,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, String> {
+        let mut args = Args {
+            path: PathBuf::new(),
+            pattern: Pattern::Random,
+            read_percent: 100,
+            size: 256 << 20,
+            block_size: 4096,
+            depth: 32,
+            batch: 1,
+            runtime: Duration::from_secs(10),
+            ios: None,
+            direct: false,
+            sqpoll: false,
+            iopoll: false,
+            fixed_buffers: false,
+            fixed_files: false,
+        };
+        let mut mixed_read_percent = 50;
+        let mut path = None;
+
+        let mut argv = env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            let mut value = || argv.next().ok_or_else(|| format!("{} takes a value", arg));
+
+            match arg.as_str() {
+                "--mode" => {
+                    let (pattern, read_percent) = match value()?.as_str() {
+                        "read" => (Pattern::Sequential, Some(100)),
+                        "write" => (Pattern::Sequential, Some(0)),
+                        "randread" => (Pattern::Random, Some(100)),
+                        "randwrite" => (Pattern::Random, Some(0)),
+                        "rw" => (Pattern::Sequential, None),
+                        "randrw" => (Pattern::Random, None),
+                        mode => return Err(format!("unknown mode {}", mode)),
+                    };
+                    args.pattern = pattern;
+                    args.read_percent = read_percent.unwrap_or(u32::MAX);
+                }
+                "--read-percent" => {
+                    mixed_read_percent = number(&arg, &value()?)?;
+                    if mixed_read_percent > 100 {
+                        return Err("--read-percent is at most 100".into());
+                    }
+                }
+                "--size" => args.size = size(&arg, &value()?)?,
+                "--block-size" => args.block_size = size(&arg, &value()?)? as usize,
+                "--depth" => args.depth = number(&arg, &value()?)?,
+                "--batch" => args.batch = number(&arg, &value()?)?,
+                "--runtime" => args.runtime = Duration::from_secs(number(&arg, &value()?)?),
+                "--ios" => args.ios = Some(number(&arg, &value()?)?),
+                "--direct" => args.direct = true,
+                "--sqpoll" => args.sqpoll = true,
+                "--iopoll" => {
+                    args.iopoll = true;
+                    args.direct = true;
+                }
+                "--fixed-buffers" => args.fixed_buffers = true,
+                "--fixed-files" => args.fixed_files = true,
+                "-h" | "--help" => return Err(String::new()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ if path.is_none() => path = Some(PathBuf::from(arg)),
+                _ => return Err("expected a single file".into()),
+            }
+        }
+
+        // The mixed modes are the ones left without a fixed share of reads
+        if args.read_percent == u32::MAX {
+            args.read_percent = mixed_read_percent;
+        }
+
+        args.path = path.ok_or("expected a file to run over")?;
+        if args.block_size == 0 || args.depth == 0 || args.batch == 0 {
+            return Err("--block-size, --depth and --batch have to be positive".into());
+        }
+        if args.size < args.block_size as u64 {
+            return Err("--size has to be at least one block".into());
+        }
+        if args.batch > args.depth {
+            return Err("--batch can't be larger than --depth".into());
+        }
+
+        Ok(args)
+    }
+
+    /// Number of whole blocks in the part of the file run over
+    pub fn blocks(&self) -> u64 {
+        self.size / self.block_size as u64
+    }
+
+    pub fn mode(&self) -> &'static str {
+        match (self.pattern, self.read_percent) {
+            (Pattern::Sequential, 100) => "read",
+            (Pattern::Sequential, 0) => "write",
+            (Pattern::Sequential, _) => "rw",
+            (Pattern::Random, 100) => "randread",
+            (Pattern::Random, 0) => "randwrite",
+            (Pattern::Random, _) => "randrw",
+        }
+    }
+}
+
+impl fmt::Display for Args {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: mode={} size={} block_size={} depth={} batch={}",
+            self.path.display(),
+            self.mode(),
+            self.size,
+            self.block_size,
+            self.depth,
+            self.batch
+        )?;
+
+        if self.read_percent != 0 && self.read_percent != 100 {
+            write!(f, " read_percent={}", self.read_percent)?;
+        }
+        for (set, name) in &[
+            (self.direct, "direct"),
+            (self.sqpoll, "sqpoll"),
+            (self.iopoll, "iopoll"),
+            (self.fixed_buffers, "fixed_buffers"),
+            (self.fixed_files, "fixed_files"),
+        ] {
+            if *set {
+                write!(f, " {}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} takes a number, got {}", arg, value))
+}
+
+/// Parse a byte count with an optional k, m or g suffix
+fn size(arg: &str, value: &str) -> Result<u64, String> {
+    let lower = value.to_ascii_lowercase();
+    let (digits, shift) = match lower.chars().last() {
+        Some('k') => (&lower[..lower.len() - 1], 10),
+        Some('m') => (&lower[..lower.len() - 1], 20),
+        Some('g') => (&lower[..lower.len() - 1], 30),
+        _ => (&lower[..], 0),
+    };
+
+    let n: u64 = number(arg, digits)?;
+    n.checked_shl(shift)
+        .filter(|&bytes| bytes >> shift == n)
+        .ok_or_else(|| format!("{} is too large: {}", arg, value))
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+/// Sub-buckets per power of two, which bounds the error of a recorded value
+/// to about 3%
+const SUB_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+const BUCKETS: usize = SUB_BUCKETS + (64 - SUB_BITS as usize) * SUB_BUCKETS;
+
+/// A log-linear histogram of latencies in nanoseconds.
+///
+/// Values below `SUB_BUCKETS` get a bucket each, larger ones share a bucket
+/// with the values that agree on their top `SUB_BITS + 1` bits.
+pub struct Histogram {
+    buckets: Box<[u64]>,
+    count: u64,
+    sum: u128,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            buckets: vec![0; BUCKETS].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+
+        self.buckets[index(nanos)] += 1;
+        self.count += 1;
+        self.sum += nanos as u128;
+        self.max = self.max.max(nanos);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            n => Duration::from_nanos((self.sum / n as u128) as u64),
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// The latency `quantile` of the recorded ones are at or below, e.g. 0.99
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        let rank = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        for (index, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_nanos(midpoint(index).min(self.max));
+            }
+        }
+
+        Duration::ZERO
+    }
+}
+
+fn index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - SUB_BITS;
+    let sub = (value >> shift) as usize - SUB_BUCKETS;
+
+    SUB_BUCKETS + shift as usize * SUB_BUCKETS + sub
+}
+
+/// Middle of the range of values that land in bucket `index`
+fn midpoint(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub = (index - SUB_BUCKETS) % SUB_BUCKETS;
+    let low = ((SUB_BUCKETS + sub) as u64) << shift;
+
+    low + ((1u64 << shift) - 1) / 2
+}
@@ -0,0 +1,382 @@
+//! A fio-like benchmark for comparing ring configurations.
+//!
+//! Runs sequential or random reads, writes or a mix of both over a file with a
+//! fixed number of operations in flight, and reports IOPS, bandwidth and
+//! completion latency percentiles for each kind of operation. Point it at a
+//! file on tmpfs to take the storage device out of the picture.
+
+mod args;
+mod histogram;
+
+use std::{
+    alloc::{self, Layout},
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::{fs::OpenOptionsExt, io::AsRawFd, io::RawFd},
+    process, slice,
+    time::{Duration, Instant},
+};
+
+use chakra::{FeatureFlags, IoRing, IoRingBuilder, Sqe, SqeFlags};
+
+use crate::{
+    args::{Args, Pattern, USAGE},
+    histogram::Histogram,
+};
+
+/// Alignment of the buffers, enough for O_DIRECT on any common device
+const ALIGN: usize = 4096;
+/// How long an idle SQPOLL thread keeps polling before it goes to sleep
+const SQ_THREAD_IDLE: Duration = Duration::from_secs(1);
+
+fn main() {
+    let args = Args::parse().unwrap_or_else(|e| {
+        if !e.is_empty() {
+            eprintln!("{}", e);
+        }
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    });
+
+    if let Err(e) = run(args) {
+        eprintln!("chakra-bench: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(mut args: Args) -> io::Result<()> {
+    lay_out(&args)?;
+
+    let mut options = OpenOptions::new();
+    options.read(true).write(true);
+    if args.direct {
+        options.custom_flags(libc::O_DIRECT);
+    }
+    let file = options.open(&args.path)?;
+
+    let mut builder = IoRingBuilder::new().sq_entries(args.depth as u32);
+    if args.sqpoll {
+        builder = builder.sqpoll(SQ_THREAD_IDLE, None);
+    }
+    if args.iopoll {
+        builder = builder.iopoll();
+    }
+    let (mut ring, params) = builder.build()?;
+
+    if args.sqpoll
+        && !args.fixed_files
+        && !params
+            .features
+            .contains(FeatureFlags::IORING_FEAT_SQPOLL_NONFIXED)
+    {
+        eprintln!("this kernel only supports SQPOLL with registered files, using --fixed-files");
+        args.fixed_files = true;
+    }
+
+    println!("{}", args);
+
+    let mut bench = Bench::new(&args, &mut ring, &file)?;
+    let start = Instant::now();
+    bench.run(&args)?;
+    let elapsed = start.elapsed();
+
+    bench.report(&args, elapsed);
+    bench.finish(&args)
+}
+
+/// Make sure the file holds `args.size` bytes of data. Reading holes never
+/// reaches the device, so the data is actually written rather than the file
+/// being extended.
+fn lay_out(args: &Args) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&args.path)?;
+
+    let len = file.metadata()?.len();
+    if len >= args.size {
+        return Ok(());
+    }
+
+    let chunk = vec![0xa5; 1 << 20];
+    let mut written = 0;
+    while written < args.size {
+        let n = (args.size - written).min(chunk.len() as u64) as usize;
+        file.write_all(&chunk[..n])?;
+        written += n as u64;
+    }
+
+    file.sync_all()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Read,
+    Write,
+}
+
+/// One of the `depth` operations in flight, `user_data` of its SQE is its index
+struct Slot {
+    state: State,
+    started: Instant,
+}
+
+struct Bench<'a> {
+    ring: &'a mut IoRing,
+    /// `depth` buffers of `block_size` bytes each
+    buffers: Buffers,
+    slots: Vec<Slot>,
+    /// File descriptor SQEs refer to, the index 0 with registered files
+    fd: RawFd,
+    sqe_flags: SqeFlags,
+    rng: XorShift,
+    next_block: u64,
+    issued: u64,
+    in_flight: usize,
+    reads: Histogram,
+    writes: Histogram,
+}
+
+impl<'a> Bench<'a> {
+    fn new(args: &Args, ring: &'a mut IoRing, file: &File) -> io::Result<Self> {
+        let mut buffers = Buffers::new(args.depth, args.block_size);
+        let mut fd = file.as_raw_fd();
+        let mut sqe_flags = SqeFlags::empty();
+
+        if args.fixed_buffers {
+            let iovecs: Vec<_> = (0..args.depth)
+                .map(|index| {
+                    let buf = buffers.get(index);
+                    libc::iovec {
+                        iov_base: buf.as_mut_ptr() as *mut _,
+                        iov_len: buf.len(),
+                    }
+                })
+                .collect();
+            // The buffers are unregistered before they're freed, see `finish`
+            unsafe { ring.register_buffers(&iovecs)? };
+        }
+
+        if args.fixed_files {
+            ring.register_files(&[fd])?;
+            fd = 0;
+            sqe_flags |= SqeFlags::IOSQE_FIXED_FILE;
+        }
+
+        Ok(Bench {
+            ring,
+            buffers,
+            slots: (0..args.depth)
+                .map(|_| Slot {
+                    state: State::Idle,
+                    started: Instant::now(),
+                })
+                .collect(),
+            fd,
+            sqe_flags,
+            rng: XorShift::new(0x9e37_79b9_7f4a_7c15),
+            next_block: 0,
+            issued: 0,
+            in_flight: 0,
+            reads: Histogram::new(),
+            writes: Histogram::new(),
+        })
+    }
+
+    fn run(&mut self, args: &Args) -> io::Result<()> {
+        let deadline = Instant::now() + args.runtime;
+        let limit = args.ios.unwrap_or(u64::MAX);
+
+        loop {
+            // Put every idle slot back to work, unless the run is over
+            if self.issued < limit && (args.ios.is_some() || Instant::now() < deadline) {
+                for index in 0..self.slots.len() {
+                    if self.slots[index].state == State::Idle && self.issued < limit {
+                        self.queue(args, index)?;
+                    }
+                }
+            }
+
+            if self.in_flight == 0 {
+                return Ok(());
+            }
+
+            let wait = args.batch.min(self.in_flight);
+            self.ring.submit_and_wait(wait as u32)?;
+
+            let mut reaped = 0;
+            while reaped < wait || self.ring.state().cq_ready > 0 {
+                let cqe = match self.ring.peek_cqe() {
+                    Some(cqe) => cqe,
+                    None => self.ring.wait_cqe()?,
+                };
+                let now = Instant::now();
+                let slot = &mut self.slots[cqe.user_data() as usize];
+
+                let n = cqe.result()?;
+                if n as usize != args.block_size {
+                    return Err(io::Error::other(format!(
+                        "short {:?}: {} of {} bytes",
+                        slot.state, n, args.block_size
+                    )));
+                }
+
+                let latency = now - slot.started;
+                match slot.state {
+                    State::Read => self.reads.record(latency),
+                    _ => self.writes.record(latency),
+                }
+                slot.state = State::Idle;
+                self.in_flight -= 1;
+                reaped += 1;
+            }
+        }
+    }
+
+    fn queue(&mut self, args: &Args, index: usize) -> io::Result<()> {
+        let block = match args.pattern {
+            Pattern::Sequential => {
+                let block = self.next_block;
+                self.next_block = (self.next_block + 1) % args.blocks();
+                block
+            }
+            Pattern::Random => self.rng.next() % args.blocks(),
+        };
+        let offset = block as usize * args.block_size;
+        let read = self.rng.next() % 100 < args.read_percent as u64;
+
+        let mut sqe = self.sqe()?;
+        let buf = self.buffers.get(index);
+        let buf_index = index as u16;
+
+        match (read, args.fixed_buffers) {
+            (true, false) => sqe.prep_read(self.fd, buf, offset)?,
+            (true, true) => sqe.prep_read_fixed(self.fd, buf, offset, buf_index)?,
+            (false, false) => sqe.prep_write(self.fd, buf, offset)?,
+            (false, true) => sqe.prep_write_fixed(self.fd, buf, offset, buf_index)?,
+        }
+        sqe.set_flags(self.sqe_flags);
+        sqe.set_user_data(index as u64);
+
+        self.slots[index] = Slot {
+            state: if read { State::Read } else { State::Write },
+            started: Instant::now(),
+        };
+        self.issued += 1;
+        self.in_flight += 1;
+
+        Ok(())
+    }
+
+    fn sqe(&mut self) -> io::Result<Sqe> {
+        if let Some(sqe) = self.ring.get_sqe() {
+            return Ok(sqe);
+        }
+
+        // The queue holds `depth` entries, but an SQPOLL thread may not have
+        // consumed the previous batch yet
+        self.ring.sq_wait()?;
+        self.ring
+            .get_sqe()
+            .ok_or_else(|| io::Error::other("submission queue is full"))
+    }
+
+    /// Unregister what `new` registered, before the buffers are freed
+    fn finish(self, args: &Args) -> io::Result<()> {
+        if args.fixed_buffers {
+            self.ring.unregister_buffers()?;
+        }
+        if args.fixed_files {
+            self.ring.unregister_files()?;
+        }
+
+        Ok(())
+    }
+
+    fn report(&self, args: &Args, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+
+        for (name, histogram) in &[("read", &self.reads), ("write", &self.writes)] {
+            let count = histogram.count();
+            if count == 0 {
+                continue;
+            }
+
+            let bytes = count * args.block_size as u64;
+            println!(
+                "{:>5}: {} ios, {:.0} IOPS, {:.1} MiB/s",
+                name,
+                count,
+                count as f64 / secs,
+                bytes as f64 / (1024.0 * 1024.0) / secs
+            );
+            println!(
+                "       lat mean {}, p50 {}, p99 {}, p999 {}, max {}",
+                micros(histogram.mean()),
+                micros(histogram.percentile(0.5)),
+                micros(histogram.percentile(0.99)),
+                micros(histogram.percentile(0.999)),
+                micros(histogram.max()),
+            );
+        }
+
+        println!("{:.3}s elapsed", secs);
+    }
+}
+
+fn micros(d: Duration) -> String {
+    format!("{:.1}us", d.as_nanos() as f64 / 1000.0)
+}
+
+/// Page aligned memory for the operation buffers, as O_DIRECT requires
+struct Buffers {
+    ptr: *mut u8,
+    layout: Layout,
+    block_size: usize,
+}
+
+impl Buffers {
+    fn new(count: usize, block_size: usize) -> Self {
+        let layout = Layout::from_size_align(count * block_size, ALIGN).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        Buffers {
+            ptr,
+            layout,
+            block_size,
+        }
+    }
+
+    /// Buffer `index`, which is only handed to a new operation once the
+    /// previous one using it has completed
+    fn get(&mut self, index: usize) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.add(index * self.block_size), self.block_size) }
+    }
+}
+
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) };
+    }
+}
+
+/// xorshift64*, good enough to pick offsets and operations
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        XorShift(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
@@ -0,0 +1,149 @@
+//! Pretty-print a recording made with `IoRing::record`.
+//!
+//! Usage: chakra-dump [--user-data N] <recording>
+//!
+//! Prints the ring the recording was made on, then one line per submitted SQE
+//! and reaped completion, with the seconds since the recording started. With
+//! `--user-data` only the events of operations with that `user_data` are
+//! printed.
+
+use std::{env, io, process};
+
+use chakra::{
+    record::{CqeRecord, Event, Header, Recording, SqeRecord},
+    FeatureFlags, Flags, IoUringOp, SqeFlags,
+};
+
+fn main() {
+    let mut path = None;
+    let mut user_data = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--user-data" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => user_data = Some(n),
+                None => usage("--user-data takes a number"),
+            },
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(&format!("unknown argument {}", arg)),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage("no recording given"));
+
+    if let Err(e) = dump(&path, user_data) {
+        eprintln!("{}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("usage: chakra-dump [--user-data N] <recording>");
+    process::exit(2);
+}
+
+fn dump(path: &str, user_data: Option<u64>) -> io::Result<()> {
+    let recording = Recording::open(path)?;
+    print_header(recording.header());
+
+    let (mut submitted, mut completed) = (0, 0);
+    for record in recording {
+        let record = record?;
+        let time = record.time.as_secs_f64();
+
+        match record.event {
+            Event::Submit(sqe) => {
+                submitted += 1;
+                if user_data.is_none_or(|u| u == sqe.user_data) {
+                    println!(
+                        "{:12.6}  submit   {:>20}  {}",
+                        time,
+                        sqe.user_data,
+                        sqe_details(&sqe)
+                    );
+                }
+            }
+            Event::Complete(cqe) => {
+                completed += 1;
+                if user_data.is_none_or(|u| u == cqe.user_data) {
+                    println!(
+                        "{:12.6}  complete {:>20}  {}",
+                        time,
+                        cqe.user_data,
+                        cqe_details(&cqe)
+                    );
+                }
+            }
+        }
+    }
+
+    println!();
+    println!("{} submitted, {} completed", submitted, completed);
+    Ok(())
+}
+
+fn print_header(header: &Header) {
+    println!(
+        "ring:     {} SQ entries, {} CQ entries",
+        header.sq_entries, header.cq_entries
+    );
+    println!("flags:    {:?}", Flags::from_bits_truncate(header.flags));
+    println!(
+        "features: {:?}",
+        FeatureFlags::from_bits_truncate(header.features)
+    );
+    println!();
+    println!("{:>12}  {:8} {:>20}  details", "time", "event", "user_data");
+}
+
+fn sqe_details(sqe: &SqeRecord) -> String {
+    let op = match sqe.op() {
+        Some(op) => format!("{:?}", op)
+            .trim_start_matches("IORING_OP_")
+            .to_owned(),
+        None => format!("opcode {}", sqe.opcode),
+    };
+
+    let mut details = format!(
+        "{} fd={} off={} addr={:#x} len={}",
+        op, sqe.fd, sqe.off as i64, sqe.addr, sqe.len
+    );
+    if sqe.flags != 0 {
+        details += &format!(" flags={:?}", SqeFlags::from_bits_truncate(sqe.flags));
+    }
+    if sqe.op_flags != 0 {
+        details += &format!(" op_flags={:#x}", sqe.op_flags);
+    }
+    if sqe.ioprio != 0 {
+        details += &format!(" ioprio={}", sqe.ioprio);
+    }
+    if sqe.buf_index != 0 {
+        details += &format!(" buf_index={}", sqe.buf_index);
+    }
+    if sqe.personality != 0 {
+        details += &format!(" personality={}", sqe.personality);
+    }
+    if let Some(IoUringOp::IORING_OP_SPLICE) | Some(IoUringOp::IORING_OP_TEE) = sqe.op() {
+        details += &format!(" fd_in={}", sqe.splice_fd_in);
+    }
+
+    details
+}
+
+fn cqe_details(cqe: &CqeRecord) -> String {
+    let mut details = if cqe.res < 0 {
+        format!(
+            "res={}: {}",
+            cqe.res,
+            io::Error::from_raw_os_error(-cqe.res)
+        )
+    } else {
+        format!("res={}", cqe.res)
+    };
+    if cqe.flags != 0 {
+        details += &format!(" flags={:#x}", cqe.flags);
+    }
+
+    details
+}
@@ -0,0 +1,314 @@
+//! Report what io_uring support the host offers.
+//!
+//! Usage: chakra-probe [--json] [--entries N]
+//!
+//! Sets up a ring and prints its parameters, the features and opcodes the
+//! kernel supports, and the limits and policies that commonly stop rings from
+//! being created: `RLIMIT_MEMLOCK`, the `kernel.io_uring_disabled` sysctl and
+//! seccomp filters. Exits with 1 if no ring could be set up.
+
+use std::{env, ffi::CStr, fs, mem, process};
+
+use chakra::{Error, FeatureFlags, IoRingBuilder, IoRingParams, IoUringOp, Probe};
+use serde_json::{json, Value};
+
+const DEFAULT_ENTRIES: u32 = 8;
+
+const FEATURES: &[(FeatureFlags, &str)] = &[
+    (
+        FeatureFlags::IORING_FEAT_SINGLE_MMAP,
+        "IORING_FEAT_SINGLE_MMAP",
+    ),
+    (FeatureFlags::IORING_FEAT_NODROP, "IORING_FEAT_NODROP"),
+    (
+        FeatureFlags::IORING_FEAT_SUBMIT_STABLE,
+        "IORING_FEAT_SUBMIT_STABLE",
+    ),
+    (
+        FeatureFlags::IORING_FEAT_RW_CUR_POS,
+        "IORING_FEAT_RW_CUR_POS",
+    ),
+    (
+        FeatureFlags::IORING_FEAT_CUR_PERSONALITY,
+        "IORING_FEAT_CUR_PERSONALITY",
+    ),
+    (FeatureFlags::IORING_FEAT_FAST_POLL, "IORING_FEAT_FAST_POLL"),
+    (
+        FeatureFlags::IORING_FEAT_POLL_32BITS,
+        "IORING_FEAT_POLL_32BITS",
+    ),
+    (
+        FeatureFlags::IORING_FEAT_SQPOLL_NONFIXED,
+        "IORING_FEAT_SQPOLL_NONFIXED",
+    ),
+];
+
+/// Everything found out about the host
+struct Report {
+    kernel: Option<String>,
+    memlock: Option<(u64, u64)>,
+    /// Value of the `kernel.io_uring_disabled` sysctl, missing before Linux 6.6
+    io_uring_disabled: Option<u32>,
+    /// `Seccomp:` field of /proc/self/status: 0 off, 1 strict, 2 filtering
+    seccomp: Option<u32>,
+    ring: Result<(IoRingParams, Result<Probe, Error>), Error>,
+}
+
+fn main() {
+    let mut json = false;
+    let mut entries = DEFAULT_ENTRIES;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--entries" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => entries = n,
+                None => usage("--entries takes a number"),
+            },
+            _ => usage(&format!("unknown argument {}", arg)),
+        }
+    }
+
+    let report = Report {
+        kernel: kernel_release(),
+        memlock: memlock_limit(),
+        io_uring_disabled: read_proc("/proc/sys/kernel/io_uring_disabled")
+            .and_then(|v| v.trim().parse().ok()),
+        seccomp: seccomp_mode(),
+        ring: IoRingBuilder::new()
+            .sq_entries(entries)
+            .build()
+            .map(|(ring, params)| (params, ring.probe())),
+    };
+
+    if json {
+        println!("{:#}", report.to_json());
+    } else {
+        report.print();
+    }
+
+    if report.ring.is_err() {
+        process::exit(1);
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("usage: chakra-probe [--json] [--entries N]");
+    process::exit(2);
+}
+
+impl Report {
+    /// Why setting up a ring failed, as far as can be told from the outside
+    fn diagnosis(&self) -> Option<String> {
+        let e = self.ring.as_ref().err()?;
+
+        Some(match (e, self.io_uring_disabled, self.seccomp) {
+            (Error::PermissionDenied, Some(2), _) => {
+                "io_uring is disabled by the kernel.io_uring_disabled sysctl".into()
+            }
+            (Error::PermissionDenied, Some(1), _) => {
+                "io_uring is restricted to the kernel.io_uring_group group by the \
+                 kernel.io_uring_disabled sysctl"
+                    .into()
+            }
+            (Error::PermissionDenied, _, Some(2)) => {
+                "io_uring is likely blocked by a seccomp filter, e.g. the container \
+                 runtime's default profile"
+                    .into()
+            }
+            _ => e.to_string(),
+        })
+    }
+
+    fn print(&self) {
+        println!("kernel:             {}", or_unknown(self.kernel.as_deref()));
+        match self.memlock {
+            Some((cur, max)) => println!("RLIMIT_MEMLOCK:     {} (max {})", limit(cur), limit(max)),
+            None => println!("RLIMIT_MEMLOCK:     unknown"),
+        }
+        match self.io_uring_disabled {
+            Some(value) => println!("io_uring_disabled:  {}", value),
+            None => println!("io_uring_disabled:  not present"),
+        }
+        match self.seccomp {
+            Some(mode) => println!("seccomp:            {}", seccomp_name(mode)),
+            None => println!("seccomp:            unknown"),
+        }
+        println!();
+
+        let (params, probe) = match &self.ring {
+            Ok(ring) => ring,
+            Err(_) => {
+                println!(
+                    "ring setup failed: {}",
+                    self.diagnosis().unwrap_or_default()
+                );
+                return;
+            }
+        };
+
+        println!("sq_entries: {}", params.sq_entries);
+        println!("cq_entries: {}", params.cq_entries);
+        println!("sq_off:     {:?}", params.sq_off);
+        println!("cq_off:     {:?}", params.cq_off);
+        println!();
+
+        println!("features:");
+        for &(flag, name) in FEATURES {
+            println!("  {:<30} {}", name, yes_no(params.features.contains(flag)));
+        }
+        println!();
+
+        match probe {
+            Ok(probe) => {
+                println!("opcodes (kernel knows up to {}):", probe.last_op());
+                for &op in IoUringOp::ALL.iter() {
+                    println!(
+                        "  {:<30} {}",
+                        format!("{:?}", op),
+                        yes_no(probe.is_supported(op))
+                    );
+                }
+            }
+            Err(e) => println!("opcodes: probing failed, it needs Linux 5.6: {}", e),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let ring = match &self.ring {
+            Ok((params, probe)) => json!({
+                "ok": true,
+                "params": {
+                    "sq_entries": params.sq_entries,
+                    "cq_entries": params.cq_entries,
+                    "sq_off": {
+                        "head": params.sq_off.head,
+                        "tail": params.sq_off.tail,
+                        "ring_mask": params.sq_off.ring_mask,
+                        "ring_entries": params.sq_off.ring_entries,
+                        "flags": params.sq_off.flags,
+                        "dropped": params.sq_off.dropped,
+                        "array": params.sq_off.array,
+                    },
+                    "cq_off": {
+                        "head": params.cq_off.head,
+                        "tail": params.cq_off.tail,
+                        "ring_mask": params.cq_off.ring_mask,
+                        "ring_entries": params.cq_off.ring_entries,
+                        "overflow": params.cq_off.overflow,
+                        "cqes": params.cq_off.cqes,
+                        "flags": params.cq_off.flags,
+                    },
+                },
+                "features": FEATURES
+                    .iter()
+                    .filter(|(flag, _)| params.features.contains(*flag))
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>(),
+                "probe": match probe {
+                    Ok(probe) => json!({
+                        "last_op": probe.last_op(),
+                        "supported": probe.supported().map(|op| format!("{:?}", op)).collect::<Vec<_>>(),
+                        "unsupported": IoUringOp::ALL
+                            .iter()
+                            .filter(|&&op| !probe.is_supported(op))
+                            .map(|op| format!("{:?}", op))
+                            .collect::<Vec<_>>(),
+                    }),
+                    Err(e) => json!({ "error": e.to_string() }),
+                },
+            }),
+            Err(e) => json!({
+                "ok": false,
+                "errno": e.raw_os_error(),
+                "error": e.to_string(),
+                "diagnosis": self.diagnosis(),
+            }),
+        };
+
+        json!({
+            "kernel": self.kernel,
+            "memlock": self.memlock.map(|(cur, max)| json!({
+                "current": limit_json(cur),
+                "max": limit_json(max),
+            })),
+            "io_uring_disabled": self.io_uring_disabled,
+            "seccomp": self.seccomp.map(seccomp_name),
+            "ring": ring,
+        })
+    }
+}
+
+fn kernel_release() -> Option<String> {
+    let mut uts: libc::utsname = unsafe { mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().into_owned())
+}
+
+/// The current and maximum `RLIMIT_MEMLOCK`, in bytes
+fn memlock_limit() -> Option<(u64, u64)> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0 {
+        return None;
+    }
+
+    Some((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+fn seccomp_mode() -> Option<u32> {
+    read_proc("/proc/self/status")?
+        .lines()
+        .find_map(|line| line.strip_prefix("Seccomp:"))
+        .and_then(|mode| mode.trim().parse().ok())
+}
+
+fn read_proc(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+fn seccomp_name(mode: u32) -> &'static str {
+    match mode {
+        0 => "disabled",
+        1 => "strict",
+        2 => "filter",
+        _ => "unknown",
+    }
+}
+
+fn limit(value: u64) -> String {
+    if value == libc::RLIM_INFINITY as u64 {
+        "unlimited".into()
+    } else {
+        format!("{} bytes", value)
+    }
+}
+
+fn limit_json(value: u64) -> Value {
+    if value == libc::RLIM_INFINITY as u64 {
+        Value::Null
+    } else {
+        value.into()
+    }
+}
+
+fn or_unknown(value: Option<&str>) -> &str {
+    value.unwrap_or("unknown")
+}
+
+fn yes_no(supported: bool) -> &'static str {
+    if supported {
+        "yes"
+    } else {
+        "no"
+    }
+}
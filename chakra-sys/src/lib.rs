@@ -19,8 +19,8 @@ pub struct io_uring_sq {
     pub array: *mut libc::c_uint,
     pub io_uring_sqe: *mut io_uring_sqe,
 
-    pub sqe_head: *mut libc::c_uint,
-    pub sqe_tail: *mut libc::c_uint,
+    pub sqe_head: libc::c_uint,
+    pub sqe_tail: libc::c_uint,
 
     pub ring_sz: libc::size_t,
     pub ring_ptr: *mut libc::c_void,
@@ -145,7 +145,7 @@ pub const IORING_SETUP_R_DISABLED: libc::__u8 = 1 << 6;
 #[repr(C)]
 #[non_exhaustive]
 #[allow(nonstandard_style)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IoUringOp {
     IORING_OP_NOP,
     IORING_OP_READV,
@@ -187,6 +187,47 @@ pub enum IoUringOp {
     IORING_OP_LAST,
 }
 
+impl IoUringOp {
+    /// Every opcode known to these bindings, in opcode order
+    pub const ALL: [IoUringOp; IoUringOp::IORING_OP_LAST as usize] = [
+        IoUringOp::IORING_OP_NOP,
+        IoUringOp::IORING_OP_READV,
+        IoUringOp::IORING_OP_WRITEV,
+        IoUringOp::IORING_OP_FSYNC,
+        IoUringOp::IORING_OP_READ_FIXED,
+        IoUringOp::IORING_OP_WRITE_FIXED,
+        IoUringOp::IORING_OP_POLL_ADD,
+        IoUringOp::IORING_OP_POLL_REMOVE,
+        IoUringOp::IORING_OP_SYNC_FILE_RANGE,
+        IoUringOp::IORING_OP_SENDMSG,
+        IoUringOp::IORING_OP_RECVMSG,
+        IoUringOp::IORING_OP_TIMEOUT,
+        IoUringOp::IORING_OP_TIMEOUT_REMOVE,
+        IoUringOp::IORING_OP_ACCEPT,
+        IoUringOp::IORING_OP_ASYNC_CANCEL,
+        IoUringOp::IORING_OP_LINK_TIMEOUT,
+        IoUringOp::IORING_OP_CONNECT,
+        IoUringOp::IORING_OP_FALLOCATE,
+        IoUringOp::IORING_OP_OPENAT,
+        IoUringOp::IORING_OP_CLOSE,
+        IoUringOp::IORING_OP_FILES_UPDATE,
+        IoUringOp::IORING_OP_STATX,
+        IoUringOp::IORING_OP_READ,
+        IoUringOp::IORING_OP_WRITE,
+        IoUringOp::IORING_OP_FADVISE,
+        IoUringOp::IORING_OP_MADVISE,
+        IoUringOp::IORING_OP_SEND,
+        IoUringOp::IORING_OP_RECV,
+        IoUringOp::IORING_OP_OPENAT2,
+        IoUringOp::IORING_OP_EPOLL_CTL,
+        IoUringOp::IORING_OP_SPLICE,
+        IoUringOp::IORING_OP_PROVIDE_BUFFERS,
+        IoUringOp::IORING_OP_REMOVE_BUFFERS,
+        IoUringOp::IORING_OP_TEE,
+        IoUringOp::IORING_OP_SHUTDOWN,
+    ];
+}
+
 /// sqe->fsync_flags
 pub const IORING_FSYNC_DATASYNC: libc::__u32 = 1 << 0;
 
@@ -200,9 +241,11 @@ pub const SPLICE_F_FD_IN_FIXED: libc::__u32 = 1 << 31;
 #[repr(C)]
 #[derive(Debug)]
 pub struct io_uring_cqe {
-    user_data: libc::__u64,
-    res: libc::__s32,
-    flags: libc::__u32,
+    /// sqe->user_data submission passed back
+    pub user_data: libc::__u64,
+    /// result code for this event
+    pub res: libc::__s32,
+    pub flags: libc::__u32,
 }
 
 /// cqe->flags
@@ -362,6 +405,8 @@ extern "C" {
         params: *mut io_uring_params,
     ) -> libc::c_int;
 
+    pub fn io_uring_queue_exit(ring: *mut io_uring);
+
     pub fn io_uring_get_sqe(ring: *mut io_uring) -> *mut io_uring_sqe;
 
     pub fn io_uring_prep_readv(
@@ -373,6 +418,60 @@ extern "C" {
     );
 }
 
+/// Raw io_uring_register(2), returning `-errno` on failure like the liburing
+/// functions do.
+///
+/// # Safety
+///
+/// `fd` must be an io_uring file descriptor and `arg` has to point to what
+/// `opcode` expects, for `nr_args` entries.
+pub unsafe fn io_uring_register(
+    fd: libc::c_int,
+    opcode: libc::c_uint,
+    arg: *const libc::c_void,
+    nr_args: libc::c_uint,
+) -> libc::c_int {
+    let ret = libc::syscall(libc::SYS_io_uring_register, fd, opcode, arg, nr_args);
+
+    if ret < 0 {
+        -*libc::__errno_location()
+    } else {
+        ret as libc::c_int
+    }
+}
+
+/// Raw io_uring_enter(2), returning `-errno` on failure like the liburing
+/// functions do.
+///
+/// # Safety
+///
+/// `fd` must be an io_uring file descriptor and `sig` either null or a valid
+/// signal mask.
+pub unsafe fn io_uring_enter(
+    fd: libc::c_int,
+    to_submit: libc::c_uint,
+    min_complete: libc::c_uint,
+    flags: libc::c_uint,
+    sig: *const libc::sigset_t,
+) -> libc::c_int {
+    let ret = libc::syscall(
+        libc::SYS_io_uring_enter,
+        fd,
+        to_submit,
+        min_complete,
+        flags,
+        sig,
+        // The kernel's sigset_t, _NSIG / 8 bytes, not glibc's 128
+        8usize,
+    );
+
+    if ret < 0 {
+        -*libc::__errno_location()
+    } else {
+        ret as libc::c_int
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,105 @@
+//! Print files to stdout, reading them through registered buffers.
+//!
+//! Usage: cat <file>...
+//!
+//! A handful of buffers is registered with the ring once, and each file is read
+//! a batch of consecutive blocks at a time with `IORING_OP_READ_FIXED`.
+
+use std::{
+    env,
+    fs::File,
+    io::{self, Write},
+    os::unix::io::AsRawFd,
+    process,
+};
+
+use chakra::{IoRing, IoRingBuilder};
+
+const BUFFERS: usize = 4;
+const BUFFER_SIZE: usize = 64 * 1024;
+
+fn main() {
+    let paths: Vec<_> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: cat <file>...");
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&paths) {
+        eprintln!("cat: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(paths: &[String]) -> io::Result<()> {
+    let (mut ring, _) = IoRingBuilder::new().sq_entries(BUFFERS as u32).build()?;
+
+    let mut buffers = vec![vec![0u8; BUFFER_SIZE]; BUFFERS];
+    let iovecs: Vec<_> = buffers
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut _,
+            iov_len: buf.len(),
+        })
+        .collect();
+    // The buffers outlive the ring's use of them, they're unregistered below
+    unsafe { ring.register_buffers(&iovecs)? };
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    for path in paths {
+        let file = File::open(path)?;
+        cat(&mut ring, &file, &mut buffers, &mut stdout)?;
+    }
+
+    stdout.flush()?;
+    ring.unregister_buffers()?;
+
+    Ok(())
+}
+
+/// Copy `file` to `out`, reading a block into each buffer per batch
+fn cat(
+    ring: &mut IoRing,
+    file: &File,
+    buffers: &mut [Vec<u8>],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut offset = 0;
+
+    loop {
+        for (index, buf) in buffers.iter_mut().enumerate() {
+            let mut sqe = ring
+                .get_sqe()
+                .ok_or_else(|| io::Error::other("submission queue is full"))?;
+            let block_offset = offset + index * BUFFER_SIZE;
+
+            sqe.prep_read_fixed(file.as_raw_fd(), buf, block_offset, index as u16)?;
+            sqe.set_user_data(index as u64);
+        }
+
+        ring.submit_and_wait(buffers.len() as u32)?;
+
+        let mut lens = vec![0; buffers.len()];
+        for _ in 0..buffers.len() {
+            let cqe = ring.wait_cqe()?;
+            lens[cqe.user_data() as usize] = cqe.result()? as usize;
+        }
+
+        // Blocks are written out in order up to the first short one, which is
+        // either the end of the file or where the next batch picks up
+        for (buf, &len) in buffers.iter().zip(&lens) {
+            out.write_all(&buf[..len])?;
+            offset += len;
+
+            if len < BUFFER_SIZE {
+                break;
+            }
+        }
+
+        if lens[0] == 0 {
+            return Ok(());
+        }
+    }
+}
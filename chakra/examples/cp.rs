@@ -1,6 +1,268 @@
-use chakra::{self, Flags, IoRing};
+//! Copy a file with reads and writes submitted through an `IoRing`.
+//!
+//! Usage: cp [--depth N] [--block-size BYTES] <source> <destination>
+//!
+//! Up to `--depth` blocks are in flight at once. Each block is read and then
+//! written, with short reads and writes resubmitted for the remainder. If a
+//! block fails, the copy stops once the blocks in flight are done.
+
+use std::{
+    convert::TryFrom,
+    env,
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::{
+        fs::{OpenOptionsExt, PermissionsExt},
+        io::AsRawFd,
+    },
+    process,
+    time::Instant,
+};
+
+use chakra::{IoRing, IoRingBuilder};
+
+const DEFAULT_DEPTH: usize = 32;
+const DEFAULT_BLOCK_SIZE: usize = 128 * 1024;
+
+struct Args {
+    depth: usize,
+    block_size: usize,
+    source: String,
+    destination: String,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Reading,
+    Writing,
+}
+
+/// A block of the file being copied, `user_data` of its SQEs is its index
+struct Block {
+    buf: Vec<u8>,
+    state: State,
+    offset: u64,
+    len: usize,
+    /// Bytes of the block read or written so far
+    done: usize,
+}
 
 fn main() {
-    let (ring, params) =
-        IoRing::init_params(256, Flags::empty()).expect("Unable to instantiate ring");
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("usage: cp [--depth N] [--block-size BYTES] <source> <destination>");
+        process::exit(2);
+    });
+
+    if let Err(e) = run(&args) {
+        eprintln!("cp: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut depth = DEFAULT_DEPTH;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" | "--block-size" => {
+                let value = args
+                    .next()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .filter(|&v| v > 0)
+                    .ok_or_else(|| format!("{} takes a positive number", arg))?;
+
+                if arg == "--depth" {
+                    depth = value;
+                } else {
+                    block_size = value;
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err("expected a source and a destination".into());
+    }
+    let destination = paths.pop().unwrap();
+    let source = paths.pop().unwrap();
+
+    Ok(Args {
+        depth,
+        block_size,
+        source,
+        destination,
+    })
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let source = File::open(&args.source)?;
+    let metadata = source.metadata()?;
+    let mode = metadata.permissions().mode();
+
+    let destination = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&args.destination)?;
+
+    let depth = u32::try_from(args.depth)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--depth is too large"))?;
+    let (mut ring, _) = IoRingBuilder::new().sq_entries(depth).build()?;
+
+    let start = Instant::now();
+    let copied = copy(&mut ring, &source, &destination, metadata.len(), args)?;
+    let elapsed = start.elapsed();
+
+    // The mode passed to open is subject to the umask
+    fs::set_permissions(&args.destination, metadata.permissions())?;
+
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "copied {} bytes in {:.3}s ({:.1} MiB/s)",
+        copied,
+        secs,
+        copied as f64 / (1024.0 * 1024.0) / secs.max(f64::EPSILON)
+    );
+
+    Ok(())
+}
+
+fn copy(
+    ring: &mut IoRing,
+    source: &File,
+    destination: &File,
+    len: u64,
+    args: &Args,
+) -> io::Result<u64> {
+    let mut blocks: Vec<Block> = (0..args.depth)
+        .map(|_| Block {
+            buf: vec![0; args.block_size],
+            state: State::Idle,
+            offset: 0,
+            len: 0,
+            done: 0,
+        })
+        .collect();
+
+    let mut next_offset = 0;
+    let mut copied = 0;
+    let mut in_flight = 0;
+    // The first error, after which nothing new is queued. The blocks already
+    // in flight are still waited for, the kernel writes to their buffers.
+    let mut error = None;
+
+    loop {
+        // Keep every idle block busy reading the next part of the file
+        for (index, block) in blocks.iter_mut().enumerate() {
+            if block.state != State::Idle || next_offset >= len || error.is_some() {
+                continue;
+            }
+
+            block.state = State::Reading;
+            block.offset = next_offset;
+            block.len = (len - next_offset).min(args.block_size as u64) as usize;
+            block.done = 0;
+            next_offset += block.len as u64;
+
+            match queue(ring, source, destination, index, block) {
+                Ok(()) => in_flight += 1,
+                Err(e) => fail(&mut error, block, e),
+            }
+        }
+
+        if in_flight == 0 {
+            return match error {
+                Some(e) => Err(e),
+                None => Ok(copied),
+            };
+        }
+
+        ring.submit_and_wait(1)?;
+
+        while let Some(cqe) = ring.peek_cqe() {
+            let index = cqe.user_data() as usize;
+            let block = &mut blocks[index];
+            in_flight -= 1;
+
+            let res = match cqe.result() {
+                Ok(0) if block.state == State::Reading => Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "source file shrank while copying",
+                )),
+                Ok(0) => Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => Ok(n as usize),
+                Err(e) if e.raw_os_error() == Some(libc::EINTR) => Ok(0),
+                Err(e) => Err(e.into()),
+            };
+            let n = match res {
+                Ok(_) if error.is_some() => {
+                    block.state = State::Idle;
+                    continue;
+                }
+                Ok(n) => n,
+                Err(e) => {
+                    fail(&mut error, block, e);
+                    continue;
+                }
+            };
+            block.done += n;
+
+            if block.done == block.len {
+                match block.state {
+                    State::Reading => {
+                        block.state = State::Writing;
+                        block.done = 0;
+                    }
+                    _ => {
+                        block.state = State::Idle;
+                        copied += block.len as u64;
+                        continue;
+                    }
+                }
+            }
+
+            // Either the other half of the block or the rest of a short transfer
+            match queue(ring, source, destination, index, block) {
+                Ok(()) => in_flight += 1,
+                Err(e) => fail(&mut error, block, e),
+            }
+        }
+    }
+}
+
+/// Give up on `block`, keeping `e` unless an earlier error was already kept
+fn fail(error: &mut Option<io::Error>, block: &mut Block, e: io::Error) {
+    block.state = State::Idle;
+    error.get_or_insert(e);
+}
+
+/// Queue the remainder of the block's current read or write
+fn queue(
+    ring: &mut IoRing,
+    source: &File,
+    destination: &File,
+    index: usize,
+    block: &mut Block,
+) -> io::Result<()> {
+    let mut sqe = ring
+        .get_sqe()
+        .ok_or_else(|| io::Error::other("submission queue is full"))?;
+
+    let range = block.done..block.len;
+    let offset = (block.offset + block.done as u64) as usize;
+
+    match block.state {
+        State::Reading => sqe.prep_read(source.as_raw_fd(), &mut block.buf[range], offset)?,
+        _ => sqe.prep_write(destination.as_raw_fd(), &block.buf[range], offset)?,
+    }
+    sqe.set_user_data(index as u64);
+
+    Ok(())
 }
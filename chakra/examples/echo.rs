@@ -0,0 +1,248 @@
+//! A TCP echo server driven by a single ring.
+//!
+//! Usage: echo [address]
+//!
+//! One accept is kept armed at all times, re-armed as each connection comes in.
+//! Connections receive into buffers provided to the kernel up front, which is
+//! picked when data arrives instead of being tied up by idle connections. Each
+//! buffer is sent back and then provided again. A recv that finds no buffer
+//! left waits for the next one to be provided and is then retried.
+
+use std::{
+    collections::HashMap,
+    env, io, mem,
+    net::TcpListener,
+    os::unix::io::{AsRawFd, RawFd},
+    process,
+};
+
+use chakra::{IoRing, IoRingBuilder, Sqe};
+
+const BUFFER_GROUP: u16 = 0;
+const BUFFERS: u16 = 256;
+const BUFFER_SIZE: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Accept = 0,
+    Recv = 1,
+    Send = 2,
+    Provide = 3,
+}
+
+/// What a completion belongs to, packed into its `user_data`
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: Kind,
+    fd: RawFd,
+    bid: u16,
+}
+
+impl Token {
+    fn pack(self) -> u64 {
+        (self.kind as u64) | u64::from(self.bid) << 8 | (self.fd as u32 as u64) << 32
+    }
+
+    fn unpack(user_data: u64) -> Self {
+        let kind = match user_data & 0xff {
+            0 => Kind::Accept,
+            1 => Kind::Recv,
+            2 => Kind::Send,
+            _ => Kind::Provide,
+        };
+
+        Token {
+            kind,
+            fd: (user_data >> 32) as RawFd,
+            bid: (user_data >> 8) as u16,
+        }
+    }
+}
+
+struct Server {
+    ring: IoRing,
+    listener: TcpListener,
+    /// Backing memory of the provided buffers, buffer `bid` starts at
+    /// `bid * BUFFER_SIZE`
+    buffers: Vec<u8>,
+    /// Bytes of each buffer being sent back, and how many of them were sent
+    sends: HashMap<u16, (usize, usize)>,
+    /// Connections whose recv failed with `ENOBUFS`, to retry once a buffer is
+    /// provided again
+    starved: Vec<RawFd>,
+    accept_addr: Box<(libc::sockaddr_storage, libc::socklen_t)>,
+}
+
+fn main() {
+    let addr = env::args().nth(1).unwrap_or_else(|| "127.0.0.1:0".into());
+
+    if let Err(e) = run(&addr) {
+        eprintln!("echo: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("listening on {}", listener.local_addr()?);
+
+    let (ring, _) = IoRingBuilder::new().sq_entries(256).build()?;
+    let mut server = Server {
+        ring,
+        listener,
+        buffers: vec![0; BUFFERS as usize * BUFFER_SIZE as usize],
+        sends: HashMap::new(),
+        starved: Vec::new(),
+        accept_addr: Box::new((unsafe { mem::zeroed() }, 0)),
+    };
+
+    server.provide(0, BUFFERS)?;
+    server.accept()?;
+
+    loop {
+        server.ring.submit_and_wait(1)?;
+
+        while let Some(cqe) = server.ring.peek_cqe() {
+            let token = Token::unpack(cqe.user_data());
+
+            match (token.kind, cqe.result()) {
+                (Kind::Accept, Ok(fd)) => {
+                    server.recv(fd as RawFd)?;
+                    server.accept()?;
+                }
+                (Kind::Recv, Ok(0)) => close(token.fd),
+                (Kind::Recv, Ok(n)) => {
+                    let bid = cqe.buffer_id().expect("recv completed without a buffer");
+                    server.sends.insert(bid, (n as usize, 0));
+                    server.send(token.fd, bid)?;
+                }
+                (Kind::Send, Ok(n)) => {
+                    let (len, sent) = server.sends.get_mut(&token.bid).unwrap();
+                    *sent += n as usize;
+
+                    if *sent < *len {
+                        server.send(token.fd, token.bid)?;
+                    } else {
+                        server.sends.remove(&token.bid);
+                        server.provide(token.bid, 1)?;
+                        server.recv(token.fd)?;
+                    }
+                }
+                (Kind::Recv, Err(e)) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                    server.starved.push(token.fd);
+                }
+                (Kind::Provide, Ok(_)) => {
+                    for fd in mem::take(&mut server.starved) {
+                        server.recv(fd)?;
+                    }
+                }
+                (Kind::Accept, Err(e)) | (Kind::Provide, Err(e)) => return Err(e.into()),
+                (_, Err(e)) => {
+                    eprintln!("echo: connection {}: {}", token.fd, e);
+                    // A failed send still holds its buffer, hand it back
+                    if token.kind == Kind::Send {
+                        server.sends.remove(&token.bid);
+                        server.provide(token.bid, 1)?;
+                    }
+                    close(token.fd);
+                }
+            }
+        }
+    }
+}
+
+impl Server {
+    fn sqe(&mut self) -> io::Result<Sqe> {
+        if let Some(sqe) = self.ring.get_sqe() {
+            return Ok(sqe);
+        }
+
+        self.ring.submit()?;
+        self.ring
+            .get_sqe()
+            .ok_or_else(|| io::Error::other("submission queue is full"))
+    }
+
+    fn accept(&mut self) -> io::Result<()> {
+        let mut sqe = self.sqe()?;
+        let (addr, addrlen) = &mut *self.accept_addr;
+
+        sqe.prep_accept(&self.listener, addr, addrlen, libc::SOCK_CLOEXEC)?;
+        sqe.set_user_data(
+            Token {
+                kind: Kind::Accept,
+                fd: self.listener.as_raw_fd(),
+                bid: 0,
+            }
+            .pack(),
+        );
+
+        Ok(())
+    }
+
+    fn recv(&mut self, fd: RawFd) -> io::Result<()> {
+        let mut sqe = self.sqe()?;
+
+        sqe.prep_recv_provided(&fd, BUFFER_SIZE, BUFFER_GROUP, 0)?;
+        sqe.set_user_data(
+            Token {
+                kind: Kind::Recv,
+                fd,
+                bid: 0,
+            }
+            .pack(),
+        );
+
+        Ok(())
+    }
+
+    fn send(&mut self, fd: RawFd, bid: u16) -> io::Result<()> {
+        let (len, sent) = self.sends[&bid];
+        let start = bid as usize * BUFFER_SIZE as usize;
+        let mut sqe = self.sqe()?;
+
+        sqe.prep_send(
+            &fd,
+            &self.buffers[start + sent..start + len],
+            libc::MSG_NOSIGNAL,
+        )?;
+        sqe.set_user_data(
+            Token {
+                kind: Kind::Send,
+                fd,
+                bid,
+            }
+            .pack(),
+        );
+
+        Ok(())
+    }
+
+    /// Provide `count` buffers starting at `bid` to the kernel
+    fn provide(&mut self, bid: u16, count: u16) -> io::Result<()> {
+        let start = bid as usize * BUFFER_SIZE as usize;
+        let end = start + count as usize * BUFFER_SIZE as usize;
+        let mut sqe = self.sqe()?;
+
+        sqe.prep_provide_buffers(
+            &mut self.buffers[start..end],
+            BUFFER_SIZE,
+            BUFFER_GROUP,
+            bid,
+        )?;
+        sqe.set_user_data(
+            Token {
+                kind: Kind::Provide,
+                fd: -1,
+                bid,
+            }
+            .pack(),
+        );
+
+        Ok(())
+    }
+}
+
+fn close(fd: RawFd) {
+    unsafe { libc::close(fd) };
+}
@@ -0,0 +1,201 @@
+//! Copy a file with reads and writes submitted through `chakra::fs`, the
+//! counterpart of the `cp` example on top of the runtime.
+//!
+//! Usage: fs_copy [--depth N] [--block-size BYTES] <source> <destination>
+//!
+//! `--depth` tasks copy the file a block at a time, so that up to that many
+//! reads or writes are in flight at once. Short reads and writes are
+//! resubmitted for the remainder by `read_exact_at` and `write_all_at`. If a
+//! block fails, the copy stops once the blocks in flight are done.
+
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    env,
+    fs::{self, Permissions},
+    io,
+    os::unix::fs::PermissionsExt,
+    process,
+    rc::Rc,
+    time::Instant,
+};
+
+use chakra::{
+    fs::{File, OpenOptions},
+    runtime::{self, Runtime},
+    IoRingBuilder,
+};
+
+const DEFAULT_DEPTH: usize = 32;
+const DEFAULT_BLOCK_SIZE: usize = 128 * 1024;
+
+struct Args {
+    depth: usize,
+    block_size: usize,
+    source: String,
+    destination: String,
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("usage: fs_copy [--depth N] [--block-size BYTES] <source> <destination>");
+        process::exit(2);
+    });
+
+    if let Err(e) = run(&args) {
+        eprintln!("fs_copy: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut depth = DEFAULT_DEPTH;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut paths = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" | "--block-size" => {
+                let value = args
+                    .next()
+                    .and_then(|v| v.parse::<usize>().ok())
+                    .filter(|&v| v > 0)
+                    .ok_or_else(|| format!("{} takes a positive number", arg))?;
+
+                if arg == "--depth" {
+                    depth = value;
+                } else {
+                    block_size = value;
+                }
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    if paths.len() != 2 {
+        return Err("expected a source and a destination".into());
+    }
+    let destination = paths.pop().unwrap();
+    let source = paths.pop().unwrap();
+
+    Ok(Args {
+        depth,
+        block_size,
+        source,
+        destination,
+    })
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let depth = u32::try_from(args.depth)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--depth is too large"))?;
+    let runtime = Runtime::with_builder(IoRingBuilder::new().sq_entries(depth))?;
+
+    let start = Instant::now();
+    let (copied, mode) = runtime.block_on(copy(args))?;
+    let elapsed = start.elapsed();
+
+    // The mode passed to open is subject to the umask
+    fs::set_permissions(&args.destination, Permissions::from_mode(mode))?;
+
+    let secs = elapsed.as_secs_f64();
+    println!(
+        "copied {} bytes in {:.3}s ({:.1} MiB/s)",
+        copied,
+        secs,
+        copied as f64 / (1024.0 * 1024.0) / secs.max(f64::EPSILON)
+    );
+
+    Ok(())
+}
+
+/// Copy the source to the destination, returning the number of bytes copied
+/// and the mode of the source
+async fn copy(args: &Args) -> io::Result<(u64, u32)> {
+    let source = File::open(&args.source).await?;
+    let metadata = source.metadata().await?;
+
+    let destination = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(metadata.mode())
+        .open(&args.destination)
+        .await?;
+
+    let source = Rc::new(source);
+    let destination = Rc::new(destination);
+    let next = Rc::new(Cell::new(0));
+
+    let tasks: Vec<_> = (0..args.depth)
+        .map(|_| {
+            runtime::spawn_local(copy_blocks(
+                source.clone(),
+                destination.clone(),
+                next.clone(),
+                metadata.len(),
+                args.block_size,
+            ))
+        })
+        .collect();
+
+    // Wait for every task, even after one has failed, so that nothing is still
+    // in flight once the files are dropped
+    let mut copied = 0;
+    let mut error = None;
+    for task in tasks {
+        match task.await {
+            Ok(n) => copied += n,
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok((copied, metadata.mode())),
+    }
+}
+
+/// Copy the blocks starting at `next` until the end of the file, returning
+/// the number of bytes copied
+async fn copy_blocks(
+    source: Rc<File>,
+    destination: Rc<File>,
+    next: Rc<Cell<u64>>,
+    len: u64,
+    block_size: usize,
+) -> io::Result<u64> {
+    let mut copied = 0;
+    let mut buf = vec![0; block_size].into_boxed_slice();
+
+    while next.get() < len {
+        let offset = next.get();
+        let n = (len - offset).min(block_size as u64) as usize;
+        next.set(offset + n as u64);
+
+        // Only the last block is short
+        if n < buf.len() {
+            buf = vec![0; n].into_boxed_slice();
+        }
+
+        let (res, b) = source.read_exact_at(buf, offset).await;
+        let (res, b) = match res {
+            Ok(()) => destination.write_all_at(b, offset).await,
+            Err(e) => (Err(e), b),
+        };
+        if let Err(e) = res {
+            // The other tasks stop once their current block is done
+            next.set(len);
+            return Err(e);
+        }
+
+        buf = b;
+        copied += n as u64;
+    }
+
+    Ok(copied)
+}
@@ -0,0 +1,198 @@
+//! A static file server on the runtime.
+//!
+//! Usage: http [root] [address]
+//!
+//! Serves GET requests for files under `root`, one request per connection.
+//! File contents never pass through userspace: they're spliced from the file
+//! into a pipe and from the pipe into the socket.
+
+use std::{
+    env, io,
+    net::{Shutdown, SocketAddr},
+    os::unix::io::{FromRawFd, RawFd},
+    path::{Component, Path, PathBuf},
+    process,
+    rc::Rc,
+};
+
+use chakra::{
+    fs::File,
+    net::{TcpListener, TcpStream},
+    runtime::{self, Runtime},
+    Splice,
+};
+
+/// Most bytes of request headers read before giving up on a request
+const MAX_REQUEST: usize = 8 * 1024;
+/// Most bytes moved by a single splice
+const CHUNK: u32 = 64 * 1024;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let root = PathBuf::from(args.next().unwrap_or_else(|| ".".into()));
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:0".into());
+
+    let addr: SocketAddr = addr.parse().unwrap_or_else(|e| {
+        eprintln!("http: invalid address {}: {}", addr, e);
+        process::exit(2);
+    });
+
+    if let Err(e) = run(root, addr) {
+        eprintln!("http: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(root: PathBuf, addr: SocketAddr) -> io::Result<()> {
+    let runtime = Runtime::new()?;
+
+    runtime.block_on(async move {
+        let listener = TcpListener::bind(addr)?;
+        println!("listening on {}", listener.local_addr()?);
+
+        let root = Rc::new(root);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let root = root.clone();
+
+            runtime::spawn_local(async move {
+                if let Err(e) = serve(&stream, &root).await {
+                    eprintln!("http: {}", e);
+                }
+                let _ = stream.shutdown(Shutdown::Write).await;
+            });
+        }
+    })
+}
+
+async fn serve(stream: &TcpStream, root: &Path) -> io::Result<()> {
+    let request = match read_request(stream).await? {
+        Some(request) => request,
+        None => return respond(stream, "400 Bad Request").await,
+    };
+
+    let mut parts = request.split(' ');
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return respond(stream, "400 Bad Request").await,
+    };
+    if method != "GET" {
+        return respond(stream, "405 Method Not Allowed").await;
+    }
+
+    let path = match resolve(root, target) {
+        Some(path) => path,
+        None => return respond(stream, "404 Not Found").await,
+    };
+
+    let file = match File::open(&path).await {
+        Ok(file) => file,
+        Err(_) => return respond(stream, "404 Not Found").await,
+    };
+    let metadata = file.metadata().await?;
+    if !metadata.is_file() {
+        return respond(stream, "404 Not Found").await;
+    }
+
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        metadata.len()
+    );
+    let (res, _) = stream.write_all(header.into_bytes()).await;
+    res?;
+
+    send_file(stream, &file, metadata.len()).await?;
+    file.close().await
+}
+
+/// Read up to the end of the request headers, returning the request line
+async fn read_request(stream: &TcpStream) -> io::Result<Option<String>> {
+    let mut request = Vec::new();
+
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST {
+            return Ok(None);
+        }
+
+        let (res, buf) = stream.read(Vec::with_capacity(1024)).await;
+        if res? == 0 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buf);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    Ok(request.lines().next().map(str::to_owned))
+}
+
+/// Map a request target to a path under `root`, refusing anything that would
+/// step outside of it
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let target = target.split('?').next()?.trim_start_matches('/');
+    let mut path = root.to_path_buf();
+
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    Some(path)
+}
+
+async fn respond(stream: &TcpStream, status: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    stream.write_all(response.into_bytes()).await.0
+}
+
+/// Splice `len` bytes of `file` into `stream` through a pipe
+async fn send_file(stream: &TcpStream, file: &File, len: u64) -> io::Result<()> {
+    let (pipe_r, pipe_w) = pipe()?;
+    let mut offset = 0;
+
+    while offset < len {
+        let chunk = (len - offset).min(u64::from(CHUNK)) as u32;
+        let mut in_pipe = splice(Splice::new(file, &pipe_w, chunk).off_in(offset)).await?;
+        if in_pipe == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        offset += u64::from(in_pipe);
+
+        while in_pipe > 0 {
+            let n = splice(Splice::new(&pipe_r, stream, in_pipe)).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            in_pipe -= n;
+        }
+    }
+
+    Ok(())
+}
+
+async fn splice(splice: Splice) -> io::Result<u32> {
+    runtime::driver()
+        .submit_op((), move |sqe, _| sqe.prep_splice(splice))
+        .await
+        .0
+}
+
+fn pipe() -> io::Result<(std::fs::File, std::fs::File)> {
+    let mut fds: [RawFd; 2] = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let (r, w) = unsafe {
+        (
+            std::fs::File::from_raw_fd(fds[0]),
+            std::fs::File::from_raw_fd(fds[1]),
+        )
+    };
+    Ok((r, w))
+}
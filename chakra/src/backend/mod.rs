@@ -0,0 +1,119 @@
+//! What an `IoRing` runs its operations on.
+//!
+//! An `IoRing` only ever touches the shared rings directly. Setting them up,
+//! entering to get SQEs consumed or wait for completions, and registering
+//! resources all go through a `Backend`. `Kernel` is the real thing, the
+//! default for `IoRingBuilder::build`, while `Sim` executes the operations in
+//! process and works where io_uring is unavailable, see `IoRingBuilder::build_with`.
+//! `Replay` plays back a recording made with `IoRing::record`.
+
+use std::{mem::MaybeUninit, os::unix::io::RawFd, ptr};
+
+use crate::{error::Error, Result};
+
+mod replay;
+mod rings;
+mod sim;
+
+pub use replay::Replay;
+pub use sim::{Execution, Order, Sim};
+
+/// The other side of the submission and completion queues of an `IoRing`.
+///
+/// # Safety
+///
+/// The rings returned by `setup` are read and written by the `IoRing` without
+/// any further checks, so every pointer in them has to be valid, and the
+/// indices laid out as the kernel does, until `exit` is called.
+pub unsafe trait Backend: Send + Sync + 'static {
+    /// Set up rings of `entries` SQEs as described by `params`, filling in
+    /// the parameters actually used like io_uring_setup(2) does
+    fn setup(
+        &mut self,
+        entries: u32,
+        params: &mut chakra_sys::io_uring_params,
+    ) -> Result<chakra_sys::io_uring>;
+
+    /// Consume up to `to_submit` published SQEs and, with
+    /// `IORING_ENTER_GETEVENTS`, wait for `min_complete` completions, as
+    /// io_uring_enter(2) does. Returns the number of SQEs consumed.
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> Result<usize>;
+
+    /// Perform the io_uring_register(2) `opcode` on `nr_args` entries of `arg`
+    ///
+    /// # Safety
+    ///
+    /// `arg` has to point to what `opcode` expects.
+    unsafe fn register(&self, opcode: u32, arg: *const libc::c_void, nr_args: u32) -> Result<u32>;
+
+    /// Tear down the rings returned by `setup`
+    ///
+    /// # Safety
+    ///
+    /// `ring` has to be the one returned by `setup`, and is unusable afterwards.
+    unsafe fn exit(&mut self, ring: &mut chakra_sys::io_uring);
+}
+
+/// The kernel's io_uring, set up through liburing.
+#[derive(Debug)]
+pub struct Kernel {
+    fd: RawFd,
+}
+
+impl Kernel {
+    pub fn new() -> Self {
+        Kernel { fd: -1 }
+    }
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Backend for Kernel {
+    fn setup(
+        &mut self,
+        entries: u32,
+        params: &mut chakra_sys::io_uring_params,
+    ) -> Result<chakra_sys::io_uring> {
+        let mut ring = MaybeUninit::uninit();
+
+        let res =
+            unsafe { chakra_sys::io_uring_queue_init_params(entries, ring.as_mut_ptr(), params) };
+        if res < 0 {
+            return Err(Error::from_setup_errno(res, entries, params));
+        }
+
+        let ring = unsafe { ring.assume_init() };
+        self.fd = ring.ring_fd;
+
+        Ok(ring)
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> Result<usize> {
+        let res = unsafe {
+            chakra_sys::io_uring_enter(self.fd, to_submit, min_complete, flags, ptr::null())
+        };
+        if res < 0 {
+            return Err(Error::from_errno(res));
+        }
+
+        Ok(res as usize)
+    }
+
+    unsafe fn register(&self, opcode: u32, arg: *const libc::c_void, nr_args: u32) -> Result<u32> {
+        let res = chakra_sys::io_uring_register(self.fd, opcode, arg, nr_args);
+        if res < 0 {
+            return Err(Error::from_errno(res));
+        }
+
+        Ok(res as u32)
+    }
+
+    unsafe fn exit(&mut self, ring: &mut chakra_sys::io_uring) {
+        chakra_sys::io_uring_queue_exit(ring);
+        self.fd = -1;
+    }
+}
@@ -0,0 +1,271 @@
+//! Feeding a recording back to a ring, see `Replay`.
+
+use std::{
+    cmp,
+    collections::VecDeque,
+    io::{self, Read},
+    os::unix::io::RawFd,
+    sync::{atomic::Ordering, Arc, Mutex, MutexGuard, OnceLock},
+};
+
+use crate::{
+    backend::{
+        rings::{Rings, SQ_ARRAY, SQ_DROPPED, SQ_FLAGS, SQ_HEAD, SQ_TAIL},
+        sim::signal,
+        Backend,
+    },
+    error::Error,
+    record::{CqeRecord, Event, Header, Recording, SqeRecord},
+    Result,
+};
+
+/// A backend that plays a recording made with `IoRing::record` back.
+///
+/// Nothing is executed. Each SQE the ring submits is checked against the next
+/// submission in the recording, and the completions recorded after it are
+/// posted in the order they were reaped, with their recorded results. A run
+/// that submits the same operations thus sees the same completions in the
+/// same order as the recorded one:
+///
+/// ```no_run
+/// use chakra::{backend::Replay, record::Recording, IoRingBuilder};
+///
+/// let replay = Replay::new(Recording::open("ring.rec")?)?;
+/// let (mut ring, _) = IoRingBuilder::new().build_with(replay.clone())?;
+///
+/// // drive the ring as the recorded run did
+///
+/// assert_eq!(replay.remaining(), 0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// Submissions are compared by opcode, flags, offset, length and
+/// `user_data`. File descriptors and buffer addresses may differ between runs,
+/// and the data the recorded operations read isn't part of the recording, so
+/// buffers are left as they are. A submission that doesn't match, or waiting
+/// for completions that the recording doesn't have before its next
+/// submission, fails with `io::ErrorKind::InvalidData`.
+///
+/// The ring gets the features of the recorded one, SQPOLL is ignored like on
+/// a `Sim`. A `Replay` can back a single ring.
+#[derive(Clone)]
+pub struct Replay {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    header: Header,
+    rings: OnceLock<Rings>,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// The events not replayed yet
+    events: VecDeque<Event>,
+    /// Completions that didn't fit in the completion queue
+    overflow: VecDeque<CqeRecord>,
+    /// Our own duplicate of the registered eventfd
+    eventfd: Option<RawFd>,
+}
+
+impl Replay {
+    /// Read in all of `recording` to play it back
+    pub fn new<R: Read>(recording: Recording<R>) -> io::Result<Self> {
+        let header = *recording.header();
+        let events = recording
+            .map(|record| record.map(|record| record.event))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Replay {
+            shared: Arc::new(Shared {
+                header,
+                rings: OnceLock::new(),
+                state: Mutex::new(State {
+                    events,
+                    overflow: VecDeque::new(),
+                    eventfd: None,
+                }),
+            }),
+        })
+    }
+
+    /// Number of events in the recording that haven't been played back yet,
+    /// including completions that were posted but not reaped
+    pub fn remaining(&self) -> usize {
+        let state = self.shared.state();
+        let posted = self.shared.rings.get().map_or(0, Rings::cq_ready);
+
+        state.events.len() + state.overflow.len() + posted as usize
+    }
+}
+
+unsafe impl Backend for Replay {
+    fn setup(
+        &mut self,
+        entries: u32,
+        params: &mut chakra_sys::io_uring_params,
+    ) -> Result<chakra_sys::io_uring> {
+        let rings = Rings::setup(entries, params)?;
+        if self.shared.rings.set(rings).is_err() {
+            return Err(Error::from_errno(-libc::EBUSY));
+        }
+
+        params.flags &= !u32::from(
+            chakra_sys::IORING_SETUP_SQPOLL
+                | chakra_sys::IORING_SETUP_SQ_AFF
+                | chakra_sys::IORING_SETUP_ATTACH_WQ
+                | chakra_sys::IORING_SETUP_R_DISABLED,
+        );
+        params.features = self.shared.header.features;
+
+        Ok(self.shared.rings().io_uring(params.flags))
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> Result<usize> {
+        let rings = self.shared.rings();
+        let (sq, entries, mask) = (&rings.sq, rings.sq_entries(), rings.sq_mask());
+        let mut state = self.shared.state();
+
+        let head = sq[SQ_HEAD].load(Ordering::Relaxed);
+        let tail = sq[SQ_TAIL].load(Ordering::Acquire);
+        let n = cmp::min(to_submit, tail.wrapping_sub(head));
+
+        let mut consumed = 0;
+        let mut res = Ok(());
+        while consumed < n {
+            self.shared.post(&mut state);
+
+            let index = sq[SQ_ARRAY + (head.wrapping_add(consumed) & mask) as usize]
+                .load(Ordering::Relaxed);
+            if index >= entries {
+                sq[SQ_DROPPED].fetch_add(1, Ordering::Relaxed);
+                consumed += 1;
+                continue;
+            }
+
+            let sqe = SqeRecord::from_raw(unsafe { &*rings.sqes[index as usize].get() });
+            match state.events.front() {
+                Some(Event::Submit(recorded)) if same_op(recorded, &sqe) => {
+                    state.events.pop_front();
+                    consumed += 1;
+                }
+                recorded => {
+                    res = Err(diverged(format!(
+                        "submitted {:?}, the recording has {:?}",
+                        sqe, recorded
+                    )));
+                    break;
+                }
+            }
+        }
+        // Hands the consumed slots back, the SQEs have been checked
+        sq[SQ_HEAD].store(head.wrapping_add(consumed), Ordering::Release);
+        self.shared.post(&mut state);
+        res?;
+
+        if flags & chakra_sys::IORING_ENTER_GETEVENTS != 0 && rings.cq_ready() < min_complete {
+            return Err(diverged(format!(
+                "waiting for {} completions, the recording has {} before its next submission",
+                min_complete,
+                rings.cq_ready()
+            )));
+        }
+
+        Ok(consumed as usize)
+    }
+
+    unsafe fn register(&self, opcode: u32, arg: *const libc::c_void, nr_args: u32) -> Result<u32> {
+        let mut state = self.shared.state();
+        let errno = |errno: i32| Err(Error::from_errno(-errno));
+
+        // Nothing is executed, so registered files and buffers aren't needed
+        match opcode {
+            chakra_sys::IORING_REGISTER_EVENTFD | chakra_sys::IORING_REGISTER_EVENTFD_ASYNC => {
+                if state.eventfd.is_some() {
+                    return errno(libc::EBUSY);
+                }
+                if nr_args != 1 {
+                    return errno(libc::EINVAL);
+                }
+                let fd = libc::fcntl(*(arg as *const RawFd), libc::F_DUPFD_CLOEXEC, 0);
+                if fd == -1 {
+                    return errno(*libc::__errno_location());
+                }
+                state.eventfd = Some(fd);
+            }
+            chakra_sys::IORING_UNREGISTER_EVENTFD => match state.eventfd.take() {
+                Some(fd) => {
+                    libc::close(fd);
+                }
+                None => return errno(libc::ENXIO),
+            },
+            // Which opcodes the recorded kernel supported isn't known
+            chakra_sys::IORING_REGISTER_PROBE => return errno(libc::EINVAL),
+            _ => {}
+        }
+
+        Ok(0)
+    }
+
+    unsafe fn exit(&mut self, _ring: &mut chakra_sys::io_uring) {
+        if let Some(fd) = self.shared.state().eventfd.take() {
+            libc::close(fd);
+        }
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn rings(&self) -> &Rings {
+        self.rings.get().expect("ring not set up")
+    }
+
+    /// Post the completions recorded before the next submission, holding on
+    /// to those that don't fit in the completion queue
+    fn post(&self, state: &mut State) {
+        let rings = self.rings();
+
+        while let Some(&Event::Complete(cqe)) = state.events.front() {
+            state.events.pop_front();
+            state.overflow.push_back(cqe);
+        }
+
+        let mut posted = false;
+        while let Some(&cqe) = state.overflow.front() {
+            if !rings.push_cqe(cqe.user_data, cqe.res, cqe.flags) {
+                break;
+            }
+            state.overflow.pop_front();
+            posted = true;
+        }
+
+        if state.overflow.is_empty() {
+            rings.sq[SQ_FLAGS].fetch_and(!chakra_sys::IORING_SQ_CQ_OVERFLOW, Ordering::Relaxed);
+        } else {
+            rings.sq[SQ_FLAGS].fetch_or(chakra_sys::IORING_SQ_CQ_OVERFLOW, Ordering::Relaxed);
+        }
+
+        if let (true, Some(fd)) = (posted, state.eventfd) {
+            signal(fd);
+        }
+    }
+}
+
+/// Whether a submission is the same operation as the recorded one
+fn same_op(recorded: &SqeRecord, sqe: &SqeRecord) -> bool {
+    recorded.opcode == sqe.opcode
+        && recorded.flags == sqe.flags
+        && recorded.off == sqe.off
+        && recorded.len == sqe.len
+        && recorded.user_data == sqe.user_data
+}
+
+fn diverged(msg: String) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("replay diverged: {}", msg),
+    ))
+}
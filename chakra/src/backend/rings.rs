@@ -0,0 +1,207 @@
+//! Ring memory for the backends that run in process, in place of the kernel's
+//! mappings.
+
+use std::{
+    cell::UnsafeCell,
+    cmp, mem, ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use chakra_sys::{io_uring_cqe, io_uring_sqe};
+
+use crate::{
+    error::{Error, IORING_MAX_CQ_ENTRIES, IORING_MAX_ENTRIES},
+    Result,
+};
+
+// Layout of the SQ ring, in u32s
+pub(super) const SQ_HEAD: usize = 0;
+pub(super) const SQ_TAIL: usize = 1;
+const SQ_MASK: usize = 2;
+const SQ_ENTRIES: usize = 3;
+pub(super) const SQ_FLAGS: usize = 4;
+pub(super) const SQ_DROPPED: usize = 5;
+pub(super) const SQ_ARRAY: usize = 6;
+
+// Layout of the CQ ring, in u32s
+const CQ_HEAD: usize = 0;
+const CQ_TAIL: usize = 1;
+const CQ_MASK: usize = 2;
+const CQ_ENTRIES: usize = 3;
+const CQ_OVERFLOW: usize = 4;
+const CQ_FLAGS: usize = 5;
+const CQ_LEN: usize = 6;
+
+/// The memory shared with the `IoRing`
+pub(super) struct Rings {
+    pub(super) sq: Box<[AtomicU32]>,
+    cq: Box<[AtomicU32]>,
+    pub(super) sqes: Box<[UnsafeCell<io_uring_sqe>]>,
+    cqes: Box<[UnsafeCell<io_uring_cqe>]>,
+}
+
+// SQEs are only read once the `IoRing` has published them with the SQ tail,
+// and CQEs only written under the backend's lock before publishing the CQ tail.
+unsafe impl Sync for Rings {}
+
+impl Rings {
+    /// Allocate rings for `entries` SQEs, sized as io_uring_setup(2) would
+    /// for `params`, and fill in the sizes and offsets
+    pub(super) fn setup(entries: u32, params: &mut chakra_sys::io_uring_params) -> Result<Self> {
+        let clamp = params.flags & u32::from(chakra_sys::IORING_SETUP_CLAMP) != 0;
+        let invalid = |params: &chakra_sys::io_uring_params| {
+            Error::from_setup_errno(-libc::EINVAL, entries, params)
+        };
+
+        if entries == 0 || (entries > IORING_MAX_ENTRIES && !clamp) {
+            return Err(invalid(params));
+        }
+        let sq_entries = cmp::min(entries, IORING_MAX_ENTRIES).next_power_of_two();
+
+        let cq_entries = if params.flags & u32::from(chakra_sys::IORING_SETUP_CQSIZE) != 0 {
+            if params.cq_entries < sq_entries
+                || (params.cq_entries > IORING_MAX_CQ_ENTRIES && !clamp)
+            {
+                return Err(invalid(params));
+            }
+            cmp::min(params.cq_entries, IORING_MAX_CQ_ENTRIES).next_power_of_two()
+        } else {
+            2 * sq_entries
+        };
+
+        params.sq_entries = sq_entries;
+        params.cq_entries = cq_entries;
+        params.sq_off = chakra_sys::io_sqring_offsets {
+            head: offset(SQ_HEAD),
+            tail: offset(SQ_TAIL),
+            ring_mask: offset(SQ_MASK),
+            ring_entries: offset(SQ_ENTRIES),
+            flags: offset(SQ_FLAGS),
+            dropped: offset(SQ_DROPPED),
+            array: offset(SQ_ARRAY),
+            resv1: 0,
+            resv2: 0,
+        };
+        params.cq_off = chakra_sys::io_cqring_offsets {
+            head: offset(CQ_HEAD),
+            tail: offset(CQ_TAIL),
+            ring_mask: offset(CQ_MASK),
+            ring_entries: offset(CQ_ENTRIES),
+            overflow: offset(CQ_OVERFLOW),
+            cqes: 0,
+            flags: offset(CQ_FLAGS),
+            resv1: 0,
+            resv2: 0,
+        };
+
+        Ok(Rings::new(sq_entries, cq_entries))
+    }
+
+    fn new(sq_entries: u32, cq_entries: u32) -> Self {
+        let sq: Box<[AtomicU32]> = (0..SQ_ARRAY + sq_entries as usize)
+            .map(|_| AtomicU32::new(0))
+            .collect();
+        sq[SQ_MASK].store(sq_entries - 1, Ordering::Relaxed);
+        sq[SQ_ENTRIES].store(sq_entries, Ordering::Relaxed);
+
+        let cq: Box<[AtomicU32]> = (0..CQ_LEN).map(|_| AtomicU32::new(0)).collect();
+        cq[CQ_MASK].store(cq_entries - 1, Ordering::Relaxed);
+        cq[CQ_ENTRIES].store(cq_entries, Ordering::Relaxed);
+
+        Rings {
+            sq,
+            cq,
+            sqes: (0..sq_entries)
+                .map(|_| UnsafeCell::new(unsafe { mem::zeroed() }))
+                .collect(),
+            cqes: (0..cq_entries)
+                .map(|_| UnsafeCell::new(unsafe { mem::zeroed() }))
+                .collect(),
+        }
+    }
+
+    /// The rings as the `IoRing` sees them, for a ring set up with `flags`
+    pub(super) fn io_uring(&self, flags: u32) -> chakra_sys::io_uring {
+        let (sq, cq) = (&self.sq, &self.cq);
+
+        chakra_sys::io_uring {
+            io_uring_sq: chakra_sys::io_uring_sq {
+                khead: sq[SQ_HEAD].as_ptr(),
+                ktail: sq[SQ_TAIL].as_ptr(),
+                kring_mask: sq[SQ_MASK].as_ptr(),
+                kring_entries: sq[SQ_ENTRIES].as_ptr(),
+                kflags: sq[SQ_FLAGS].as_ptr(),
+                kdropped: sq[SQ_DROPPED].as_ptr(),
+                array: sq[SQ_ARRAY].as_ptr(),
+                io_uring_sqe: self.sqes.as_ptr() as *mut io_uring_sqe,
+                sqe_head: 0,
+                sqe_tail: 0,
+                ring_sz: 0,
+                ring_ptr: ptr::null_mut(),
+                pad: [0; 4],
+            },
+            io_uring_cq: chakra_sys::io_uring_cq {
+                khead: cq[CQ_HEAD].as_ptr(),
+                ktail: cq[CQ_TAIL].as_ptr(),
+                kring_mask: cq[CQ_MASK].as_ptr(),
+                kring_entries: cq[CQ_ENTRIES].as_ptr(),
+                kflags: cq[CQ_FLAGS].as_ptr(),
+                koverflow: cq[CQ_OVERFLOW].as_ptr(),
+                io_uring_cqe: self.cqes.as_ptr() as *mut io_uring_cqe,
+                ring_sz: 0,
+                ring_ptr: ptr::null_mut(),
+                pad: [0; 4],
+            },
+            flags,
+            ring_fd: -1,
+            pad: [0; 4],
+        }
+    }
+
+    pub(super) fn sq_entries(&self) -> u32 {
+        self.sq[SQ_ENTRIES].load(Ordering::Relaxed)
+    }
+
+    pub(super) fn sq_mask(&self) -> u32 {
+        self.sq[SQ_MASK].load(Ordering::Relaxed)
+    }
+
+    pub(super) fn cq_ready(&self) -> u32 {
+        let tail = self.cq[CQ_TAIL].load(Ordering::Relaxed);
+        tail.wrapping_sub(self.cq[CQ_HEAD].load(Ordering::Acquire))
+    }
+
+    /// Write a CQE and publish it, unless the completion queue is full. Only
+    /// called under the backend's lock.
+    pub(super) fn push_cqe(&self, user_data: u64, res: i32, flags: u32) -> bool {
+        let entries = self.cq[CQ_ENTRIES].load(Ordering::Relaxed);
+        let tail = self.cq[CQ_TAIL].load(Ordering::Relaxed);
+
+        // The slots before the head have been copied out by the `IoRing`
+        if tail.wrapping_sub(self.cq[CQ_HEAD].load(Ordering::Acquire)) >= entries {
+            return false;
+        }
+
+        let index = (tail & self.cq[CQ_MASK].load(Ordering::Relaxed)) as usize;
+        unsafe {
+            *self.cqes[index].get() = io_uring_cqe {
+                user_data,
+                res,
+                flags,
+            };
+        }
+        self.cq[CQ_TAIL].store(tail.wrapping_add(1), Ordering::Release);
+
+        true
+    }
+
+    /// Count a CQE that was thrown away because the completion queue was full
+    pub(super) fn drop_cqe(&self) {
+        self.cq[CQ_OVERFLOW].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Byte offset of the u32 at `index` in a ring
+fn offset(index: usize) -> u32 {
+    (index * mem::size_of::<u32>()) as u32
+}
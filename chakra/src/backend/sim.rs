@@ -0,0 +1,1278 @@
+//! An in-process stand-in for the kernel's io_uring, see `Sim`.
+//!
+//! The rings live in ordinary memory shared with the `IoRing`. Entering
+//! consumes the published SQEs and runs each of them, or each chain of linked
+//! ones, as the blocking syscall it corresponds to, either on a pool of worker
+//! threads or right away on the submitting thread. Finished operations are
+//! posted to the completion queue in the `Order` the `Sim` is set up with.
+//!
+//! Operations on sockets and pipes wait for readiness with poll(2) before
+//! making the syscall, so that they can still be cancelled while they wait.
+
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::TryFrom,
+    mem,
+    os::unix::io::RawFd,
+    ptr,
+    sync::{atomic::Ordering as AtomicOrdering, Arc, Condvar, Mutex, MutexGuard, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use chakra_sys::{io_uring_probe, io_uring_probe_op, io_uring_sqe, IoUringOp};
+
+use crate::{
+    backend::{
+        rings::{Rings, SQ_ARRAY, SQ_DROPPED, SQ_FLAGS, SQ_HEAD, SQ_TAIL},
+        Backend,
+    },
+    error::Error,
+    Result,
+};
+
+/// How long a worker thread waits for a new operation before it exits
+const WORKER_IDLE: Duration = Duration::from_secs(1);
+
+/// Where a `Sim` runs the operations submitted to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Execution {
+    /// On a pool of worker threads, which grows to run every operation in
+    /// flight at once
+    Threads,
+    /// On the submitting thread, before `io_uring_enter` returns. This makes
+    /// runs repeatable, but an operation that blocks, like a recv nothing is
+    /// ever sent to, blocks the submitter along with it.
+    Inline,
+}
+
+/// The order in which a `Sim` posts the completions of finished operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// As soon as each operation finishes
+    Completion,
+    /// In the order the operations were submitted, holding each completion
+    /// back until every operation submitted before it has finished
+    Submission,
+    /// Once every operation consumed by an `io_uring_enter` has finished,
+    /// all of them in the reverse of the order they were submitted in
+    Reverse,
+    /// Like `Reverse`, but in an order shuffled by a generator seeded with the
+    /// given value
+    Shuffle(u64),
+    /// Only once released with `Sim::release` or `Sim::release_all`
+    Manual,
+}
+
+/// A simulated io_uring, executing the submitted operations in process.
+///
+/// Rings set up on a `Sim` behave like the kernel's, without needing io_uring
+/// to be available, and with control over when completions show up. Clones
+/// refer to the same simulation, so a clone kept around after handing one to
+/// `IoRingBuilder::build_with` can release completions held back by
+/// `Order::Manual`:
+///
+/// ```no_run
+/// use chakra::{backend::{Order, Sim}, IoRingBuilder};
+///
+/// let sim = Sim::new().order(Order::Manual);
+/// let (mut ring, _) = IoRingBuilder::new().build_with(sim.clone())?;
+///
+/// ring.get_sqe().unwrap().prep_nop().unwrap();
+/// ring.submit()?;
+/// assert!(ring.peek_cqe().is_none());
+///
+/// sim.release_all();
+/// assert!(ring.peek_cqe().is_some());
+/// # Ok::<(), chakra::Error>(())
+/// ```
+///
+/// Unless set up otherwise with `Sim::nodrop`, rings advertise
+/// `IORING_FEAT_NODROP` and completions that don't fit in the completion queue
+/// are held on to until there is room.
+///
+/// SQPOLL and `IORING_SETUP_ATTACH_WQ` are accepted but have no effect, the
+/// ring behaves as if they weren't set. Rings set up disabled can't be entered
+/// until they're enabled, and the restrictions registered before that are
+/// enforced.
+/// `IOSQE_IO_DRAIN` and `IOSQE_ASYNC` are ignored, and links are honoured.
+/// A `Sim` can back a single ring.
+#[derive(Clone)]
+pub struct Sim {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    rings: OnceLock<Rings>,
+    state: Mutex<State>,
+    /// Signalled whenever a completion is posted
+    posted: Condvar,
+    /// Signalled whenever a chain is queued for the workers
+    work: Condvar,
+}
+
+struct State {
+    order: Order,
+    execution: Execution,
+    /// Whether completions that don't fit in the completion queue are kept
+    nodrop: bool,
+    /// splitmix64 state for `Order::Shuffle`
+    rng: u64,
+    next_seq: u64,
+    next_batch: u64,
+    /// Operations whose completion hasn't been posted yet, by sequence number
+    ops: BTreeMap<u64, Entry>,
+    /// Chains waiting for a worker
+    queue: VecDeque<Vec<Op>>,
+    /// Workers waiting for a chain
+    idle: usize,
+    /// Completions that didn't fit in the completion queue
+    overflow: VecDeque<(u64, i32, u32)>,
+    files: Vec<RawFd>,
+    /// Number of registered buffers, which fixed reads and writes address
+    /// directly
+    buffers: u32,
+    /// A duplicate of the registered eventfd
+    eventfd: Option<RawFd>,
+    /// Provided buffers by group, as address, length and id
+    groups: HashMap<u16, Vec<(u64, u32, u16)>>,
+    /// Set up with `IORING_SETUP_R_DISABLED` and not enabled yet
+    disabled: bool,
+    restrictions: Option<Restrictions>,
+    exiting: bool,
+}
+
+/// Registered with `IORING_REGISTER_RESTRICTIONS`, in force once the ring is
+/// enabled
+#[derive(Default)]
+struct Restrictions {
+    register_ops: Vec<u32>,
+    sqe_ops: Vec<u8>,
+    sqe_flags_allowed: u8,
+    sqe_flags_required: u8,
+}
+
+struct Entry {
+    /// The `io_uring_enter` that consumed the SQE
+    batch: u64,
+    user_data: u64,
+    status: Status,
+    released: bool,
+}
+
+enum Status {
+    /// Waiting for a worker, or for the operations linked before it
+    Queued,
+    Running,
+    /// Waiting for its file to become ready, a write to the eventfd wakes the
+    /// worker up to cancel it
+    Polling(RawFd),
+    /// Cancelled before or while polling, the worker is yet to notice
+    Cancelled,
+    Done {
+        res: i32,
+        flags: u32,
+    },
+}
+
+/// A consumed SQE
+struct Op {
+    seq: u64,
+    sqe: io_uring_sqe,
+    /// Denied by the ring's restrictions, it fails with `EACCES`
+    denied: bool,
+}
+
+impl Sim {
+    /// A simulation running operations on worker threads and posting their
+    /// completions as soon as they finish
+    pub fn new() -> Self {
+        Sim {
+            shared: Arc::new(Shared {
+                rings: OnceLock::new(),
+                state: Mutex::new(State {
+                    order: Order::Completion,
+                    execution: Execution::Threads,
+                    nodrop: true,
+                    rng: 0,
+                    next_seq: 0,
+                    next_batch: 0,
+                    ops: BTreeMap::new(),
+                    queue: VecDeque::new(),
+                    idle: 0,
+                    overflow: VecDeque::new(),
+                    files: Vec::new(),
+                    buffers: 0,
+                    eventfd: None,
+                    groups: HashMap::new(),
+                    disabled: false,
+                    restrictions: None,
+                    exiting: false,
+                }),
+                posted: Condvar::new(),
+                work: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Post completions in `order`
+    pub fn order(self, order: Order) -> Self {
+        {
+            let mut state = self.shared.state();
+            state.order = order;
+            if let Order::Shuffle(seed) = order {
+                state.rng = seed;
+            }
+        }
+        self
+    }
+
+    /// Run operations as described by `execution`
+    pub fn execution(self, execution: Execution) -> Self {
+        self.shared.state().execution = execution;
+        self
+    }
+
+    /// Whether to advertise `IORING_FEAT_NODROP`, as every kernel since 5.5
+    /// does. Without it, completions that don't fit in the completion queue
+    /// are thrown away and counted in its overflow counter.
+    pub fn nodrop(self, nodrop: bool) -> Self {
+        self.shared.state().nodrop = nodrop;
+        self
+    }
+
+    /// Let the first operation with `user_data` that hasn't been released yet
+    /// post its completion once it finishes, with `Order::Manual`.
+    ///
+    /// Returns false if no such operation is in flight.
+    pub fn release(&self, user_data: u64) -> bool {
+        let mut state = self.shared.state();
+
+        let entry = state
+            .ops
+            .values_mut()
+            .find(|entry| entry.user_data == user_data && !entry.released);
+        match entry {
+            Some(entry) => entry.released = true,
+            None => return false,
+        }
+
+        self.shared.flush(&mut state);
+        true
+    }
+
+    /// Release every operation in flight, see `release`
+    pub fn release_all(&self) {
+        let mut state = self.shared.state();
+
+        for entry in state.ops.values_mut() {
+            entry.released = true;
+        }
+        self.shared.flush(&mut state);
+    }
+
+    /// The `user_data` of the operations that have finished but whose
+    /// completion is being held back, in submission order
+    pub fn held(&self) -> Vec<u64> {
+        self.shared
+            .state()
+            .ops
+            .values()
+            .filter(|entry| entry.is_done())
+            .map(|entry| entry.user_data)
+            .collect()
+    }
+}
+
+impl Default for Sim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Backend for Sim {
+    fn setup(
+        &mut self,
+        entries: u32,
+        params: &mut chakra_sys::io_uring_params,
+    ) -> Result<chakra_sys::io_uring> {
+        let rings = Rings::setup(entries, params)?;
+        if self.shared.rings.set(rings).is_err() {
+            return Err(Error::from_errno(-libc::EBUSY));
+        }
+
+        params.flags &= !u32::from(
+            chakra_sys::IORING_SETUP_SQPOLL
+                | chakra_sys::IORING_SETUP_SQ_AFF
+                | chakra_sys::IORING_SETUP_ATTACH_WQ,
+        );
+        let mut state = self.shared.state();
+        state.disabled = params.flags & u32::from(chakra_sys::IORING_SETUP_R_DISABLED) != 0;
+        params.features =
+            chakra_sys::IORING_FEAT_SUBMIT_STABLE | chakra_sys::IORING_FEAT_RW_CUR_POS;
+        if state.nodrop {
+            params.features |= chakra_sys::IORING_FEAT_NODROP;
+        }
+        drop(state);
+
+        Ok(self.shared.rings().io_uring(params.flags))
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: u32) -> Result<usize> {
+        if self.shared.state().disabled {
+            return Err(Error::from_errno(-libc::EBADFD));
+        }
+
+        let submitted = self.shared.submit(to_submit);
+
+        if flags & chakra_sys::IORING_ENTER_GETEVENTS != 0 {
+            self.shared.wait(min_complete);
+        }
+
+        Ok(submitted)
+    }
+
+    unsafe fn register(&self, opcode: u32, arg: *const libc::c_void, nr_args: u32) -> Result<u32> {
+        let mut state = self.shared.state();
+        let errno = |errno: i32| Err(Error::from_errno(-errno));
+
+        let restricted = state.restrictions.as_ref().filter(|_| !state.disabled);
+        if restricted.is_some_and(|r| !r.register_ops.contains(&opcode)) {
+            return errno(libc::EACCES);
+        }
+
+        match opcode {
+            chakra_sys::IORING_REGISTER_BUFFERS => {
+                if state.buffers > 0 {
+                    return errno(libc::EBUSY);
+                }
+                if nr_args == 0 {
+                    return errno(libc::EINVAL);
+                }
+                state.buffers = nr_args;
+            }
+            chakra_sys::IORING_UNREGISTER_BUFFERS => {
+                if state.buffers == 0 {
+                    return errno(libc::ENXIO);
+                }
+                state.buffers = 0;
+            }
+            chakra_sys::IORING_REGISTER_FILES => {
+                if !state.files.is_empty() {
+                    return errno(libc::EBUSY);
+                }
+                if nr_args == 0 {
+                    return errno(libc::EINVAL);
+                }
+                state.files =
+                    std::slice::from_raw_parts(arg as *const RawFd, nr_args as usize).to_vec();
+            }
+            chakra_sys::IORING_UNREGISTER_FILES => {
+                if state.files.is_empty() {
+                    return errno(libc::ENXIO);
+                }
+                state.files.clear();
+            }
+            chakra_sys::IORING_REGISTER_EVENTFD | chakra_sys::IORING_REGISTER_EVENTFD_ASYNC => {
+                if state.eventfd.is_some() {
+                    return errno(libc::EBUSY);
+                }
+                if nr_args != 1 {
+                    return errno(libc::EINVAL);
+                }
+                let fd = libc::fcntl(*(arg as *const RawFd), libc::F_DUPFD_CLOEXEC, 0);
+                if fd == -1 {
+                    return errno(*libc::__errno_location());
+                }
+                state.eventfd = Some(fd);
+            }
+            chakra_sys::IORING_UNREGISTER_EVENTFD => match state.eventfd.take() {
+                Some(fd) => {
+                    libc::close(fd);
+                }
+                None => return errno(libc::ENXIO),
+            },
+            chakra_sys::IORING_REGISTER_PROBE => probe(arg as *mut io_uring_probe, nr_args),
+            chakra_sys::IORING_REGISTER_RESTRICTIONS => {
+                if !state.disabled {
+                    return errno(libc::EBADFD);
+                }
+                if state.restrictions.is_some() {
+                    return errno(libc::EBUSY);
+                }
+                let raw = std::slice::from_raw_parts(
+                    arg as *const chakra_sys::io_uring_restriction,
+                    nr_args as usize,
+                );
+                state.restrictions =
+                    Some(restrictions(raw).ok_or_else(|| Error::from_errno(-libc::EINVAL))?);
+            }
+            chakra_sys::IORING_REGISTER_ENABLE_RINGS => {
+                if !state.disabled {
+                    return errno(libc::EBADFD);
+                }
+                state.disabled = false;
+            }
+            _ => return errno(libc::EINVAL),
+        }
+
+        Ok(0)
+    }
+
+    unsafe fn exit(&mut self, _ring: &mut chakra_sys::io_uring) {
+        let mut state = self.shared.state();
+
+        state.exiting = true;
+        state.queue.clear();
+        for entry in state.ops.values_mut() {
+            if let Status::Polling(efd) = entry.status {
+                entry.status = Status::Cancelled;
+                signal(efd);
+            }
+        }
+        if let Some(fd) = state.eventfd.take() {
+            libc::close(fd);
+        }
+
+        self.shared.work.notify_all();
+        self.shared.posted.notify_all();
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn rings(&self) -> &Rings {
+        self.rings.get().expect("ring not set up")
+    }
+
+    /// Consume up to `to_submit` published SQEs and get them running,
+    /// returning how many were consumed
+    fn submit(self: &Arc<Self>, to_submit: u32) -> usize {
+        let rings = self.rings();
+        let (sq, entries, mask) = (&rings.sq, rings.sq_entries(), rings.sq_mask());
+        let mut state = self.state();
+
+        let head = sq[SQ_HEAD].load(AtomicOrdering::Relaxed);
+        let tail = sq[SQ_TAIL].load(AtomicOrdering::Acquire);
+        let n = cmp::min(to_submit, tail.wrapping_sub(head));
+
+        let batch = state.next_batch;
+        state.next_batch += 1;
+
+        let mut chains: Vec<Vec<Op>> = Vec::new();
+        let mut linked = false;
+        for i in 0..n {
+            let index =
+                sq[SQ_ARRAY + (head.wrapping_add(i) & mask) as usize].load(AtomicOrdering::Relaxed);
+            if index >= entries {
+                sq[SQ_DROPPED].fetch_add(1, AtomicOrdering::Relaxed);
+                linked = false;
+                continue;
+            }
+
+            let sqe = unsafe { ptr::read(rings.sqes[index as usize].get()) };
+            let denied = state.restrictions.as_ref().is_some_and(|r| !r.allows(&sqe));
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.ops.insert(
+                seq,
+                Entry {
+                    batch,
+                    user_data: sqe.user_data,
+                    status: Status::Queued,
+                    released: false,
+                },
+            );
+
+            let link = sqe.flags & (chakra_sys::IOSQE_IO_LINK | chakra_sys::IOSQE_IO_HARDLINK) != 0;
+            let op = Op { seq, sqe, denied };
+            match chains.last_mut() {
+                Some(chain) if linked => chain.push(op),
+                _ => chains.push(vec![op]),
+            }
+            linked = link;
+        }
+        // Hands the consumed slots back, the SQEs have been copied out
+        sq[SQ_HEAD].store(head.wrapping_add(n), AtomicOrdering::Release);
+
+        let mut inline = Vec::new();
+        for chain in chains {
+            if state.execution == Execution::Inline || chain.iter().all(Op::is_immediate) {
+                inline.push(chain);
+            } else {
+                state.queue.push_back(chain);
+            }
+        }
+
+        while state.idle < state.queue.len() {
+            state.idle += 1;
+            let shared = self.clone();
+            thread::Builder::new()
+                .name("chakra-sim".into())
+                .spawn(move || shared.worker())
+                .expect("failed to spawn a worker thread");
+        }
+        self.work.notify_all();
+        drop(state);
+
+        for chain in inline {
+            self.run_chain(chain, -1);
+        }
+
+        n as usize
+    }
+
+    /// Block until `min_complete` completions are ready, moving any that
+    /// overflowed back into the completion queue first
+    fn wait(&self, min_complete: u32) {
+        let mut state = self.state();
+
+        loop {
+            self.flush_overflow(&mut state);
+            if self.rings().cq_ready() >= min_complete || state.exiting {
+                return;
+            }
+
+            state = self.posted.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn worker(self: Arc<Self>) {
+        // Woken to cancel an operation waiting for readiness
+        let efd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        let mut state = self.state();
+
+        loop {
+            if let Some(chain) = state.queue.pop_front() {
+                state.idle -= 1;
+                drop(state);
+
+                self.run_chain(chain, efd);
+
+                state = self.state();
+                state.idle += 1;
+                continue;
+            }
+
+            if state.exiting {
+                break;
+            }
+
+            let (guard, timeout) = self
+                .work
+                .wait_timeout(state, WORKER_IDLE)
+                .unwrap_or_else(|e| e.into_inner());
+            state = guard;
+            if timeout.timed_out() && state.queue.is_empty() {
+                break;
+            }
+        }
+
+        state.idle -= 1;
+        drop(state);
+        if efd >= 0 {
+            unsafe { libc::close(efd) };
+        }
+    }
+
+    /// Run the operations of a chain one after the other, cancelling the rest
+    /// once one breaks the link
+    fn run_chain(&self, chain: Vec<Op>, efd: RawFd) {
+        let mut broken = false;
+
+        for op in chain {
+            let cancelled = broken || self.start(op.seq);
+            let (res, flags) = if cancelled {
+                (-libc::ECANCELED, 0)
+            } else {
+                unsafe { self.run(&op, efd) }
+            };
+            broken = broken || op.breaks_link(res);
+
+            let mut state = self.state();
+            if let Some(entry) = state.ops.get_mut(&op.seq) {
+                entry.status = Status::Done { res, flags };
+            }
+            self.flush(&mut state);
+        }
+    }
+
+    /// Mark the operation `seq` as running, unless it was cancelled while
+    /// waiting for the operations linked before it
+    fn start(&self, seq: u64) -> bool {
+        match self.state().ops.get_mut(&seq) {
+            Some(entry) if matches!(entry.status, Status::Cancelled) => true,
+            Some(entry) => {
+                entry.status = Status::Running;
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Run a single operation, returning the `res` and `flags` of its completion
+    unsafe fn run(&self, op: &Op, efd: RawFd) -> (i32, u32) {
+        use IoUringOp::*;
+
+        let sqe = &op.sqe;
+        let addr = sqe.addr_off.addr;
+        let off = sqe.file_off.off;
+        let len = sqe.len;
+
+        let opcode = match IoUringOp::ALL.get(usize::from(sqe.opcode)) {
+            Some(&opcode) => opcode,
+            None => return (-libc::EINVAL, 0),
+        };
+        if op.denied {
+            return (-libc::EACCES, 0);
+        }
+
+        // Operations that don't take a file, or don't accept registered ones
+        let res = match opcode {
+            IORING_OP_NOP => 0,
+            IORING_OP_ASYNC_CANCEL => self.cancel(op.seq, addr),
+            IORING_OP_PROVIDE_BUFFERS => self.provide_buffers(sqe),
+            IORING_OP_REMOVE_BUFFERS => self.remove_buffers(sqe),
+            IORING_OP_OPENAT => cvt(libc::openat(
+                sqe.fd,
+                addr as *const libc::c_char,
+                sqe.cmd_flags.open_flags as i32,
+                len,
+            ) as i64),
+            IORING_OP_CLOSE if sqe.flags & chakra_sys::IOSQE_FIXED_FILE != 0 => -libc::EBADF,
+            IORING_OP_CLOSE => cvt(libc::close(sqe.fd) as i64),
+            IORING_OP_STATX => cvt(libc::statx(
+                sqe.fd,
+                addr as *const libc::c_char,
+                sqe.cmd_flags.statx_flags as i32,
+                len,
+                off as *mut libc::statx,
+            ) as i64),
+            IORING_OP_MADVISE => cvt(libc::madvise(
+                addr as *mut libc::c_void,
+                len as usize,
+                sqe.cmd_flags.fadvise_advice as i32,
+            ) as i64),
+            _ => return self.run_on_file(op, opcode, efd),
+        };
+
+        (res, 0)
+    }
+
+    unsafe fn run_on_file(&self, op: &Op, opcode: IoUringOp, efd: RawFd) -> (i32, u32) {
+        use IoUringOp::*;
+
+        let sqe = &op.sqe;
+        let addr = sqe.addr_off.addr;
+        let off = sqe.file_off.off;
+        let len = sqe.len;
+        let msg_flags = sqe.cmd_flags.msg_flags as i32;
+
+        let fd = match self.file(sqe.fd, sqe.flags & chakra_sys::IOSQE_FIXED_FILE != 0) {
+            Ok(fd) => fd,
+            Err(res) => return (res, 0),
+        };
+
+        let fd_in = match opcode {
+            IORING_OP_SPLICE | IORING_OP_TEE => {
+                let splice = sqe.buf_index_padding.personality;
+                let fixed = sqe.cmd_flags.splice_flags & chakra_sys::SPLICE_F_FD_IN_FIXED != 0;
+                match self.file(splice.splice_fd_in, fixed) {
+                    Ok(fd) => fd,
+                    Err(res) => return (res, 0),
+                }
+            }
+            _ => -1,
+        };
+
+        if let IORING_OP_READ_FIXED | IORING_OP_WRITE_FIXED = opcode {
+            let index = sqe.buf_index_padding.personality.buf_or_group;
+            if u32::from(index) >= self.state().buffers {
+                return (-libc::EFAULT, 0);
+            }
+        }
+
+        let ready = match opcode {
+            IORING_OP_READV | IORING_OP_READ_FIXED | IORING_OP_READ | IORING_OP_RECV
+            | IORING_OP_RECVMSG | IORING_OP_ACCEPT => self.poll(op.seq, fd, libc::POLLIN, efd),
+            IORING_OP_WRITEV
+            | IORING_OP_WRITE_FIXED
+            | IORING_OP_WRITE
+            | IORING_OP_SEND
+            | IORING_OP_SENDMSG => self.poll(op.seq, fd, libc::POLLOUT, efd),
+            IORING_OP_SPLICE | IORING_OP_TEE => self.poll(op.seq, fd_in, libc::POLLIN, efd),
+            _ => Ok(()),
+        };
+        if let Err(res) = ready {
+            return (res, 0);
+        }
+
+        let res = match opcode {
+            IORING_OP_READV => positioned(
+                off,
+                || libc::readv(fd, addr as *const libc::iovec, len as i32),
+                |off| libc::preadv(fd, addr as *const libc::iovec, len as i32, off),
+            ),
+            IORING_OP_WRITEV => positioned(
+                off,
+                || libc::writev(fd, addr as *const libc::iovec, len as i32),
+                |off| libc::pwritev(fd, addr as *const libc::iovec, len as i32, off),
+            ),
+            IORING_OP_READ | IORING_OP_READ_FIXED => positioned(
+                off,
+                || libc::read(fd, addr as *mut libc::c_void, len as usize),
+                |off| libc::pread(fd, addr as *mut libc::c_void, len as usize, off),
+            ),
+            IORING_OP_WRITE | IORING_OP_WRITE_FIXED => positioned(
+                off,
+                || libc::write(fd, addr as *const libc::c_void, len as usize),
+                |off| libc::pwrite(fd, addr as *const libc::c_void, len as usize, off),
+            ),
+            IORING_OP_FSYNC => {
+                if sqe.cmd_flags.fsync_flags & chakra_sys::IORING_FSYNC_DATASYNC != 0 {
+                    cvt(libc::fdatasync(fd) as i64)
+                } else {
+                    cvt(libc::fsync(fd) as i64)
+                }
+            }
+            IORING_OP_FALLOCATE => {
+                cvt(libc::fallocate(fd, len as i32, off as libc::off_t, addr as libc::off_t) as i64)
+            }
+            IORING_OP_ACCEPT => cvt(libc::accept4(
+                fd,
+                addr as *mut libc::sockaddr,
+                off as *mut libc::socklen_t,
+                sqe.cmd_flags.accept_flags as i32,
+            ) as i64),
+            IORING_OP_CONNECT => {
+                cvt(libc::connect(fd, addr as *const libc::sockaddr, off as libc::socklen_t) as i64)
+            }
+            IORING_OP_SEND => {
+                cvt(libc::send(fd, addr as *const libc::c_void, len as usize, msg_flags) as i64)
+            }
+            IORING_OP_RECV if sqe.flags & chakra_sys::IOSQE_BUFFER_SELECT != 0 => {
+                return self.recv_provided(sqe, fd);
+            }
+            IORING_OP_RECV => {
+                cvt(libc::recv(fd, addr as *mut libc::c_void, len as usize, msg_flags) as i64)
+            }
+            IORING_OP_SENDMSG => {
+                cvt(libc::sendmsg(fd, addr as *const libc::msghdr, msg_flags) as i64)
+            }
+            IORING_OP_RECVMSG => {
+                cvt(libc::recvmsg(fd, addr as *mut libc::msghdr, msg_flags) as i64)
+            }
+            IORING_OP_SHUTDOWN => cvt(libc::shutdown(fd, len as i32) as i64),
+            IORING_OP_SPLICE => {
+                let mut off_in = sqe.addr_off.splice_off_in as libc::loff_t;
+                let mut off_out = off as libc::loff_t;
+                let off_in = if off_in == -1 {
+                    ptr::null_mut()
+                } else {
+                    &mut off_in as *mut _
+                };
+                let off_out = if off_out == -1 {
+                    ptr::null_mut()
+                } else {
+                    &mut off_out as *mut _
+                };
+                let flags = sqe.cmd_flags.splice_flags & !chakra_sys::SPLICE_F_FD_IN_FIXED;
+
+                cvt(libc::splice(fd_in, off_in, fd, off_out, len as usize, flags) as i64)
+            }
+            IORING_OP_TEE => {
+                let flags = sqe.cmd_flags.splice_flags & !chakra_sys::SPLICE_F_FD_IN_FIXED;
+                cvt(libc::tee(fd_in, fd, len as usize, flags) as i64)
+            }
+            IORING_OP_FADVISE => -libc::posix_fadvise(
+                fd,
+                off as libc::off_t,
+                libc::off_t::from(len),
+                sqe.cmd_flags.fadvise_advice as i32,
+            ),
+            IORING_OP_EPOLL_CTL => {
+                cvt(
+                    libc::epoll_ctl(fd, len as i32, off as i32, addr as *mut libc::epoll_event)
+                        as i64,
+                )
+            }
+            _ => -libc::EINVAL,
+        };
+
+        (res, 0)
+    }
+
+    /// Map `fd` through the registered files if `fixed` is set
+    fn file(&self, fd: RawFd, fixed: bool) -> std::result::Result<RawFd, i32> {
+        if !fixed {
+            return Ok(fd);
+        }
+
+        let state = self.state();
+        usize::try_from(fd)
+            .ok()
+            .and_then(|index| state.files.get(index).copied())
+            .filter(|&fd| fd >= 0)
+            .ok_or(-libc::EBADF)
+    }
+
+    /// Wait for `fd` to become ready for `events`, or for the operation to be
+    /// cancelled. Operations run inline, without an eventfd, can't be, and
+    /// ones on no file at all fail straight away.
+    fn poll(
+        &self,
+        seq: u64,
+        fd: RawFd,
+        events: libc::c_short,
+        efd: RawFd,
+    ) -> std::result::Result<(), i32> {
+        if efd < 0 || fd < 0 {
+            return Ok(());
+        }
+
+        if let Some(entry) = self.state().ops.get_mut(&seq) {
+            entry.status = Status::Polling(efd);
+        }
+
+        let mut fds = [
+            libc::pollfd {
+                fd,
+                events,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: efd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        while unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } == -1
+            && unsafe { *libc::__errno_location() } == libc::EINTR
+        {}
+
+        let mut state = self.state();
+        let entry = match state.ops.get_mut(&seq) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        if let Status::Cancelled = entry.status {
+            let mut value = 0u64;
+            unsafe { libc::read(efd, &mut value as *mut u64 as *mut libc::c_void, 8) };
+            return Err(-libc::ECANCELED);
+        }
+        entry.status = Status::Running;
+
+        Ok(())
+    }
+
+    /// `IORING_OP_ASYNC_CANCEL` of the operation with `user_data`, from the
+    /// operation `seq`
+    fn cancel(&self, seq: u64, user_data: u64) -> i32 {
+        let mut state = self.state();
+
+        let target = state
+            .ops
+            .iter()
+            .find(|(&other, entry)| {
+                other != seq && entry.user_data == user_data && !entry.is_done()
+            })
+            .map(|(&target, _)| target);
+        let target = match target {
+            Some(target) => target,
+            None => return -libc::ENOENT,
+        };
+
+        let entry = state.ops.get_mut(&target).unwrap();
+        match entry.status {
+            Status::Polling(efd) => {
+                entry.status = Status::Cancelled;
+                signal(efd);
+                0
+            }
+            Status::Queued => {
+                // Only chains that haven't started can still be taken off the
+                // queue, along with whatever is linked after the target
+                let position = state.queue.iter().enumerate().find_map(|(chain, ops)| {
+                    ops.iter()
+                        .position(|op| op.seq == target)
+                        .map(|index| (chain, index))
+                });
+                let (chain, index) = match position {
+                    Some(position) => position,
+                    None => {
+                        // Its chain has been picked up by a worker, which
+                        // skips it once it gets there
+                        state.ops.get_mut(&target).unwrap().status = Status::Cancelled;
+                        return 0;
+                    }
+                };
+
+                let cancelled: Vec<_> = if index == 0 {
+                    state.queue.remove(chain).unwrap()
+                } else {
+                    state.queue[chain].split_off(index)
+                };
+                for op in cancelled {
+                    if let Some(entry) = state.ops.get_mut(&op.seq) {
+                        entry.status = Status::Done {
+                            res: -libc::ECANCELED,
+                            flags: 0,
+                        };
+                    }
+                }
+                self.flush(&mut state);
+                0
+            }
+            _ => -libc::EALREADY,
+        }
+    }
+
+    unsafe fn provide_buffers(&self, sqe: &io_uring_sqe) -> i32 {
+        let nr = sqe.fd;
+        let bid = sqe.file_off.off;
+        let group = sqe.buf_index_padding.personality.buf_or_group;
+
+        if nr <= 0 || bid + nr as u64 > u64::from(u16::MAX) + 1 {
+            return -libc::EINVAL;
+        }
+
+        let mut state = self.state();
+        let buffers = state.groups.entry(group).or_default();
+        for i in 0..nr as u64 {
+            let addr = sqe.addr_off.addr + i * u64::from(sqe.len);
+            buffers.push((addr, sqe.len, (bid + i) as u16));
+        }
+
+        0
+    }
+
+    unsafe fn remove_buffers(&self, sqe: &io_uring_sqe) -> i32 {
+        let group = sqe.buf_index_padding.personality.buf_or_group;
+        let mut state = self.state();
+
+        match state.groups.get_mut(&group) {
+            Some(buffers) if !buffers.is_empty() => {
+                let n = cmp::min(buffers.len(), sqe.fd.max(0) as usize);
+                buffers.truncate(buffers.len() - n);
+                n as i32
+            }
+            _ => -libc::ENOENT,
+        }
+    }
+
+    /// A recv into a buffer picked from the group of the SQE
+    unsafe fn recv_provided(&self, sqe: &io_uring_sqe, fd: RawFd) -> (i32, u32) {
+        let group = sqe.buf_index_padding.personality.buf_or_group;
+        let buffer = self.state().groups.get_mut(&group).and_then(Vec::pop);
+        let (addr, buf_len, bid) = match buffer {
+            Some(buffer) => buffer,
+            None => return (-libc::ENOBUFS, 0),
+        };
+
+        let len = if sqe.len == 0 {
+            buf_len
+        } else {
+            cmp::min(sqe.len, buf_len)
+        };
+        let res = cvt(libc::recv(
+            fd,
+            addr as *mut libc::c_void,
+            len as usize,
+            sqe.cmd_flags.msg_flags as i32,
+        ) as i64);
+
+        if res < 0 {
+            self.state()
+                .groups
+                .entry(group)
+                .or_default()
+                .push((addr, buf_len, bid));
+            return (res, 0);
+        }
+
+        (
+            res,
+            chakra_sys::IORING_CQE_F_BUFFER | u32::from(bid) << chakra_sys::IORING_CQE_BUFFER_SHIFT,
+        )
+    }
+
+    /// Post the completions of finished operations that `Order` lets through
+    fn flush(&self, state: &mut State) {
+        let mut ready = Vec::new();
+
+        match state.order {
+            Order::Completion => ready.extend(
+                state
+                    .ops
+                    .iter()
+                    .filter(|(_, entry)| entry.is_done())
+                    .map(|(&seq, _)| seq),
+            ),
+            Order::Submission => ready.extend(
+                state
+                    .ops
+                    .iter()
+                    .take_while(|(_, entry)| entry.is_done())
+                    .map(|(&seq, _)| seq),
+            ),
+            Order::Manual => ready.extend(
+                state
+                    .ops
+                    .iter()
+                    .filter(|(_, entry)| entry.is_done() && entry.released)
+                    .map(|(&seq, _)| seq),
+            ),
+            Order::Reverse | Order::Shuffle(_) => {
+                let mut batches: BTreeMap<u64, (Vec<u64>, bool)> = BTreeMap::new();
+                for (&seq, entry) in &state.ops {
+                    let batch = batches.entry(entry.batch).or_insert((Vec::new(), true));
+                    batch.0.push(seq);
+                    batch.1 &= entry.is_done();
+                }
+
+                for (_, (mut seqs, done)) in batches {
+                    if !done {
+                        continue;
+                    }
+
+                    if state.order == Order::Reverse {
+                        seqs.reverse();
+                    } else {
+                        for i in (1..seqs.len()).rev() {
+                            let j = (splitmix64(&mut state.rng) % (i as u64 + 1)) as usize;
+                            seqs.swap(i, j);
+                        }
+                    }
+                    ready.extend(seqs);
+                }
+            }
+        }
+
+        for seq in ready {
+            if let Some(Entry {
+                user_data,
+                status: Status::Done { res, flags },
+                ..
+            }) = state.ops.remove(&seq)
+            {
+                self.post(state, user_data, res, flags);
+            }
+        }
+    }
+
+    /// Write a CQE, or hold on to it if the completion queue is full, or
+    /// without `nodrop` throw it away
+    fn post(&self, state: &mut State, user_data: u64, res: i32, flags: u32) {
+        let rings = self.rings();
+
+        if !state.nodrop {
+            if !rings.push_cqe(user_data, res, flags) {
+                rings.drop_cqe();
+            }
+        } else if !state.overflow.is_empty() || !rings.push_cqe(user_data, res, flags) {
+            state.overflow.push_back((user_data, res, flags));
+            rings.sq[SQ_FLAGS].fetch_or(chakra_sys::IORING_SQ_CQ_OVERFLOW, AtomicOrdering::Relaxed);
+        }
+
+        if let Some(fd) = state.eventfd {
+            signal(fd);
+        }
+        self.posted.notify_all();
+    }
+
+    /// Move completions that overflowed into the completion queue, as far as
+    /// there is room
+    fn flush_overflow(&self, state: &mut State) {
+        let rings = self.rings();
+
+        while let Some(&(user_data, res, flags)) = state.overflow.front() {
+            if !rings.push_cqe(user_data, res, flags) {
+                return;
+            }
+            state.overflow.pop_front();
+        }
+
+        rings.sq[SQ_FLAGS].fetch_and(!chakra_sys::IORING_SQ_CQ_OVERFLOW, AtomicOrdering::Relaxed);
+    }
+}
+
+impl Entry {
+    fn is_done(&self) -> bool {
+        matches!(self.status, Status::Done { .. })
+    }
+}
+
+impl Op {
+    /// Operations that never block, and are run right away even with
+    /// `Execution::Threads`
+    fn is_immediate(&self) -> bool {
+        use IoUringOp::*;
+
+        self.denied
+            || [
+                IORING_OP_NOP,
+                IORING_OP_ASYNC_CANCEL,
+                IORING_OP_PROVIDE_BUFFERS,
+                IORING_OP_REMOVE_BUFFERS,
+            ]
+            .iter()
+            .any(|&op| op as u8 == self.sqe.opcode)
+    }
+
+    /// Whether a completion with `res` cancels the operations linked after
+    /// this one. Reads, writes and splices that come up short do too.
+    fn breaks_link(&self, res: i32) -> bool {
+        use IoUringOp::*;
+
+        if self.sqe.flags & chakra_sys::IOSQE_IO_HARDLINK != 0 {
+            return false;
+        }
+
+        let transfers = [
+            IORING_OP_READ,
+            IORING_OP_WRITE,
+            IORING_OP_READ_FIXED,
+            IORING_OP_WRITE_FIXED,
+            IORING_OP_SEND,
+            IORING_OP_RECV,
+            IORING_OP_SPLICE,
+            IORING_OP_TEE,
+        ]
+        .iter()
+        .any(|&op| op as u8 == self.sqe.opcode);
+
+        res < 0 || (transfers && (res as u32) < self.sqe.len)
+    }
+}
+
+/// The opcodes `Shared::run` executes
+const SUPPORTED: &[IoUringOp] = &[
+    IoUringOp::IORING_OP_NOP,
+    IoUringOp::IORING_OP_READV,
+    IoUringOp::IORING_OP_WRITEV,
+    IoUringOp::IORING_OP_FSYNC,
+    IoUringOp::IORING_OP_READ_FIXED,
+    IoUringOp::IORING_OP_WRITE_FIXED,
+    IoUringOp::IORING_OP_SENDMSG,
+    IoUringOp::IORING_OP_RECVMSG,
+    IoUringOp::IORING_OP_ACCEPT,
+    IoUringOp::IORING_OP_ASYNC_CANCEL,
+    IoUringOp::IORING_OP_CONNECT,
+    IoUringOp::IORING_OP_FALLOCATE,
+    IoUringOp::IORING_OP_OPENAT,
+    IoUringOp::IORING_OP_CLOSE,
+    IoUringOp::IORING_OP_STATX,
+    IoUringOp::IORING_OP_READ,
+    IoUringOp::IORING_OP_WRITE,
+    IoUringOp::IORING_OP_FADVISE,
+    IoUringOp::IORING_OP_MADVISE,
+    IoUringOp::IORING_OP_SEND,
+    IoUringOp::IORING_OP_RECV,
+    IoUringOp::IORING_OP_EPOLL_CTL,
+    IoUringOp::IORING_OP_SPLICE,
+    IoUringOp::IORING_OP_PROVIDE_BUFFERS,
+    IoUringOp::IORING_OP_REMOVE_BUFFERS,
+    IoUringOp::IORING_OP_TEE,
+    IoUringOp::IORING_OP_SHUTDOWN,
+];
+
+impl Restrictions {
+    /// Whether `sqe` may be submitted
+    fn allows(&self, sqe: &io_uring_sqe) -> bool {
+        self.sqe_ops.contains(&sqe.opcode)
+            && sqe.flags & !(self.sqe_flags_allowed | self.sqe_flags_required) == 0
+            && sqe.flags & self.sqe_flags_required == self.sqe_flags_required
+    }
+}
+
+/// Parse the restrictions registered with `IORING_REGISTER_RESTRICTIONS`, or
+/// `None` if any is invalid
+unsafe fn restrictions(raw: &[chakra_sys::io_uring_restriction]) -> Option<Restrictions> {
+    let mut restrictions = Restrictions::default();
+
+    for restriction in raw {
+        let arg = restriction.restriction_op.register_op;
+        match u32::from(restriction.opcode) {
+            chakra_sys::IORING_RESTRICTION_REGISTER_OP => {
+                restrictions.register_ops.push(u32::from(arg))
+            }
+            chakra_sys::IORING_RESTRICTION_SQE_OP => restrictions.sqe_ops.push(arg),
+            chakra_sys::IORING_RESTRICTION_SQE_FLAGS_ALLOWED => {
+                restrictions.sqe_flags_allowed = arg
+            }
+            chakra_sys::IORING_RESTRICTION_SQE_FLAGS_REQUIRED => {
+                restrictions.sqe_flags_required = arg
+            }
+            _ => return None,
+        }
+    }
+
+    Some(restrictions)
+}
+
+/// Fill in the `IORING_REGISTER_PROBE` result, with room for `nr_ops` ops
+unsafe fn probe(probe: *mut io_uring_probe, nr_ops: u32) {
+    let last = IoUringOp::IORING_OP_LAST as u32;
+    let ops = (probe as *mut u8).add(mem::size_of::<io_uring_probe>()) as *mut io_uring_probe_op;
+    let len = cmp::min(nr_ops, last);
+
+    (*probe).last_op = (last - 1) as u8;
+    (*probe).ops_len = len as u8;
+    for (i, &op) in IoUringOp::ALL.iter().take(len as usize).enumerate() {
+        let flags = if SUPPORTED.contains(&op) {
+            chakra_sys::IO_URING_OP_SUPPORTED as u16
+        } else {
+            0
+        };
+
+        ptr::write(
+            ops.add(i),
+            io_uring_probe_op {
+                op: op as u8,
+                resv: 0,
+                flags,
+                resv2: 0,
+            },
+        );
+    }
+}
+
+/// A read or write at `off`, or at the file position if it's -1 or the file
+/// can't seek, like the kernel does
+fn positioned(off: u64, current: impl FnOnce() -> isize, at: impl FnOnce(i64) -> isize) -> i32 {
+    if off == u64::MAX {
+        return cvt(current() as i64);
+    }
+
+    match cvt(at(off as i64) as i64) {
+        res if res == -libc::ESPIPE => cvt(current() as i64),
+        res => res,
+    }
+}
+
+/// Turn a syscall return value into a CQE result
+fn cvt(res: i64) -> i32 {
+    if res < 0 {
+        -unsafe { *libc::__errno_location() }
+    } else {
+        res as i32
+    }
+}
+
+/// Signal the eventfd `fd`
+pub(super) fn signal(fd: RawFd) {
+    let value = 1u64;
+    unsafe { libc::write(fd, &value as *const u64 as *const libc::c_void, 8) };
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
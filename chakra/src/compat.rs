@@ -0,0 +1,337 @@
+//! `AsyncRead`/`AsyncWrite` adapters for the owned-buffer types.
+//!
+//! The poll-based traits hand in borrowed slices, which can't be given to the
+//! kernel because the caller may drop them while the operation is in flight.
+//! `Compat` keeps a read buffer and a write buffer of its own instead, which
+//! are what the kernel reads into and writes from. That costs a copy per call:
+//!
+//! - `poll_read` copies from the read buffer into the caller's slice. Reading
+//!   through `AsyncBufRead` avoids it, the caller gets the read buffer itself.
+//! - `poll_write` copies the caller's slice into the write buffer, and returns
+//!   as soon as it's copied. Errors from the write show up on the next
+//!   `poll_write`, `poll_flush` or `poll_close`.
+//! - Short writes move the unwritten tail to the front of the write buffer
+//!   before it's written again.
+//!
+//! The `futures-io` traits are always implemented, tokio's are implemented with
+//! the `tokio` feature.
+
+use std::{
+    cmp,
+    future::Future,
+    io, mem,
+    net::Shutdown,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use crate::{
+    fs::File,
+    net::{TcpStream, UnixStream},
+};
+
+/// Default capacity of each of the buffers of a `Compat`
+const BUF_CAPACITY: usize = 8 * 1024;
+
+/// An owned-buffer read or write, resolving to its result and the buffer
+pub type BufFuture = Pin<Box<dyn Future<Output = (io::Result<usize>, Vec<u8>)>>>;
+/// Shutting down the write side of an `OwnedIo` stream
+pub type CloseFuture = Pin<Box<dyn Future<Output = io::Result<()>>>>;
+
+/// A stream `Compat` can adapt, performing sequential reads and writes with
+/// owned buffers.
+pub trait OwnedIo: 'static {
+    /// Read into `buf`, up to its capacity
+    fn read_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture;
+
+    /// Write the contents of `buf`
+    fn write_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture;
+
+    /// Shut down the write side, once everything written has been flushed
+    fn close_owned(self: Rc<Self>) -> CloseFuture;
+}
+
+impl OwnedIo for TcpStream {
+    fn read_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture {
+        Box::pin(async move { self.read(buf).await })
+    }
+
+    fn write_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture {
+        Box::pin(async move { self.write(buf).await })
+    }
+
+    fn close_owned(self: Rc<Self>) -> CloseFuture {
+        Box::pin(async move { self.shutdown(Shutdown::Write).await })
+    }
+}
+
+impl OwnedIo for UnixStream {
+    fn read_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture {
+        Box::pin(async move { self.read(buf).await })
+    }
+
+    fn write_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture {
+        Box::pin(async move { self.write(buf).await })
+    }
+
+    fn close_owned(self: Rc<Self>) -> CloseFuture {
+        Box::pin(async move { self.shutdown(Shutdown::Write).await })
+    }
+}
+
+/// Files are read and written at their cursor, see `File::read`.
+impl OwnedIo for File {
+    fn read_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture {
+        Box::pin(async move { self.read(buf).await })
+    }
+
+    fn write_owned(self: Rc<Self>, buf: Vec<u8>) -> BufFuture {
+        Box::pin(async move { self.write(buf).await })
+    }
+
+    fn close_owned(self: Rc<Self>) -> CloseFuture {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Implements the poll-based I/O traits for an `OwnedIo` stream, copying
+/// through buffers of its own. See the module docs for the costs.
+pub struct Compat<T> {
+    inner: Rc<T>,
+    /// Data read but not consumed yet is `read_buf[read_pos..]`. The buffer is
+    /// moved into `read` while a read is in flight.
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    read: Option<BufFuture>,
+    /// Data accepted but not handed to a write yet. The buffer is moved into
+    /// `write` while a write is in flight.
+    write_buf: Vec<u8>,
+    write: Option<BufFuture>,
+    close: Option<CloseFuture>,
+}
+
+impl<T: OwnedIo> Compat<T> {
+    pub fn new(inner: T) -> Self {
+        Self::with_capacity(BUF_CAPACITY, inner)
+    }
+
+    /// Adapt `inner` with read and write buffers of `capacity` bytes, which is
+    /// the most a single operation transfers. A `capacity` of 0 is rounded up
+    /// to 1, as `poll_write` couldn't accept anything otherwise.
+    pub fn with_capacity(capacity: usize, inner: T) -> Self {
+        let capacity = cmp::max(capacity, 1);
+
+        Compat {
+            inner: Rc::new(inner),
+            read_buf: Vec::with_capacity(capacity),
+            read_pos: 0,
+            read: None,
+            write_buf: Vec::with_capacity(capacity),
+            write: None,
+            close: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        if self.read_pos == self.read_buf.len() {
+            if self.read.is_none() {
+                let mut buf = mem::take(&mut self.read_buf);
+                buf.clear();
+                self.read_pos = 0;
+                self.read = Some(self.inner.clone().read_owned(buf));
+            }
+
+            let (res, buf) = match self.read.as_mut().unwrap().as_mut().poll(cx) {
+                Poll::Ready(done) => done,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.read = None;
+            self.read_buf = buf;
+
+            if let Err(e) = res {
+                return Poll::Ready(Err(e));
+            }
+        }
+
+        Poll::Ready(Ok(&self.read_buf[self.read_pos..]))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.read_pos = cmp::min(self.read_pos + amt, self.read_buf.len());
+    }
+
+    fn poll_read_into(&mut self, cx: &mut Context<'_>, dst: &mut [u8]) -> Poll<io::Result<usize>> {
+        let src = match self.poll_fill(cx) {
+            Poll::Ready(Ok(src)) => src,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let n = cmp::min(src.len(), dst.len());
+        dst[..n].copy_from_slice(&src[..n]);
+        self.consume(n);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write_from(&mut self, cx: &mut Context<'_>, src: &[u8]) -> Poll<io::Result<usize>> {
+        match self.poll_written(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let room = self.write_buf.capacity() - self.write_buf.len();
+        let n = cmp::min(room, src.len());
+        self.write_buf.extend_from_slice(&src[..n]);
+        self.start_write();
+
+        Poll::Ready(Ok(n))
+    }
+
+    /// Hand the write buffer to a write, if there's anything in it
+    fn start_write(&mut self) {
+        if !self.write_buf.is_empty() {
+            let buf = mem::take(&mut self.write_buf);
+            self.write = Some(self.inner.clone().write_owned(buf));
+        }
+    }
+
+    /// Drive the writes in flight until all accepted data has been written
+    fn poll_written(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(write) = self.write.as_mut() {
+            let (res, mut buf) = match write.as_mut().poll(cx) {
+                Poll::Ready(done) => done,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.write = None;
+
+            match res {
+                Ok(n) if n > 0 => {
+                    buf.drain(..n);
+                    self.write_buf = buf;
+                    self.start_write();
+                }
+                Ok(_) => {
+                    buf.clear();
+                    self.write_buf = buf;
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                Err(e) => {
+                    buf.clear();
+                    self.write_buf = buf;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close_inner(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.close.is_none() {
+            match self.poll_written(cx) {
+                Poll::Ready(Ok(())) => {}
+                other => return other,
+            }
+            self.close = Some(self.inner.clone().close_owned());
+        }
+
+        self.close.as_mut().unwrap().as_mut().poll(cx)
+    }
+}
+
+impl<T: OwnedIo> futures_io::AsyncRead for Compat<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_read_into(cx, buf)
+    }
+}
+
+impl<T: OwnedIo> futures_io::AsyncBufRead for Compat<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().poll_fill(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume(amt)
+    }
+}
+
+impl<T: OwnedIo> futures_io::AsyncWrite for Compat<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_from(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_written(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_close_inner(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: OwnedIo> tokio::io::AsyncRead for Compat<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let src = match this.poll_fill(cx) {
+            Poll::Ready(Ok(src)) => src,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let n = cmp::min(src.len(), buf.remaining());
+        buf.put_slice(&src[..n]);
+        this.consume(n);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: OwnedIo> tokio::io::AsyncBufRead for Compat<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().poll_fill(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().consume(amt)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: OwnedIo> tokio::io::AsyncWrite for Compat<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().poll_write_from(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_written(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_close_inner(cx)
+    }
+}
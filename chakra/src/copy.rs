@@ -0,0 +1,166 @@
+//! Zero-copy transfers between file descriptors, built on linked splices.
+
+use std::{
+    cmp,
+    fs::File,
+    io,
+    ops::Range,
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+
+use crate::{IoRing, Splice, SpliceFlags, Sqe, SqeFlags};
+
+/// Amount of data moved through the pipe per pair of splices. Matches the
+/// default pipe capacity so the first splice never blocks on a full pipe.
+const PIPE_CHUNK: u64 = 64 * 1024;
+
+/// `user_data` of the splices into and out of the pipe, telling their
+/// completions apart from each other and from anything else on the ring.
+/// `SPLICE_OUT` is also what `rt::Driver` submits its cancellations with.
+const SPLICE_IN: u64 = u64::MAX - 1;
+const SPLICE_OUT: u64 = u64::MAX;
+
+/// Send `range` of `file` to `socket` without copying it through user space.
+///
+/// Data is moved through an intermediate pipe using a linked pair of splices
+/// per chunk. The ring must not have any other operations in flight, as the
+/// completions are reaped by this function: finding one that isn't a splice
+/// of the transfer fails it with `io::ErrorKind::InvalidData`, and that
+/// completion is lost. The splices are submitted with the `user_data`
+/// `u64::MAX - 1` and `u64::MAX`, and it's up to the caller not to have
+/// anything else on the ring use those, e.g. by not sharing it with an
+/// `rt::Driver`.
+///
+/// Returns the number of bytes sent, which is less than the length of `range`
+/// only if the file ends first.
+pub fn sendfile<F, S>(ring: &mut IoRing, file: &F, socket: &S, range: Range<u64>) -> io::Result<u64>
+where
+    F: AsRawFd,
+    S: AsRawFd,
+{
+    let pipe = Pipe::new()?;
+    let mut offset = range.start;
+
+    while offset < range.end {
+        let len = cmp::min(range.end - offset, PIPE_CHUNK) as u32;
+
+        // Both halves are taken before either is prepared, so that a link is
+        // never left dangling in the queue
+        if ring.split().0.space_left() < 2 {
+            return Err(sq_full());
+        }
+        let mut splice_in = next_sqe(ring)?;
+        let mut splice_out = next_sqe(ring)?;
+
+        let prepared = splice_in
+            .prep_splice(
+                Splice::new(file, &pipe.write, len)
+                    .off_in(offset)
+                    .flags(SpliceFlags::SPLICE_F_MOVE),
+            )
+            .and_then(|()| {
+                splice_out.prep_splice(
+                    Splice::new(&pipe.read, socket, len).flags(SpliceFlags::SPLICE_F_MOVE),
+                )
+            });
+        if let Err(e) = prepared {
+            splice_in.discard(SPLICE_IN);
+            splice_out.discard(SPLICE_OUT);
+            return Err(e.into());
+        }
+        splice_in.set_flags(SqeFlags::IOSQE_IO_LINK);
+        splice_in.set_user_data(SPLICE_IN);
+        splice_out.set_user_data(SPLICE_OUT);
+
+        ring.submit_and_wait(2)?;
+
+        let (mut filled, mut drained) = (None, None);
+        for _ in 0..2 {
+            let cqe = ring.wait_cqe()?;
+            match cqe.user_data() {
+                SPLICE_IN if filled.is_none() => filled = Some(cqe.result()),
+                SPLICE_OUT if drained.is_none() => drained = Some(cqe.result()),
+                user_data => return Err(unexpected(user_data)),
+            }
+        }
+
+        let (filled, drained) = match (filled, drained) {
+            (Some(filled), Some(drained)) => (filled?, drained),
+            _ => return Err(io::Error::other("missing splice completion")),
+        };
+        // A short splice into the pipe breaks the link, so the second half is
+        // cancelled and whatever did make it into the pipe is drained below.
+        let drained = match drained {
+            Ok(n) => n,
+            Err(ref e) if e.raw_os_error() == Some(libc::ECANCELED) => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        if filled == 0 {
+            break;
+        }
+
+        drain(ring, &pipe.read, socket, filled - drained)?;
+        offset += u64::from(filled);
+    }
+
+    Ok(offset - range.start)
+}
+
+/// Splice exactly `len` bytes out of `pipe` into `output`.
+fn drain<O: AsRawFd>(ring: &mut IoRing, pipe: &File, output: &O, mut len: u32) -> io::Result<()> {
+    while len > 0 {
+        let mut sqe = next_sqe(ring)?;
+        sqe.prep_splice(Splice::new(pipe, output, len).flags(SpliceFlags::SPLICE_F_MOVE))?;
+        sqe.set_user_data(SPLICE_OUT);
+
+        ring.submit_and_wait(1)?;
+
+        let cqe = ring.wait_cqe()?;
+        if cqe.user_data() != SPLICE_OUT {
+            return Err(unexpected(cqe.user_data()));
+        }
+
+        match cqe.result()? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            n => len -= n,
+        }
+    }
+
+    Ok(())
+}
+
+fn unexpected(user_data: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected completion with user_data {}", user_data),
+    )
+}
+
+fn next_sqe(ring: &mut IoRing) -> io::Result<Sqe> {
+    ring.get_sqe().ok_or_else(sq_full)
+}
+
+fn sq_full() -> io::Error {
+    io::Error::other("submission queue is full")
+}
+
+struct Pipe {
+    read: File,
+    write: File,
+}
+
+impl Pipe {
+    fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Pipe {
+            read: unsafe { File::from_raw_fd(fds[0]) },
+            write: unsafe { File::from_raw_fd(fds[1]) },
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use crate::{error::Error, Result};
+
+/// A completion queue event, copied out of the ring once it has been reaped.
+#[derive(Debug, Clone, Copy)]
+pub struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+impl Cqe {
+    pub(crate) fn from_raw(cqe: &chakra_sys::io_uring_cqe) -> Self {
+        Cqe {
+            user_data: cqe.user_data,
+            res: cqe.res,
+            flags: cqe.flags,
+        }
+    }
+
+    /// The same event with `res` as its result, for `Faults`
+    pub(crate) fn with_result(self, res: i32) -> Self {
+        Cqe { res, ..self }
+    }
+
+    pub(crate) fn raw_result(&self) -> i32 {
+        self.res
+    }
+
+    /// The `user_data` of the submission this event completes
+    pub fn user_data(&self) -> u64 {
+        self.user_data
+    }
+
+    /// The result of the operation, with negative errno values turned into errors
+    pub fn result(&self) -> Result<u32> {
+        if self.res < 0 {
+            Err(Error::from_errno(self.res))
+        } else {
+            Ok(self.res as u32)
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// The id of the provided buffer the kernel picked for this operation,
+    /// see `Sqe::prep_recv_provided`
+    pub fn buffer_id(&self) -> Option<u16> {
+        if self.flags & chakra_sys::IORING_CQE_F_BUFFER == 0 {
+            return None;
+        }
+
+        Some((self.flags >> chakra_sys::IORING_CQE_BUFFER_SHIFT) as u16)
+    }
+}
@@ -0,0 +1,348 @@
+use chakra_sys::IoUringOp;
+
+use std::{error, ffi::CStr, fmt, io};
+
+/// The largest submission queue the kernel accepts, anything above this is
+/// rejected with `EINVAL` unless the ring is set up with `IORING_SETUP_CLAMP`.
+pub(crate) const IORING_MAX_ENTRIES: u32 = 32768;
+
+/// Likewise for the completion queue, when sized with `IORING_SETUP_CQSIZE`
+pub(crate) const IORING_MAX_CQ_ENTRIES: u32 = 2 * IORING_MAX_ENTRIES;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the ring, register and completion paths.
+///
+/// Failures to set up a ring are mapped onto the variants describing the usual
+/// causes, so that they can be reported in a way that's actionable. Everything
+/// else is passed through as `Error::Io`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The kernel was built without io_uring, or predates it (`ENOSYS`)
+    Unsupported,
+    /// The requested queue sizes are larger than the kernel allows (`EINVAL`),
+    /// or than it could find memory for (`ENOMEM`). `cq_entries` is only set
+    /// for rings sized with `IORING_SETUP_CQSIZE`.
+    RingTooLarge {
+        entries: u32,
+        cq_entries: Option<u32>,
+        errno: i32,
+    },
+    /// Locking the ring memory would exceed `RLIMIT_MEMLOCK`, which is set to
+    /// `limit` bytes (`ENOMEM`)
+    MemlockLimit { limit: u64 },
+    /// io_uring is blocked for this process, usually by a seccomp filter or the
+    /// `kernel.io_uring_disabled` sysctl (`EPERM`)
+    PermissionDenied,
+    /// The opcode can't be used on an `IORING_SETUP_IOPOLL` ring, which only
+    /// supports reads and writes
+    IopollUnsupported(IoUringOp),
+    /// Submitting would risk overflowing the completion queue on a kernel
+    /// without `IORING_FEAT_NODROP`, completions have to be reaped first
+    CompletionQueueFull,
+    /// Any other error returned by the kernel
+    Io(io::Error),
+}
+
+impl Error {
+    /// Convert the negative errno returned by liburing, a syscall or a CQE
+    pub(crate) fn from_errno(res: i32) -> Self {
+        Error::Io(io::Error::from_raw_os_error(-res))
+    }
+
+    /// Convert the negative errno returned when setting up a ring of `entries`
+    /// with `params`
+    pub(crate) fn from_setup_errno(
+        res: i32,
+        entries: u32,
+        params: &chakra_sys::io_uring_params,
+    ) -> Self {
+        Self::map_setup_errno(res, entries, params, Accounting::current())
+    }
+
+    fn map_setup_errno(
+        res: i32,
+        entries: u32,
+        params: &chakra_sys::io_uring_params,
+        accounting: Accounting,
+    ) -> Self {
+        let clamp = params.flags & u32::from(chakra_sys::IORING_SETUP_CLAMP) != 0;
+        let cq_entries = Some(params.cq_entries)
+            .filter(|_| params.flags & u32::from(chakra_sys::IORING_SETUP_CQSIZE) != 0);
+        let too_large = |errno| Error::RingTooLarge {
+            entries,
+            cq_entries,
+            errno,
+        };
+
+        match -res {
+            libc::ENOSYS => Error::Unsupported,
+            libc::EPERM => Error::PermissionDenied,
+            libc::EINVAL
+                if !clamp
+                    && (entries > IORING_MAX_ENTRIES
+                        || cq_entries.is_some_and(|n| n > IORING_MAX_CQ_ENTRIES)) =>
+            {
+                too_large(libc::EINVAL)
+            }
+            libc::ENOMEM => match accounting {
+                Accounting::Memlock(Some(limit)) => Error::MemlockLimit { limit },
+                Accounting::Memlock(None) | Accounting::Memcg => too_large(libc::ENOMEM),
+            },
+            _ => Error::from_errno(res),
+        }
+    }
+
+    /// The errno this error was created from, or `None` for the errors
+    /// raised by chakra itself
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            Error::Unsupported => Some(libc::ENOSYS),
+            Error::RingTooLarge { errno, .. } => Some(*errno),
+            Error::MemlockLimit { .. } => Some(libc::ENOMEM),
+            Error::PermissionDenied => Some(libc::EPERM),
+            Error::IopollUnsupported(_) | Error::CompletionQueueFull => None,
+            Error::Io(e) => e.raw_os_error(),
+        }
+    }
+}
+
+/// What the memory of a ring is charged against, which decides what an
+/// `ENOMEM` from setting one up means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Accounting {
+    /// `RLIMIT_MEMLOCK` before Linux 5.12, with its value unless unlimited
+    Memlock(Option<u64>),
+    /// The memory cgroup from Linux 5.12 on
+    Memcg,
+}
+
+impl Accounting {
+    fn current() -> Self {
+        match kernel_version() {
+            Some(version) if version < (5, 12) => Accounting::Memlock(memlock_limit()),
+            _ => Accounting::Memcg,
+        }
+    }
+}
+
+/// The major and minor version of the running kernel
+fn kernel_version() -> Option<(u32, u32)> {
+    let mut uts = unsafe { std::mem::zeroed::<libc::utsname>() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) }
+        .to_str()
+        .ok()?;
+    let mut numbers = release.split(|c: char| !c.is_ascii_digit()).map(str::parse);
+
+    Some((numbers.next()?.ok()?, numbers.next()?.ok()?))
+}
+
+/// The current `RLIMIT_MEMLOCK`, or `None` if it is unlimited
+fn memlock_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+
+    Some(limit.rlim_cur)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unsupported => write!(
+                f,
+                "io_uring is not supported by this kernel, it needs Linux 5.1 or later \
+                 built with CONFIG_IO_URING"
+            ),
+            Error::RingTooLarge {
+                entries,
+                cq_entries,
+                errno,
+            } => {
+                write!(f, "a ring of {} entries", entries)?;
+                if let Some(cq_entries) = cq_entries {
+                    write!(f, " and {} completion queue entries", cq_entries)?;
+                }
+                if *errno == libc::ENOMEM {
+                    write!(
+                        f,
+                        " needs more memory than the kernel could allocate, check the memory \
+                         cgroup limits or reduce the queue size"
+                    )
+                } else {
+                    write!(
+                        f,
+                        " is larger than the kernel allows, reduce the queue size or set the \
+                         ring up with `IoRingBuilder::clamp`"
+                    )
+                }
+            }
+            Error::MemlockLimit { limit } => write!(
+                f,
+                "the ring does not fit in the RLIMIT_MEMLOCK of {} bytes, raise it with \
+                 `ulimit -l` or `LimitMEMLOCK=` or use a smaller ring",
+                limit
+            ),
+            Error::PermissionDenied => write!(
+                f,
+                "io_uring is blocked for this process, check for seccomp filters (e.g. the \
+                 container runtime's default profile) and the kernel.io_uring_disabled sysctl"
+            ),
+            Error::IopollUnsupported(op) => write!(
+                f,
+                "{:?} can't be used on an IOPOLL ring, which only supports reads and writes",
+                op
+            ),
+            Error::CompletionQueueFull => write!(
+                f,
+                "the completion queue has no room for more submissions and this kernel drops \
+                 completions on overflow, reap completions before submitting"
+            ),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Io(e) => return e,
+            Error::Unsupported => io::ErrorKind::Unsupported,
+            Error::RingTooLarge {
+                errno: libc::ENOMEM,
+                ..
+            } => io::ErrorKind::OutOfMemory,
+            Error::RingTooLarge { .. } => io::ErrorKind::InvalidInput,
+            Error::MemlockLimit { .. } => io::ErrorKind::OutOfMemory,
+            Error::PermissionDenied => io::ErrorKind::PermissionDenied,
+            Error::IopollUnsupported(_) => io::ErrorKind::Unsupported,
+            Error::CompletionQueueFull => io::ErrorKind::WouldBlock,
+        };
+
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(flags: u8, cq_entries: u32) -> chakra_sys::io_uring_params {
+        let mut params = chakra_sys::io_uring_params::from(crate::IoRingParams::new(
+            crate::Flags::from_bits_truncate(u32::from(flags)),
+        ));
+        params.cq_entries = cq_entries;
+        params
+    }
+
+    fn map(res: i32, entries: u32, params: &chakra_sys::io_uring_params) -> Error {
+        Error::map_setup_errno(res, entries, params, Accounting::Memcg)
+    }
+
+    #[test]
+    fn passthrough() {
+        let err = map(-libc::ENOSYS, 8, &params(0, 0));
+        assert!(matches!(err, Error::Unsupported));
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSYS));
+
+        let err = map(-libc::EPERM, 8, &params(0, 0));
+        assert!(matches!(err, Error::PermissionDenied));
+
+        let err = map(-libc::EFAULT, 8, &params(0, 0));
+        assert_eq!(err.raw_os_error(), Some(libc::EFAULT));
+    }
+
+    #[test]
+    fn too_many_entries() {
+        let err = map(-libc::EINVAL, 65536, &params(0, 0));
+        assert!(matches!(
+            err,
+            Error::RingTooLarge {
+                entries: 65536,
+                cq_entries: None,
+                errno: libc::EINVAL,
+            }
+        ));
+        assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+
+        // Sizes the kernel accepts, or clamps, fail for some other reason
+        let err = map(-libc::EINVAL, 8, &params(0, 0));
+        assert!(matches!(err, Error::Io(_)));
+        let clamp = chakra_sys::IORING_SETUP_CLAMP;
+        let err = map(-libc::EINVAL, 65536, &params(clamp, 0));
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[test]
+    fn too_many_cq_entries() {
+        let cqsize = chakra_sys::IORING_SETUP_CQSIZE;
+
+        let err = map(-libc::EINVAL, 8, &params(cqsize, 1 << 20));
+        assert!(matches!(
+            err,
+            Error::RingTooLarge {
+                entries: 8,
+                cq_entries: Some(0x100000),
+                errno: libc::EINVAL,
+            }
+        ));
+
+        let err = map(-libc::EINVAL, 8, &params(cqsize, 4));
+        assert!(matches!(err, Error::Io(_)));
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let oom = |accounting| Error::map_setup_errno(-libc::ENOMEM, 8, &params(0, 0), accounting);
+
+        let err = oom(Accounting::Memlock(Some(65536)));
+        assert!(matches!(err, Error::MemlockLimit { limit: 65536 }));
+        assert_eq!(err.raw_os_error(), Some(libc::ENOMEM));
+
+        for accounting in [Accounting::Memlock(None), Accounting::Memcg] {
+            let err = oom(accounting);
+            assert!(matches!(
+                err,
+                Error::RingTooLarge {
+                    errno: libc::ENOMEM,
+                    ..
+                }
+            ));
+            assert_eq!(err.raw_os_error(), Some(libc::ENOMEM));
+        }
+    }
+
+    #[test]
+    fn raised_by_chakra() {
+        assert_eq!(Error::CompletionQueueFull.raw_os_error(), None);
+        let err = Error::IopollUnsupported(IoUringOp::IORING_OP_NOP);
+        assert_eq!(err.raw_os_error(), None);
+    }
+}
@@ -0,0 +1,265 @@
+//! Injecting failures into the operations of a ring, see `IoRing::faults`.
+//!
+//! Rules are checked against every SQE as it's published. A matching SQE is
+//! rewritten before the backend sees it, and its completion is rewritten as
+//! it's reaped. Completions are matched up with their SQE by `user_data`, so
+//! operations that are faulted have to have a `user_data` no other operation
+//! in flight uses.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    os::unix::io::RawFd,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use chakra_sys::IoUringOp;
+
+use crate::cqe::Cqe;
+
+/// What happens to an operation matched by a `FaultRule`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail with this errno, e.g. `libc::EIO`, without the operation being
+    /// executed. Operations linked after it are cancelled as if it had
+    /// failed by itself.
+    Errno(i32),
+    /// Transfer at most this many bytes, by shrinking the length of the SQE.
+    /// Only affects reads, writes, sends, receives, splices and tees.
+    Short(u32),
+    /// Hold the completion back for this long after it has been posted.
+    ///
+    /// Delayed completions don't signal a registered eventfd, they only show up
+    /// when reaping or waiting on the ring.
+    Delay(Duration),
+}
+
+/// Which operations to inject a `Fault` into
+pub struct FaultRule {
+    fault: Fault,
+    opcode: Option<IoUringOp>,
+    fd: Option<RawFd>,
+    user_data: Option<Box<dyn Fn(u64) -> bool + Send>>,
+    /// Matches left before the rule stops applying
+    times: Option<usize>,
+}
+
+impl FaultRule {
+    /// Inject `fault` into every operation, narrowed down by the other methods
+    pub fn new(fault: Fault) -> Self {
+        FaultRule {
+            fault,
+            opcode: None,
+            fd: None,
+            user_data: None,
+            times: None,
+        }
+    }
+
+    /// Only match operations with `opcode`
+    pub fn opcode(mut self, opcode: IoUringOp) -> Self {
+        self.opcode = Some(opcode);
+        self
+    }
+
+    /// Only match operations on `fd`, which is the index for operations on
+    /// registered files
+    pub fn fd(mut self, fd: RawFd) -> Self {
+        self.fd = Some(fd);
+        self
+    }
+
+    /// Only match operations whose `user_data` satisfies `predicate`
+    pub fn user_data<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(u64) -> bool + Send + 'static,
+    {
+        self.user_data = Some(Box::new(predicate));
+        self
+    }
+
+    /// Stop applying once `n` operations have been matched
+    pub fn times(mut self, n: usize) -> Self {
+        self.times = Some(n);
+        self
+    }
+
+    fn matches(&self, sqe: &chakra_sys::io_uring_sqe) -> bool {
+        self.times != Some(0)
+            && self.opcode.is_none_or(|op| op as u8 == sqe.opcode)
+            && self.fd.is_none_or(|fd| fd == sqe.fd)
+            && self
+                .user_data
+                .as_ref()
+                .is_none_or(|predicate| predicate(sqe.user_data))
+    }
+}
+
+impl fmt::Debug for FaultRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultRule")
+            .field("fault", &self.fault)
+            .field("opcode", &self.opcode)
+            .field("fd", &self.fd)
+            .field("user_data", &self.user_data.as_ref().map(|_| ".."))
+            .field("times", &self.times)
+            .finish()
+    }
+}
+
+/// The fault rules of a ring, and the faulted operations still in flight.
+#[derive(Debug, Default)]
+pub struct Faults {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Checked in order, the first match applies
+    rules: Vec<FaultRule>,
+    /// Faults to apply to completions, by `user_data`
+    pending: HashMap<u64, VecDeque<Fault>>,
+    /// Completions taken off the ring, with when they are due to be reaped
+    reaped: Vec<(Instant, Cqe)>,
+}
+
+impl Faults {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule, checked after the ones added before it
+    pub fn inject(&self, rule: FaultRule) {
+        self.lock().rules.push(rule);
+    }
+
+    /// Remove every rule. Operations already faulted still complete as such.
+    pub fn clear(&self) {
+        self.lock().rules.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Rewrite `sqe` as it's published, if a rule matches it
+    pub(crate) fn submit(&self, sqe: &mut chakra_sys::io_uring_sqe) {
+        let mut inner = self.lock();
+
+        let rule = match inner.rules.iter_mut().find(|rule| rule.matches(sqe)) {
+            Some(rule) => rule,
+            None => return,
+        };
+        if let Some(times) = &mut rule.times {
+            *times -= 1;
+        }
+
+        let fault = rule.fault;
+        match fault {
+            Fault::Errno(_) => {
+                // A read on no file at all fails on the spot, breaking any link
+                // like the injected error would. Its -EBADF is swapped out on
+                // completion.
+                sqe.opcode = IoUringOp::IORING_OP_READ as u8;
+                sqe.flags &= chakra_sys::IOSQE_IO_LINK | chakra_sys::IOSQE_IO_HARDLINK;
+                sqe.fd = -1;
+                sqe.len = 0;
+            }
+            Fault::Short(max) => {
+                if is_transfer(sqe.opcode) {
+                    sqe.len = sqe.len.min(max);
+                }
+                return;
+            }
+            Fault::Delay(_) => {}
+        }
+
+        inner
+            .pending
+            .entry(sqe.user_data)
+            .or_default()
+            .push_back(fault);
+    }
+
+    /// Take `cqe` off the ring, rewriting it and holding it back as its
+    /// faults say
+    pub(crate) fn complete(&self, cqe: Cqe) {
+        let mut inner = self.lock();
+        let mut due = Instant::now();
+
+        let user_data = cqe.user_data();
+        let fault = match inner.pending.get_mut(&user_data) {
+            Some(faults) => faults.pop_front(),
+            None => None,
+        };
+        if inner
+            .pending
+            .get(&user_data)
+            .is_some_and(VecDeque::is_empty)
+        {
+            inner.pending.remove(&user_data);
+        }
+
+        let cqe = match fault {
+            Some(Fault::Errno(errno)) if cqe.raw_result() == -libc::EBADF => {
+                cqe.with_result(-errno)
+            }
+            Some(Fault::Delay(delay)) => {
+                due += delay;
+                cqe
+            }
+            _ => cqe,
+        };
+
+        inner.reaped.push((due, cqe));
+    }
+
+    /// Take the oldest completion that is due
+    pub(crate) fn take_due(&self) -> Option<Cqe> {
+        let mut inner = self.lock();
+        let now = Instant::now();
+
+        let index = inner.reaped.iter().position(|&(due, _)| due <= now)?;
+        Some(inner.reaped.remove(index).1)
+    }
+
+    /// Number of completions that are due
+    pub(crate) fn due(&self) -> u32 {
+        let now = Instant::now();
+        self.lock()
+            .reaped
+            .iter()
+            .filter(|&&(due, _)| due <= now)
+            .count() as u32
+    }
+
+    /// When the next delayed completion that isn't due yet will be
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.lock()
+            .reaped
+            .iter()
+            .map(|&(due, _)| due)
+            .filter(|&due| due > now)
+            .min()
+    }
+}
+
+/// Whether the `len` of an SQE with `opcode` is a number of bytes to transfer
+fn is_transfer(opcode: u8) -> bool {
+    use IoUringOp::*;
+
+    [
+        IORING_OP_READ,
+        IORING_OP_WRITE,
+        IORING_OP_READ_FIXED,
+        IORING_OP_WRITE_FIXED,
+        IORING_OP_SEND,
+        IORING_OP_RECV,
+        IORING_OP_SPLICE,
+        IORING_OP_TEE,
+    ]
+    .iter()
+    .any(|&op| op as u8 == opcode)
+}
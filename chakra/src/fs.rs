@@ -0,0 +1,453 @@
+//! Files whose operations are submitted to the current driver.
+//!
+//! Reads and writes take ownership of their buffers and hand them back along
+//! with the result, see `rt::IoBuf`.
+
+use std::{
+    cell::Cell,
+    convert::TryFrom,
+    ffi::{CStr, CString},
+    fmt, io, mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, IntoRawFd, RawFd},
+    },
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    rt::{self, Driver, IoBuf, IoBufMut},
+    FeatureFlags, FsyncFlags,
+};
+
+/// A file open on the current driver.
+///
+/// Dropping the file closes it synchronously, `close` does so through the ring
+/// and reports errors.
+pub struct File {
+    fd: RawFd,
+    driver: Driver,
+    /// Cursor used by `read` and `write` on kernels without
+    /// `IORING_FEAT_RW_CUR_POS`, which otherwise use the file position
+    pos: Cell<Option<u64>>,
+}
+
+impl File {
+    /// Open the file at `path` read-only
+    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        OpenOptions::new().read(true).open(path).await
+    }
+
+    /// Open the file at `path` write-only, creating it if it doesn't exist and
+    /// truncating it if it does
+    pub async fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await
+    }
+
+    /// Wrap an open std file, on the current driver.
+    ///
+    /// `read` and `write` carry on from the file position, which is only
+    /// read once here on kernels without `IORING_FEAT_RW_CUR_POS`.
+    pub fn from_std(file: std::fs::File) -> File {
+        let file = File::from_fd(rt::current(), file.into_raw_fd());
+
+        if file.pos.get().is_some() {
+            let pos = unsafe { libc::lseek(file.fd, 0, libc::SEEK_CUR) };
+            // Fails on pipes and sockets, which ignore the offset anyway
+            file.pos.set(Some(u64::try_from(pos).unwrap_or(0)));
+        }
+
+        file
+    }
+
+    fn from_fd(driver: Driver, fd: RawFd) -> File {
+        let cur_pos = driver.with_ring(|ring| {
+            ring.params()
+                .features
+                .contains(FeatureFlags::IORING_FEAT_RW_CUR_POS)
+        });
+
+        File {
+            fd,
+            driver,
+            pos: Cell::new(if cur_pos { None } else { Some(0) }),
+        }
+    }
+
+    /// Read up to `buf.bytes_total()` bytes at `pos`, returning how many were read
+    pub async fn read_at<B: IoBufMut>(&self, buf: B, pos: u64) -> (io::Result<usize>, B) {
+        let (res, buf) = self.driver.read_at(self, buf, pos).await;
+        (res.map(|n| n as usize), buf)
+    }
+
+    /// Write the initialized bytes of `buf` at `pos`, returning how many were written
+    pub async fn write_at<B: IoBuf>(&self, buf: B, pos: u64) -> (io::Result<usize>, B) {
+        let (res, buf) = self.driver.write_at(self, buf, pos).await;
+        (res.map(|n| n as usize), buf)
+    }
+
+    /// Fill all of `buf.bytes_total()` with bytes read at `pos`, failing with
+    /// `UnexpectedEof` if the file ends first
+    pub async fn read_exact_at<B: IoBufMut>(&self, mut buf: B, pos: u64) -> (io::Result<()>, B) {
+        let total = buf.bytes_total();
+        let mut filled = 0;
+
+        while filled < total {
+            let (res, b) = self
+                .driver
+                .read_into(self, buf, filled, pos + filled as u64)
+                .await;
+            buf = b;
+
+            match res {
+                Ok(0) => return (Err(io::ErrorKind::UnexpectedEof.into()), buf),
+                Ok(n) => filled += n as usize,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (Err(e), buf),
+            }
+        }
+
+        (Ok(()), buf)
+    }
+
+    /// Write all of the initialized bytes of `buf` at `pos`
+    pub async fn write_all_at<B: IoBuf>(&self, mut buf: B, pos: u64) -> (io::Result<()>, B) {
+        let total = buf.bytes_init();
+        let mut written = 0;
+
+        while written < total {
+            let (res, b) = self
+                .driver
+                .write_from(self, buf, written, pos + written as u64)
+                .await;
+            buf = b;
+
+            match res {
+                Ok(0) => return (Err(io::ErrorKind::WriteZero.into()), buf),
+                Ok(n) => written += n as usize,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (Err(e), buf),
+            }
+        }
+
+        (Ok(()), buf)
+    }
+
+    /// Read at the cursor, advancing it by the number of bytes read
+    pub async fn read<B: IoBufMut>(&self, buf: B) -> (io::Result<usize>, B) {
+        let (res, buf) = self.read_at(buf, self.cursor()).await;
+        self.advance(&res);
+        (res, buf)
+    }
+
+    /// Write at the cursor, advancing it by the number of bytes written
+    pub async fn write<B: IoBuf>(&self, buf: B) -> (io::Result<usize>, B) {
+        let (res, buf) = self.write_at(buf, self.cursor()).await;
+        self.advance(&res);
+        (res, buf)
+    }
+
+    /// Offset for the cursor mode, where -1 has the kernel use the file position
+    fn cursor(&self) -> u64 {
+        self.pos.get().unwrap_or(u64::MAX)
+    }
+
+    fn advance(&self, res: &io::Result<usize>) {
+        if let (Some(pos), Ok(n)) = (self.pos.get(), res) {
+            self.pos.set(Some(pos + *n as u64));
+        }
+    }
+
+    /// Flush data and metadata to disk
+    pub async fn sync_all(&self) -> io::Result<()> {
+        self.fsync(FsyncFlags::empty()).await
+    }
+
+    /// Flush data to disk, along with only the metadata needed to read it back
+    pub async fn sync_data(&self) -> io::Result<()> {
+        self.fsync(FsyncFlags::IORING_FSYNC_DATASYNC).await
+    }
+
+    async fn fsync(&self, flags: FsyncFlags) -> io::Result<()> {
+        let (res, ()) = self
+            .driver
+            .submit_op((), |sqe, _| sqe.prep_fsync(self, flags))
+            .await;
+        res.map(drop)
+    }
+
+    /// Change the size of the file.
+    ///
+    /// Growing the file allocates the new blocks with fallocate(2). There is no
+    /// io_uring operation to shrink a file, so that's done with a blocking
+    /// ftruncate(2).
+    pub async fn set_len(&self, size: u64) -> io::Result<()> {
+        let len = self.metadata().await?.len();
+
+        if size > len {
+            let (res, ()) = self
+                .driver
+                .submit_op((), |sqe, _| sqe.prep_fallocate(self, 0, len, size - len))
+                .await;
+            res?;
+        } else if size < len && unsafe { libc::ftruncate(self.fd, size as libc::off_t) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Query the metadata of the file with statx(2)
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        let empty = CStr::from_bytes_with_nul(b"\0").unwrap();
+        let statx = Box::new(unsafe { mem::zeroed::<libc::statx>() });
+
+        let (res, statx) = self
+            .driver
+            .submit_op(statx, |sqe, statx| {
+                sqe.prep_statx(
+                    self.fd,
+                    empty,
+                    libc::AT_EMPTY_PATH,
+                    libc::STATX_BASIC_STATS | libc::STATX_BTIME,
+                    statx,
+                )
+            })
+            .await;
+        res?;
+
+        Ok(Metadata { statx: *statx })
+    }
+
+    /// Close the file through the ring, reporting any error
+    pub async fn close(mut self) -> io::Result<()> {
+        let fd = mem::replace(&mut self.fd, -1);
+
+        let (res, ()) = self.driver.submit_op((), |sqe, _| sqe.prep_close(fd)).await;
+        res.map(drop)
+    }
+}
+
+impl AsRawFd for File {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if self.fd >= 0 {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+/// Options for opening a file, mirroring `std::fs::OpenOptions`.
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    mode: u32,
+    custom_flags: i32,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            mode: 0o666,
+            custom_flags: 0,
+        }
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Write at the end of the file, implies `write`
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Create the file, failing if it already exists
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Permissions of a newly created file, before the umask is applied
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Extra flags passed to openat(2), like `O_DIRECT`
+    pub fn custom_flags(&mut self, flags: i32) -> &mut Self {
+        self.custom_flags = flags;
+        self
+    }
+
+    /// Open the file at `path` on the current driver
+    pub async fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let flags =
+            libc::O_CLOEXEC | self.access_mode()? | self.creation_mode()? | self.custom_flags;
+        let driver = rt::current();
+
+        let (res, _) = driver
+            .submit_op(path, |sqe, path| {
+                sqe.prep_openat(libc::AT_FDCWD, path, flags, self.mode)
+            })
+            .await;
+
+        Ok(File::from_fd(driver, res? as RawFd))
+    }
+
+    fn access_mode(&self) -> io::Result<i32> {
+        match (self.read, self.write, self.append) {
+            (true, false, false) => Ok(libc::O_RDONLY),
+            (false, true, false) => Ok(libc::O_WRONLY),
+            (true, true, false) => Ok(libc::O_RDWR),
+            (false, _, true) => Ok(libc::O_WRONLY | libc::O_APPEND),
+            (true, _, true) => Ok(libc::O_RDWR | libc::O_APPEND),
+            (false, false, false) => Err(io::ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    fn creation_mode(&self) -> io::Result<i32> {
+        if !self.write && !self.append && (self.truncate || self.create || self.create_new) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        if self.append && self.truncate && !self.create_new {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        Ok(match (self.create, self.truncate, self.create_new) {
+            (false, false, false) => 0,
+            (true, false, false) => libc::O_CREAT,
+            (false, true, false) => libc::O_TRUNC,
+            (true, true, false) => libc::O_CREAT | libc::O_TRUNC,
+            (_, _, true) => libc::O_CREAT | libc::O_EXCL,
+        })
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Metadata of a file, as returned by statx(2).
+#[derive(Clone, Copy)]
+pub struct Metadata {
+    statx: libc::statx,
+}
+
+impl Metadata {
+    /// Size of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.statx.stx_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == libc::S_IFREG
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == libc::S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == libc::S_IFLNK
+    }
+
+    /// Permission bits of the file
+    pub fn mode(&self) -> u32 {
+        u32::from(self.statx.stx_mode) & 0o7777
+    }
+
+    /// Preferred block size for I/O
+    pub fn blksize(&self) -> u32 {
+        self.statx.stx_blksize
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        system_time(&self.statx.stx_mtime)
+    }
+
+    pub fn accessed(&self) -> SystemTime {
+        system_time(&self.statx.stx_atime)
+    }
+
+    /// Creation time, if the filesystem records it
+    pub fn created(&self) -> Option<SystemTime> {
+        if self.statx.stx_mask & libc::STATX_BTIME == 0 {
+            return None;
+        }
+
+        Some(system_time(&self.statx.stx_btime))
+    }
+
+    fn file_type(&self) -> u32 {
+        u32::from(self.statx.stx_mode) & libc::S_IFMT
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metadata")
+            .field("len", &self.len())
+            .field("mode", &format_args!("{:o}", self.mode()))
+            .field("is_dir", &self.is_dir())
+            .field("modified", &self.modified())
+            .finish()
+    }
+}
+
+fn system_time(ts: &libc::statx_timestamp) -> SystemTime {
+    let secs = Duration::from_secs(ts.tv_sec.unsigned_abs());
+    let base = if ts.tv_sec >= 0 {
+        SystemTime::UNIX_EPOCH + secs
+    } else {
+        SystemTime::UNIX_EPOCH - secs
+    };
+
+    base + Duration::from_nanos(ts.tv_nsec.into())
+}
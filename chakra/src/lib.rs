@@ -1,4 +1,29 @@
+pub mod backend;
+pub mod compat;
+pub mod copy;
+mod cqe;
+mod error;
+mod fault;
+pub mod fs;
+pub mod net;
+mod probe;
+mod queue;
+pub mod record;
+mod restrict;
 mod ring;
+pub mod rt;
+pub mod runtime;
 mod sqe;
+mod stats;
+mod trace;
+pub use cqe::*;
+pub use error::*;
+pub use fault::*;
+pub use probe::*;
+pub use queue::{CompletionQueue, SubmissionQueue, Submitter};
+pub use restrict::*;
 pub use ring::*;
 pub use sqe::*;
+pub use stats::*;
+
+pub use chakra_sys::IoUringOp;
@@ -0,0 +1,14 @@
+//! Sockets whose operations are submitted to the current driver.
+//!
+//! Sockets are created, bound and set to listen with blocking syscalls, which
+//! never wait on the network. Accepting, connecting and the transfers go
+//! through the ring, taking ownership of their buffers like `fs::File` does.
+
+mod socket;
+mod tcp;
+mod udp;
+mod unix;
+
+pub use tcp::{TcpListener, TcpStream};
+pub use udp::UdpSocket;
+pub use unix::{UnixListener, UnixStream};
@@ -0,0 +1,366 @@
+use std::{
+    io,
+    mem::{self, ManuallyDrop},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6},
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+    path::Path,
+    ptr, slice,
+};
+
+use crate::rt::{self, Driver, IoBuf, IoBufMut};
+
+/// A socket on the current driver, shared by the TCP, UDP and Unix types.
+pub(crate) struct Socket {
+    fd: RawFd,
+    driver: Driver,
+}
+
+impl Socket {
+    /// Create a socket of `ty` in `domain`
+    pub(crate) fn new(domain: i32, ty: i32) -> io::Result<Socket> {
+        let fd = unsafe { libc::socket(domain, ty | libc::SOCK_CLOEXEC, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Socket::from_fd(fd))
+    }
+
+    /// Take ownership of the open socket `fd`, on the current driver
+    pub(crate) fn from_fd(fd: RawFd) -> Socket {
+        Socket {
+            fd,
+            driver: rt::current(),
+        }
+    }
+
+    /// Borrow the socket as a std type, for the queries that don't block
+    pub(crate) fn as_std<T: FromRawFd>(&self) -> ManuallyDrop<T> {
+        ManuallyDrop::new(unsafe { T::from_raw_fd(self.fd) })
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<(Socket, SockAddr)> {
+        let addr = Box::new(SockAddr::empty());
+
+        let (res, addr) = self
+            .driver
+            .submit_op(addr, |sqe, addr| {
+                sqe.prep_accept(self, &mut addr.storage, &mut addr.len, libc::SOCK_CLOEXEC)
+            })
+            .await;
+
+        Ok((
+            Socket {
+                fd: res? as RawFd,
+                driver: self.driver.clone(),
+            },
+            *addr,
+        ))
+    }
+
+    pub(crate) async fn connect(&self, addr: SockAddr) -> io::Result<()> {
+        let (res, _) = self
+            .driver
+            .submit_op(Box::new(addr), |sqe, addr| {
+                sqe.prep_connect(self, &addr.storage, addr.len)
+            })
+            .await;
+
+        res.map(drop)
+    }
+
+    pub(crate) async fn recv<B: IoBufMut>(&self, buf: B) -> (io::Result<usize>, B) {
+        let (res, mut buf) = self
+            .driver
+            .submit_op(buf, |sqe, buf| {
+                let buf =
+                    unsafe { slice::from_raw_parts_mut(buf.stable_mut_ptr(), buf.bytes_total()) };
+                sqe.prep_recv(self, buf, 0)
+            })
+            .await;
+
+        if let Ok(n) = res {
+            unsafe { buf.set_init(n as usize) };
+        }
+
+        (res.map(|n| n as usize), buf)
+    }
+
+    pub(crate) async fn send<B: IoBuf>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.send_from(buf, 0).await
+    }
+
+    /// Send all of the initialized bytes of `buf`
+    pub(crate) async fn send_all<B: IoBuf>(&self, mut buf: B) -> (io::Result<()>, B) {
+        let total = buf.bytes_init();
+        let mut sent = 0;
+
+        while sent < total {
+            let (res, b) = self.send_from(buf, sent).await;
+            buf = b;
+
+            match res {
+                Ok(0) => return (Err(io::ErrorKind::WriteZero.into()), buf),
+                Ok(n) => sent += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return (Err(e), buf),
+            }
+        }
+
+        (Ok(()), buf)
+    }
+
+    /// Send the initialized bytes of `buf` from byte `start` onwards
+    async fn send_from<B: IoBuf>(&self, buf: B, start: usize) -> (io::Result<usize>, B) {
+        let (res, buf) = self
+            .driver
+            .submit_op(buf, |sqe, buf| {
+                let len = buf.bytes_init() - start;
+                let buf = unsafe { slice::from_raw_parts(buf.stable_ptr().add(start), len) };
+                sqe.prep_send(self, buf, libc::MSG_NOSIGNAL)
+            })
+            .await;
+
+        (res.map(|n| n as usize), buf)
+    }
+
+    pub(crate) async fn send_to<B: IoBuf>(&self, buf: B, addr: SockAddr) -> (io::Result<usize>, B) {
+        let msg = Box::new(Msg::new(buf, addr));
+
+        let (res, msg) = self
+            .driver
+            .submit_op(msg, |sqe, msg| {
+                msg.iov.iov_base = msg.buf.stable_ptr() as *mut _;
+                msg.iov.iov_len = msg.buf.bytes_init();
+                sqe.prep_sendmsg(self, msg.hdr(), libc::MSG_NOSIGNAL)
+            })
+            .await;
+
+        (res.map(|n| n as usize), msg.buf)
+    }
+
+    pub(crate) async fn recv_from<B: IoBufMut>(
+        &self,
+        buf: B,
+    ) -> (io::Result<(usize, SockAddr)>, B) {
+        let msg = Box::new(Msg::new(buf, SockAddr::empty()));
+
+        let (res, mut msg) = self
+            .driver
+            .submit_op(msg, |sqe, msg| {
+                msg.iov.iov_base = msg.buf.stable_mut_ptr() as *mut _;
+                msg.iov.iov_len = msg.buf.bytes_total();
+                sqe.prep_recvmsg(self, msg.hdr(), 0)
+            })
+            .await;
+
+        let res = res.map(|n| {
+            unsafe { msg.buf.set_init(n as usize) };
+            msg.addr.len = msg.hdr.msg_namelen;
+            (n as usize, msg.addr)
+        });
+
+        (res, msg.buf)
+    }
+
+    pub(crate) async fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let (res, ()) = self
+            .driver
+            .submit_op((), |sqe, _| sqe.prep_shutdown(self, how))
+            .await;
+
+        res.map(drop)
+    }
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Everything a sendmsg(2) or recvmsg(2) points to, kept in one allocation so
+/// it stays in place while the operation is in flight.
+struct Msg<B> {
+    hdr: libc::msghdr,
+    iov: libc::iovec,
+    addr: SockAddr,
+    buf: B,
+}
+
+impl<B> Msg<B> {
+    fn new(buf: B, addr: SockAddr) -> Self {
+        Msg {
+            hdr: unsafe { mem::zeroed() },
+            iov: libc::iovec {
+                iov_base: ptr::null_mut(),
+                iov_len: 0,
+            },
+            addr,
+            buf,
+        }
+    }
+
+    /// Point the header at the address and the buffer
+    fn hdr(&mut self) -> &mut libc::msghdr {
+        self.hdr.msg_name = &mut self.addr.storage as *mut _ as *mut _;
+        self.hdr.msg_namelen = self.addr.len;
+        self.hdr.msg_iov = &mut self.iov;
+        self.hdr.msg_iovlen = 1;
+
+        &mut self.hdr
+    }
+}
+
+/// A socket address in the form the kernel takes it.
+#[derive(Clone, Copy)]
+pub(crate) struct SockAddr {
+    storage: libc::sockaddr_storage,
+    len: libc::socklen_t,
+}
+
+impl SockAddr {
+    /// An address to be filled in by the kernel
+    fn empty() -> Self {
+        SockAddr {
+            storage: unsafe { mem::zeroed() },
+            len: mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+        }
+    }
+
+    pub(crate) fn domain(&self) -> i32 {
+        i32::from(self.storage.ss_family)
+    }
+
+    pub(crate) fn from_inet(addr: &SocketAddr) -> Self {
+        let mut sockaddr = SockAddr::empty();
+        let storage = &mut sockaddr.storage as *mut libc::sockaddr_storage;
+
+        sockaddr.len = match addr {
+            SocketAddr::V4(addr) => {
+                let sin = unsafe { &mut *(storage as *mut libc::sockaddr_in) };
+                sin.sin_family = libc::AF_INET as libc::sa_family_t;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                let sin6 = unsafe { &mut *(storage as *mut libc::sockaddr_in6) };
+                sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_scope_id = addr.scope_id();
+
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        } as libc::socklen_t;
+
+        sockaddr
+    }
+
+    pub(crate) fn from_path(path: &Path) -> io::Result<Self> {
+        let mut sockaddr = SockAddr::empty();
+        let sun = unsafe { &mut *(&mut sockaddr.storage as *mut _ as *mut libc::sockaddr_un) };
+        let bytes = path.as_os_str().as_bytes();
+
+        // Leave room for the nul terminator
+        if bytes.len() >= sun.sun_path.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path is too long for a unix socket address",
+            ));
+        }
+
+        sun.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, src) in sun.sun_path.iter_mut().zip(bytes) {
+            *dst = *src as libc::c_char;
+        }
+        sockaddr.len = (mem::size_of::<libc::sa_family_t>() + bytes.len() + 1) as libc::socklen_t;
+
+        Ok(sockaddr)
+    }
+
+    pub(crate) fn as_inet(&self) -> io::Result<SocketAddr> {
+        let storage = &self.storage as *const libc::sockaddr_storage;
+
+        match self.domain() {
+            libc::AF_INET => {
+                let sin = unsafe { &*(storage as *const libc::sockaddr_in) };
+                Ok(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes()),
+                    u16::from_be(sin.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(storage as *const libc::sockaddr_in6) };
+                Ok(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an internet socket address",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inet_round_trip() {
+        let addrs = [
+            SocketAddr::from((Ipv4Addr::new(192, 168, 1, 2), 8080)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, 443)),
+            SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::new(0xfe80, 0, 0, 0, 1, 2, 3, 4),
+                65535,
+                0x12345,
+                3,
+            )),
+        ];
+
+        for addr in &addrs {
+            let sockaddr = SockAddr::from_inet(addr);
+            let (domain, len) = match addr {
+                SocketAddr::V4(_) => (libc::AF_INET, mem::size_of::<libc::sockaddr_in>()),
+                SocketAddr::V6(_) => (libc::AF_INET6, mem::size_of::<libc::sockaddr_in6>()),
+            };
+
+            assert_eq!(sockaddr.domain(), domain);
+            assert_eq!(sockaddr.len as usize, len);
+            assert_eq!(sockaddr.as_inet().unwrap(), *addr);
+        }
+    }
+
+    #[test]
+    fn path() {
+        let sockaddr = SockAddr::from_path(Path::new("/tmp/socket")).unwrap();
+        assert_eq!(sockaddr.domain(), libc::AF_UNIX);
+        // The family, the path and its nul terminator
+        assert_eq!(sockaddr.len, 2 + 11 + 1);
+        let err = sockaddr.as_inet().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let long = "x".repeat(108);
+        let err = SockAddr::from_path(Path::new(&long)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(SockAddr::from_path(Path::new(&long[1..])).is_ok());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    io,
+    net::{self, Shutdown, SocketAddr},
+    os::unix::io::{AsRawFd, IntoRawFd, RawFd},
+};
+
+use crate::rt::{IoBuf, IoBufMut};
+
+use super::socket::{SockAddr, Socket};
+
+/// A TCP socket listening for connections.
+pub struct TcpListener {
+    socket: Socket,
+}
+
+impl TcpListener {
+    /// Bind to `addr` and start listening, on the current driver
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self::from_std(net::TcpListener::bind(addr)?))
+    }
+
+    /// Take over a std listener, on the current driver
+    pub fn from_std(listener: net::TcpListener) -> Self {
+        TcpListener {
+            socket: Socket::from_fd(listener.into_raw_fd()),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.as_std::<net::TcpListener>().local_addr()
+    }
+
+    /// Wait for a connection, returning it along with the peer address
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (socket, addr) = self.socket.accept().await?;
+        Ok((TcpStream { socket }, addr.as_inet()?))
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// A TCP connection.
+pub struct TcpStream {
+    socket: Socket,
+}
+
+impl TcpStream {
+    /// Connect to `addr`, on the current driver
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let addr = SockAddr::from_inet(&addr);
+        let socket = Socket::new(addr.domain(), libc::SOCK_STREAM)?;
+
+        socket.connect(addr).await?;
+
+        Ok(TcpStream { socket })
+    }
+
+    /// Take over a connected std stream, on the current driver
+    pub fn from_std(stream: net::TcpStream) -> Self {
+        TcpStream {
+            socket: Socket::from_fd(stream.into_raw_fd()),
+        }
+    }
+
+    /// Read up to `buf.bytes_total()` bytes, returning how many were read.
+    /// Zero means the peer closed its end of the connection.
+    pub async fn read<B: IoBufMut>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.socket.recv(buf).await
+    }
+
+    /// Write the initialized bytes of `buf`, returning how many were written
+    pub async fn write<B: IoBuf>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.socket.send(buf).await
+    }
+
+    /// Write all of the initialized bytes of `buf`
+    pub async fn write_all<B: IoBuf>(&self, buf: B) -> (io::Result<()>, B) {
+        self.socket.send_all(buf).await
+    }
+
+    /// Shut down the read half, write half or both halves of the connection
+    pub async fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.socket.shutdown(how).await
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.as_std::<net::TcpStream>().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.as_std::<net::TcpStream>().peer_addr()
+    }
+
+    /// Set `TCP_NODELAY`, disabling Nagle's algorithm
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.socket.as_std::<net::TcpStream>().set_nodelay(nodelay)
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
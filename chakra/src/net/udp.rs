@@ -0,0 +1,68 @@
+use std::{
+    io,
+    net::{self, SocketAddr},
+    os::unix::io::{AsRawFd, IntoRawFd, RawFd},
+};
+
+use crate::rt::{IoBuf, IoBufMut};
+
+use super::socket::{SockAddr, Socket};
+
+/// A UDP socket.
+pub struct UdpSocket {
+    socket: Socket,
+}
+
+impl UdpSocket {
+    /// Bind to `addr`, on the current driver
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(Self::from_std(net::UdpSocket::bind(addr)?))
+    }
+
+    /// Take over a std socket, on the current driver
+    pub fn from_std(socket: net::UdpSocket) -> Self {
+        UdpSocket {
+            socket: Socket::from_fd(socket.into_raw_fd()),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.as_std::<net::UdpSocket>().local_addr()
+    }
+
+    /// Set the peer `send` and `recv` use and the only one datagrams are
+    /// received from
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<()> {
+        self.socket.connect(SockAddr::from_inet(&addr)).await
+    }
+
+    /// Send the initialized bytes of `buf` as one datagram to `addr`
+    pub async fn send_to<B: IoBuf>(&self, buf: B, addr: SocketAddr) -> (io::Result<usize>, B) {
+        self.socket.send_to(buf, SockAddr::from_inet(&addr)).await
+    }
+
+    /// Receive one datagram into `buf`, returning its size and sender. Bytes
+    /// past `buf.bytes_total()` are discarded.
+    pub async fn recv_from<B: IoBufMut>(&self, buf: B) -> (io::Result<(usize, SocketAddr)>, B) {
+        let (res, buf) = self.socket.recv_from(buf).await;
+        let res = res.and_then(|(n, addr)| Ok((n, addr.as_inet()?)));
+
+        (res, buf)
+    }
+
+    /// Send the initialized bytes of `buf` as one datagram to the connected peer
+    pub async fn send<B: IoBuf>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.socket.send(buf).await
+    }
+
+    /// Receive one datagram from the connected peer into `buf`
+    pub async fn recv<B: IoBufMut>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.socket.recv(buf).await
+    }
+}
+
+impl AsRawFd for UdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
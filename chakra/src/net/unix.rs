@@ -0,0 +1,103 @@
+use std::{
+    io,
+    net::Shutdown,
+    os::unix::{
+        io::{AsRawFd, IntoRawFd, RawFd},
+        net,
+    },
+    path::Path,
+};
+
+use crate::rt::{IoBuf, IoBufMut};
+
+use super::socket::{SockAddr, Socket};
+
+/// A Unix domain socket listening for connections.
+pub struct UnixListener {
+    socket: Socket,
+}
+
+impl UnixListener {
+    /// Bind to `path` and start listening, on the current driver
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::from_std(net::UnixListener::bind(path)?))
+    }
+
+    /// Take over a std listener, on the current driver
+    pub fn from_std(listener: net::UnixListener) -> Self {
+        UnixListener {
+            socket: Socket::from_fd(listener.into_raw_fd()),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.socket.as_std::<net::UnixListener>().local_addr()
+    }
+
+    /// Wait for a connection
+    pub async fn accept(&self) -> io::Result<UnixStream> {
+        let (socket, _) = self.socket.accept().await?;
+        Ok(UnixStream { socket })
+    }
+}
+
+impl AsRawFd for UnixListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// A connected Unix domain stream socket.
+pub struct UnixStream {
+    socket: Socket,
+}
+
+impl UnixStream {
+    /// Connect to the socket bound to `path`, on the current driver
+    pub async fn connect<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let addr = SockAddr::from_path(path.as_ref())?;
+        let socket = Socket::new(libc::AF_UNIX, libc::SOCK_STREAM)?;
+
+        socket.connect(addr).await?;
+
+        Ok(UnixStream { socket })
+    }
+
+    /// Take over a connected std stream, on the current driver
+    pub fn from_std(stream: net::UnixStream) -> Self {
+        UnixStream {
+            socket: Socket::from_fd(stream.into_raw_fd()),
+        }
+    }
+
+    /// Read up to `buf.bytes_total()` bytes, returning how many were read.
+    /// Zero means the peer closed its end of the connection.
+    pub async fn read<B: IoBufMut>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.socket.recv(buf).await
+    }
+
+    /// Write the initialized bytes of `buf`, returning how many were written
+    pub async fn write<B: IoBuf>(&self, buf: B) -> (io::Result<usize>, B) {
+        self.socket.send(buf).await
+    }
+
+    /// Write all of the initialized bytes of `buf`
+    pub async fn write_all<B: IoBuf>(&self, buf: B) -> (io::Result<()>, B) {
+        self.socket.send_all(buf).await
+    }
+
+    /// Shut down the read half, write half or both halves of the connection
+    pub async fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.socket.shutdown(how).await
+    }
+
+    pub fn peer_addr(&self) -> io::Result<net::SocketAddr> {
+        self.socket.as_std::<net::UnixStream>().peer_addr()
+    }
+}
+
+impl AsRawFd for UnixStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
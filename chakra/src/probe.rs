@@ -0,0 +1,78 @@
+use std::{mem, slice};
+
+use chakra_sys::{io_uring_probe, io_uring_probe_op, IoUringOp};
+
+use crate::{IoRing, Result};
+
+/// Room for every opcode that fits in the u8 `opcode` field of an SQE
+const PROBE_OPS: usize = 256;
+
+/// The opcodes supported by the running kernel, see `IoRing::probe`.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    last_op: u8,
+    /// `IO_URING_OP_SUPPORTED` and friends, indexed by opcode
+    flags: Vec<u16>,
+}
+
+impl Probe {
+    /// The highest opcode the kernel knows about, which may be beyond the ones
+    /// in `IoUringOp`
+    pub fn last_op(&self) -> u8 {
+        self.last_op
+    }
+
+    pub fn is_supported(&self, op: IoUringOp) -> bool {
+        self.flags
+            .get(op as usize)
+            .is_some_and(|&flags| u32::from(flags) & chakra_sys::IO_URING_OP_SUPPORTED != 0)
+    }
+
+    /// Iterate over the opcodes in `IoUringOp` the kernel supports
+    pub fn supported(&self) -> impl Iterator<Item = IoUringOp> + '_ {
+        IoUringOp::ALL
+            .iter()
+            .copied()
+            .filter(move |&op| self.is_supported(op))
+    }
+}
+
+impl IoRing {
+    /// Ask the kernel which opcodes it supports, with `IORING_REGISTER_PROBE`.
+    ///
+    /// Kernels before 5.6 can't be probed and fail with `EINVAL`.
+    pub fn probe(&self) -> Result<Probe> {
+        // io_uring_probe ends in a flexible array of ops, allocate them along
+        // with it as u64s to keep the header aligned
+        let size =
+            mem::size_of::<io_uring_probe>() + PROBE_OPS * mem::size_of::<io_uring_probe_op>();
+        let mut buf = vec![0u64; size.div_ceil(8)];
+
+        unsafe {
+            self.register(
+                chakra_sys::IORING_REGISTER_PROBE,
+                buf.as_mut_ptr() as *const _,
+                PROBE_OPS,
+            )?
+        };
+
+        let probe = unsafe { &*(buf.as_ptr() as *const io_uring_probe) };
+        let ops = unsafe {
+            let first = (buf.as_ptr() as *const u8).add(mem::size_of::<io_uring_probe>());
+            slice::from_raw_parts(
+                first as *const io_uring_probe_op,
+                usize::from(probe.ops_len),
+            )
+        };
+
+        let mut flags = vec![0; PROBE_OPS];
+        for op in ops {
+            flags[usize::from(op.op)] = op.flags;
+        }
+
+        Ok(Probe {
+            last_op: probe.last_op,
+            flags,
+        })
+    }
+}
@@ -0,0 +1,627 @@
+//! Independent halves of an `IoRing`, see `IoRing::split`.
+//!
+//! The submission and completion queues are single producer, single consumer
+//! rings shared with the kernel. Each side owns one index and only reads the
+//! other:
+//!
+//! * The SQ tail is written by us and read by the kernel. SQEs and the index
+//!   array are filled in first and the tail is published with a `Release`
+//!   store, so the kernel never sees a slot before its contents. The SQ head
+//!   is written by the kernel and read with `Acquire`, after which the slots
+//!   it has moved past can be reused.
+//! * The CQ tail is written by the kernel and read with `Acquire`, making the
+//!   CQEs before it visible. Once they have been copied out the CQ head is
+//!   published with a `Release` store, handing the slots back to the kernel.
+//!
+//! Each index has a single writer, which is what lets the halves live on
+//! different threads: the `SubmissionQueue` is the only writer of the SQ tail
+//! and the `CompletionQueue` the only writer of the CQ head.
+
+use std::{
+    cmp,
+    sync::atomic::{self, AtomicU32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use chakra_sys::IoUringOp;
+
+use crate::{
+    backend::Backend,
+    cqe::Cqe,
+    fault::Faults,
+    record::Recorder,
+    ring::{FeatureFlags, Flags, IoRingParams},
+    sqe::Sqe,
+    stats::StatsCollector,
+    trace::{self, Tracer},
+    Result,
+};
+
+/// How often waits poll for completions while a delayed one is pending
+const DELAY_POLL: Duration = Duration::from_millis(1);
+
+/// What else gets to see the SQEs and CQEs of a ring as they go through the
+/// queues
+#[derive(Default)]
+pub(crate) struct Hooks {
+    /// See `IoRing::faults`
+    pub(crate) faults: Option<Faults>,
+    /// See `IoRing::record`
+    pub(crate) recorder: Option<Recorder>,
+    pub(crate) tracer: Tracer,
+    /// See `IoRing::stats`
+    pub(crate) stats: StatsCollector,
+}
+
+// The halves of a split ring share the hooks, and the manual `Send` impls
+// below rely on that being sound.
+const _: fn() = || {
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<Hooks>();
+};
+
+/// The submitting half of a split `IoRing`, handing out SQEs and publishing
+/// them to the kernel.
+pub struct SubmissionQueue<'a> {
+    sq: &'a mut chakra_sys::io_uring_sq,
+    params: &'a IoRingParams,
+    in_flight: &'a AtomicU32,
+    /// The kernel's count of dropped SQEs, as of the last time they were
+    /// taken off `in_flight`
+    dropped: &'a mut u32,
+    hooks: &'a Hooks,
+    /// The SQ head as last read from the kernel, only refreshed once the
+    /// queue looks full
+    head: u32,
+}
+
+// SAFETY: Only `sq` keeps this from being `Send`, as `io_uring_sq` holds raw
+// pointers. They all point into the ring mapping, which outlives `'a`, and the
+// indices this half writes have no other writer while it exists. `hooks` is
+// shared with the other halves, and asserted to be `Sync` above.
+unsafe impl Send for SubmissionQueue<'_> {}
+
+impl<'a> SubmissionQueue<'a> {
+    pub(crate) fn new(
+        sq: &'a mut chakra_sys::io_uring_sq,
+        params: &'a IoRingParams,
+        in_flight: &'a AtomicU32,
+        dropped: &'a mut u32,
+        hooks: &'a Hooks,
+    ) -> Self {
+        let head = unsafe { as_atomic(sq.khead) }.load(Ordering::Acquire);
+
+        SubmissionQueue {
+            sq,
+            params,
+            in_flight,
+            dropped,
+            hooks,
+            head,
+        }
+    }
+
+    /// Get the next free SQE, or `None` if the submission queue is full.
+    ///
+    /// The SQE is only seen by the kernel once `sync` has been called, so any
+    /// number of SQEs can be prepared and then published together.
+    pub fn get_sqe(&mut self) -> Option<Sqe> {
+        let next = self.sq.sqe_tail.wrapping_add(1);
+
+        if next.wrapping_sub(self.head) > self.entries() {
+            self.head = unsafe { as_atomic(self.sq.khead) }.load(Ordering::Acquire);
+
+            if next.wrapping_sub(self.head) > self.entries() {
+                self.hooks.stats.sq_full();
+                return None;
+            }
+        }
+
+        let slot = self.sq.sqe_tail & self.mask();
+        let sqe = unsafe { self.sq.io_uring_sqe.add(slot as usize) };
+        self.sq.sqe_tail = next;
+        self.hooks.tracer.prepare(slot);
+
+        Sqe::new(sqe, self.params.flags.contains(Flags::IORING_SETUP_IOPOLL))
+    }
+
+    /// Number of SQEs handed out by `get_sqe` that haven't been synced yet
+    pub fn pending(&self) -> u32 {
+        self.sq.sqe_tail.wrapping_sub(self.sq.sqe_head)
+    }
+
+    /// Number of SQEs that can still be handed out by `get_sqe`
+    pub fn space_left(&mut self) -> u32 {
+        self.head = unsafe { as_atomic(self.sq.khead) }.load(Ordering::Acquire);
+        self.entries() - self.sq.sqe_tail.wrapping_sub(self.head)
+    }
+
+    /// Publish the SQEs handed out by `get_sqe` to the kernel with a single
+    /// store of the SQ tail, returning how many entries the kernel has yet to
+    /// consume.
+    ///
+    /// Without `IORING_FEAT_NODROP`, SQEs that could overflow the completion
+    /// queue are held back. If the kernel lacks `IORING_FEAT_SQPOLL_NONFIXED`,
+    /// SQPOLL rings can only be used with registered files, and pending SQEs
+    /// referring to any other file are rewritten to complete with `EBADF`.
+    pub fn sync(&mut self) -> Result<u32> {
+        self.publish();
+
+        let tail = unsafe { as_atomic(self.sq.ktail) }.load(Ordering::Relaxed);
+        self.head = unsafe { as_atomic(self.sq.khead) }.load(Ordering::Acquire);
+        Ok(tail.wrapping_sub(self.head))
+    }
+
+    /// Publish the SQEs `sync` would, returning how many that is
+    pub(crate) fn publish(&mut self) -> u32 {
+        self.check_fixed_files();
+        self.forget_dropped();
+        let to_submit = self.publishable();
+
+        let sq = &mut *self.sq;
+        let mask = unsafe { *sq.kring_mask };
+        let ktail = unsafe { as_atomic(sq.ktail) };
+        // Only this half ever writes the tail, so there is nothing to
+        // synchronize with when reading it.
+        let mut tail = ktail.load(Ordering::Relaxed);
+
+        for _ in 0..to_submit {
+            let slot = sq.sqe_head & mask;
+            let sqe = unsafe { &mut *sq.io_uring_sqe.add(slot as usize) };
+            if let Some(recorder) = &self.hooks.recorder {
+                recorder.submit(sqe);
+            }
+            self.hooks.tracer.submit(slot, sqe);
+            self.hooks.stats.submit(sqe);
+            if let Some(faults) = &self.hooks.faults {
+                faults.submit(sqe);
+            }
+            unsafe { *sq.array.add((tail & mask) as usize) = sq.sqe_head & mask };
+            tail = tail.wrapping_add(1);
+            sq.sqe_head = sq.sqe_head.wrapping_add(1);
+        }
+
+        // Makes the array and SQE writes visible before the new tail.
+        ktail.store(tail, Ordering::Release);
+        self.in_flight.fetch_add(to_submit, Ordering::Relaxed);
+
+        to_submit
+    }
+
+    /// Number of pending SQEs `sync` would publish right now. Without
+    /// `IORING_FEAT_NODROP` that's only as many as there is room for in the
+    /// completion queue.
+    pub(crate) fn publishable(&self) -> u32 {
+        let pending = self.pending();
+
+        if self
+            .params
+            .features
+            .contains(FeatureFlags::IORING_FEAT_NODROP)
+        {
+            pending
+        } else {
+            let in_flight = self.in_flight.load(Ordering::Relaxed);
+            cmp::min(pending, self.params.cq_entries.saturating_sub(in_flight))
+        }
+    }
+
+    /// Take the SQEs the kernel dropped as invalid since the last call off
+    /// `in_flight`, they never complete.
+    fn forget_dropped(&mut self) {
+        let dropped = unsafe { as_atomic(self.sq.kdropped) }.load(Ordering::Relaxed);
+        let newly = dropped.wrapping_sub(*self.dropped);
+
+        if newly > 0 {
+            self.in_flight
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    Some(n.saturating_sub(newly))
+                })
+                .ok();
+            *self.dropped = dropped;
+        }
+    }
+
+    /// Reject pending SQEs using unregistered files when the kernel can't
+    /// handle them on an SQPOLL ring.
+    ///
+    /// Each one is turned into a read from the registered file at index -1,
+    /// which the kernel fails with `EBADF` before looking at anything else,
+    /// so that it still completes with its own `user_data` and breaks its
+    /// link chain. The other SQEs are left alone.
+    fn check_fixed_files(&mut self) {
+        if !self.params.flags.contains(Flags::IORING_SETUP_SQPOLL)
+            || self
+                .params
+                .features
+                .contains(FeatureFlags::IORING_FEAT_SQPOLL_NONFIXED)
+        {
+            return;
+        }
+
+        let mask = self.mask();
+        let mut index = self.sq.sqe_head;
+
+        while index != self.sq.sqe_tail {
+            let sqe = unsafe { &mut *self.sq.io_uring_sqe.add((index & mask) as usize) };
+
+            if takes_file(sqe.opcode)
+                && sqe.fd >= 0
+                && sqe.flags & chakra_sys::IOSQE_FIXED_FILE == 0
+            {
+                sqe.opcode = IoUringOp::IORING_OP_READV as u8;
+                sqe.flags =
+                    (sqe.flags & !chakra_sys::IOSQE_BUFFER_SELECT) | chakra_sys::IOSQE_FIXED_FILE;
+                sqe.fd = -1;
+                sqe.addr_off.addr = 0;
+                sqe.len = 0;
+            }
+
+            index = index.wrapping_add(1);
+        }
+    }
+
+    fn entries(&self) -> u32 {
+        unsafe { *self.sq.kring_entries }
+    }
+
+    fn mask(&self) -> u32 {
+        unsafe { *self.sq.kring_mask }
+    }
+}
+
+/// The half of a split `IoRing` that enters the kernel to get published SQEs
+/// consumed and to wait for completions.
+pub struct Submitter<'a> {
+    backend: &'a dyn Backend,
+    params: &'a IoRingParams,
+    hooks: &'a Hooks,
+    khead: &'a AtomicU32,
+    ktail: &'a AtomicU32,
+    kflags: &'a AtomicU32,
+    entries: u32,
+}
+
+impl<'a> Submitter<'a> {
+    pub(crate) fn new(
+        backend: &'a dyn Backend,
+        sq: &chakra_sys::io_uring_sq,
+        params: &'a IoRingParams,
+        hooks: &'a Hooks,
+    ) -> Self {
+        unsafe {
+            Submitter {
+                backend,
+                params,
+                hooks,
+                khead: as_atomic(sq.khead),
+                ktail: as_atomic(sq.ktail),
+                kflags: as_atomic(sq.kflags),
+                entries: *sq.kring_entries,
+            }
+        }
+    }
+
+    /// Have the kernel consume the SQEs published by `SubmissionQueue::sync`,
+    /// returning the number submitted
+    pub fn submit(&self) -> Result<usize> {
+        self.submit_and_wait(0)
+    }
+
+    /// Have the kernel consume the SQEs published by `SubmissionQueue::sync`
+    /// and block until at least `wait_nr` completions are available
+    ///
+    /// On `IORING_SETUP_SQPOLL` rings the kernel thread picks up new entries by
+    /// itself, so the kernel is only entered to wake it up once it has gone idle,
+    /// or to wait for completions.
+    pub fn submit_and_wait(&self, wait_nr: u32) -> Result<usize> {
+        let to_submit = self.unconsumed();
+        let mut flags = 0;
+
+        if self.sq_needs_enter(&mut flags) || wait_nr > 0 {
+            if wait_nr > 0 || self.params.flags.contains(Flags::IORING_SETUP_IOPOLL) {
+                flags |= chakra_sys::IORING_ENTER_GETEVENTS;
+            }
+
+            self.hooks.stats.syscall();
+            let submitted = self.backend.enter(to_submit, wait_nr, flags)?;
+            trace::submitted(to_submit, submitted, wait_nr);
+            Ok(submitted)
+        } else {
+            Ok(to_submit as usize)
+        }
+    }
+
+    /// Block until the SQPOLL thread has consumed entries from a full
+    /// submission queue.
+    ///
+    /// Returns immediately if the ring isn't in SQPOLL mode or there is room.
+    pub fn sq_wait(&self) -> Result<()> {
+        if self.params.flags.contains(Flags::IORING_SETUP_SQPOLL)
+            && self.unconsumed() >= self.entries
+        {
+            self.hooks.stats.syscall();
+            self.backend.enter(0, 0, chakra_sys::IORING_ENTER_SQ_WAIT)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of published SQEs the kernel hasn't consumed yet
+    fn unconsumed(&self) -> u32 {
+        // The tail may have been published from another thread.
+        let tail = self.ktail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.khead.load(Ordering::Acquire))
+    }
+
+    /// Whether the kernel has to be entered for published SQEs to be picked
+    /// up, adding `IORING_ENTER_SQ_WAKEUP` to `flags` if the SQPOLL thread
+    /// needs waking.
+    fn sq_needs_enter(&self, flags: &mut u32) -> bool {
+        if !self.params.flags.contains(Flags::IORING_SETUP_SQPOLL) {
+            return true;
+        }
+
+        // The tail store has to be ordered before reading the flags, otherwise
+        // we could miss the thread going to sleep before it saw the new entries.
+        atomic::fence(Ordering::SeqCst);
+
+        if self.kflags.load(Ordering::Relaxed) & chakra_sys::IORING_SQ_NEED_WAKEUP != 0 {
+            *flags |= chakra_sys::IORING_ENTER_SQ_WAKEUP;
+            trace::sqpoll_wakeup();
+            return true;
+        }
+
+        false
+    }
+}
+
+/// The reaping half of a split `IoRing`, iterating over the completions that
+/// are ready.
+///
+/// Reaped completions are handed back to the kernel in batches, with a single
+/// store of the CQ head on `sync` or when the queue is dropped.
+pub struct CompletionQueue<'a> {
+    cq: &'a chakra_sys::io_uring_cq,
+    backend: &'a dyn Backend,
+    params: &'a IoRingParams,
+    sq_flags: &'a AtomicU32,
+    in_flight: &'a AtomicU32,
+    hooks: &'a Hooks,
+    /// Local copy of the head, ahead of the kernel's until the next `sync`
+    head: u32,
+    /// The head as last published to the kernel
+    published: u32,
+    /// The tail as last read from the kernel
+    tail: u32,
+}
+
+// SAFETY: Only `cq` keeps this from being `Send`, as `io_uring_cq` holds raw
+// pointers. They all point into the ring mapping, which outlives `'a`, and the
+// CQ head has no other writer while this half exists. `hooks` is shared with
+// the other halves, and asserted to be `Sync` above.
+unsafe impl Send for CompletionQueue<'_> {}
+
+impl<'a> CompletionQueue<'a> {
+    pub(crate) fn new(
+        cq: &'a chakra_sys::io_uring_cq,
+        backend: &'a dyn Backend,
+        sq_flags: &'a AtomicU32,
+        params: &'a IoRingParams,
+        in_flight: &'a AtomicU32,
+        hooks: &'a Hooks,
+    ) -> Self {
+        let (head, tail) = unsafe {
+            (
+                as_atomic(cq.khead).load(Ordering::Relaxed),
+                as_atomic(cq.ktail).load(Ordering::Acquire),
+            )
+        };
+
+        CompletionQueue {
+            cq,
+            backend,
+            params,
+            sq_flags,
+            in_flight,
+            hooks,
+            head,
+            published: head,
+            tail,
+        }
+    }
+
+    /// Number of completions waiting to be reaped
+    pub fn ready(&self) -> u32 {
+        let tail = unsafe { as_atomic(self.cq.ktail) }.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head) + self.hooks.faults.as_ref().map_or(0, Faults::due)
+    }
+
+    /// Number of completions the kernel had to drop because the completion
+    /// queue was full.
+    ///
+    /// This stays at zero on kernels with `IORING_FEAT_NODROP` unless the
+    /// kernel runs out of memory buffering the overflow.
+    pub fn dropped(&self) -> u32 {
+        unsafe { as_atomic(self.cq.koverflow) }.load(Ordering::Relaxed)
+    }
+
+    /// Whether the kernel is holding on to completions that didn't fit in the
+    /// completion queue
+    pub fn overflow_pending(&self) -> bool {
+        self.sq_flags.load(Ordering::Relaxed) & chakra_sys::IORING_SQ_CQ_OVERFLOW != 0
+    }
+
+    /// Block until at least `want` completions are ready to be reaped.
+    ///
+    /// This only waits, any SQEs still to be submitted are left to the `Submitter`.
+    pub fn wait(&mut self, want: u32) -> Result<()> {
+        // Reaped slots have to be handed back first, or the kernel may be
+        // waiting on them to post the completions we are about to wait for.
+        self.sync();
+        self.drain();
+
+        if self.overflow_pending() {
+            trace::cq_overflow(self.dropped());
+            self.hooks.stats.cq_overflow();
+        }
+
+        // Completions on IOPOLL rings are never posted from an interrupt, they
+        // only show up once io_uring_enter has polled the device for them.
+        // Likewise, completions that overflowed are only moved back into the
+        // queue when entering the kernel.
+        while self.ready() < want
+            || (self.ready() == 0 && (self.is_iopoll() || self.overflow_pending()))
+        {
+            if let Some(due) = self.delayed_until().filter(|_| want > 0) {
+                // Delayed completions turn up without the kernel's help, so it
+                // can only be polled until the next one is due
+                self.hooks.stats.syscall();
+                self.backend
+                    .enter(0, 0, chakra_sys::IORING_ENTER_GETEVENTS)?;
+                thread::sleep(cmp::min(
+                    due.saturating_duration_since(Instant::now()),
+                    DELAY_POLL,
+                ));
+                self.drain();
+                continue;
+            }
+
+            // Completions already taken off the ring don't count for the kernel
+            let min_complete = want - self.hooks.faults.as_ref().map_or(0, Faults::due);
+            self.hooks.stats.syscall();
+            self.backend
+                .enter(0, min_complete, chakra_sys::IORING_ENTER_GETEVENTS)?;
+            self.drain();
+
+            if want == 0 {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hand the completions reaped so far back to the kernel and pick up any
+    /// new ones it has posted.
+    pub fn sync(&mut self) {
+        if self.head != self.published {
+            // The copies out of the reaped slots have to be ordered before
+            // handing them back.
+            unsafe { as_atomic(self.cq.khead) }.store(self.head, Ordering::Release);
+
+            let reaped = self.head.wrapping_sub(self.published);
+            self.in_flight
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    Some(n.saturating_sub(reaped))
+                })
+                .ok();
+            self.published = self.head;
+        }
+
+        self.tail = unsafe { as_atomic(self.cq.ktail) }.load(Ordering::Acquire);
+    }
+
+    /// When the next completion held back by `Fault::Delay` is due, if any
+    pub(crate) fn delayed_until(&self) -> Option<Instant> {
+        self.hooks.faults.as_ref().and_then(Faults::next_due)
+    }
+
+    fn is_iopoll(&self) -> bool {
+        self.params.flags.contains(Flags::IORING_SETUP_IOPOLL)
+    }
+}
+
+impl CompletionQueue<'_> {
+    /// Take every CQE off the ring and hand it to the `Faults`, which decide
+    /// when it can be reaped
+    fn drain(&mut self) {
+        if let Some(faults) = &self.hooks.faults {
+            while let Some(cqe) = self.next_raw() {
+                faults.complete(cqe);
+            }
+            self.sync();
+        }
+    }
+
+    /// Copy the next CQE out of the ring
+    fn next_raw(&mut self) -> Option<Cqe> {
+        if self.head == self.tail {
+            self.tail = unsafe { as_atomic(self.cq.ktail) }.load(Ordering::Acquire);
+
+            if self.head == self.tail {
+                return None;
+            }
+        }
+
+        let cqe = unsafe {
+            let index = self.head & *self.cq.kring_mask;
+            Cqe::from_raw(&*self.cq.io_uring_cqe.add(index as usize))
+        };
+        self.head = self.head.wrapping_add(1);
+
+        Some(cqe)
+    }
+}
+
+impl Iterator for CompletionQueue<'_> {
+    type Item = Cqe;
+
+    fn next(&mut self) -> Option<Cqe> {
+        let cqe = match &self.hooks.faults {
+            Some(faults) => {
+                self.drain();
+                faults.take_due()
+            }
+            None => self.next_raw(),
+        }?;
+
+        if let Some(recorder) = &self.hooks.recorder {
+            recorder.complete(&cqe);
+        }
+        self.hooks.tracer.complete(&cqe);
+        self.hooks.stats.reap(&cqe);
+        Some(cqe)
+    }
+}
+
+impl Drop for CompletionQueue<'_> {
+    fn drop(&mut self) {
+        self.sync();
+    }
+}
+
+/// View an index shared with the kernel as an atomic.
+///
+/// # Safety
+///
+/// `ptr` must point into the ring mapping, which has to outlive the reference.
+pub(crate) unsafe fn as_atomic<'a>(ptr: *mut libc::c_uint) -> &'a AtomicU32 {
+    &*(ptr as *const AtomicU32)
+}
+
+/// Whether SQEs with `opcode` refer to a file through their `fd`, which
+/// SQPOLL rings without `IORING_FEAT_SQPOLL_NONFIXED` need to be registered
+fn takes_file(opcode: u8) -> bool {
+    use IoUringOp::*;
+
+    match IoUringOp::ALL.get(usize::from(opcode)) {
+        Some(
+            IORING_OP_NOP
+            | IORING_OP_TIMEOUT
+            | IORING_OP_TIMEOUT_REMOVE
+            | IORING_OP_ASYNC_CANCEL
+            | IORING_OP_LINK_TIMEOUT
+            | IORING_OP_OPENAT
+            | IORING_OP_OPENAT2
+            | IORING_OP_STATX
+            | IORING_OP_FILES_UPDATE
+            | IORING_OP_MADVISE
+            | IORING_OP_PROVIDE_BUFFERS
+            | IORING_OP_REMOVE_BUFFERS,
+        ) => false,
+        Some(_) => true,
+        None => false,
+    }
+}
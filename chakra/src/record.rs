@@ -0,0 +1,334 @@
+//! Recording the traffic of a ring, and reading recordings back.
+//!
+//! `IoRing::record` writes every SQE as it's submitted and every completion
+//! as it's reaped, each with the time since the recording started, so that a
+//! run can be inspected afterwards or fed back through `backend::Replay`.
+//!
+//! A recording starts with a header describing the ring, followed by one
+//! record per event. All integers are little-endian:
+//!
+//! | Header      | Bytes |
+//! |-------------|-------|
+//! | `MAGIC`     | 8     |
+//! | `VERSION`   | 2     |
+//! | SQ entries  | 4     |
+//! | CQ entries  | 4     |
+//! | setup flags | 4     |
+//! | features    | 4     |
+//!
+//! Each record is a tag byte, 0 for a submission and 1 for a completion, and
+//! the nanoseconds since the start of the recording as a u64. Submissions go
+//! on with the SQE fields in the order of `SqeRecord`, completions with those
+//! of `CqeRecord`.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use chakra_sys::IoUringOp;
+
+use crate::{cqe::Cqe, ring::IoRingParams};
+
+/// The first bytes of every recording
+pub const MAGIC: [u8; 8] = *b"chakrarc";
+
+/// The version of the format written by `IoRing::record`
+pub const VERSION: u16 = 1;
+
+const SUBMIT: u8 = 0;
+const COMPLETE: u8 = 1;
+
+/// The ring a recording was made on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    /// The `Flags` the ring was set up with
+    pub flags: u32,
+    /// The `FeatureFlags` the ring was set up with
+    pub features: u32,
+}
+
+/// A submitted SQE, without the parts that are only meaningful to the kernel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SqeRecord {
+    pub opcode: u8,
+    /// `SqeFlags`
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    pub off: u64,
+    pub addr: u64,
+    pub len: u32,
+    /// The opcode specific flags, like `rw_flags` or `msg_flags`
+    pub op_flags: u32,
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+}
+
+impl SqeRecord {
+    pub(crate) fn from_raw(sqe: &chakra_sys::io_uring_sqe) -> Self {
+        let personality = unsafe { sqe.buf_index_padding.personality };
+
+        SqeRecord {
+            opcode: sqe.opcode,
+            flags: sqe.flags,
+            ioprio: sqe.ioprio,
+            fd: sqe.fd,
+            off: unsafe { sqe.file_off.off },
+            addr: unsafe { sqe.addr_off.addr },
+            len: sqe.len,
+            op_flags: unsafe { sqe.cmd_flags.fsync_flags },
+            user_data: sqe.user_data,
+            buf_index: personality.buf_or_group,
+            personality: personality.personality,
+            splice_fd_in: personality.splice_fd_in,
+        }
+    }
+
+    /// The opcode, if it's one this crate knows about
+    pub fn op(&self) -> Option<IoUringOp> {
+        IoUringOp::ALL.get(self.opcode as usize).copied()
+    }
+}
+
+/// A reaped completion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CqeRecord {
+    pub user_data: u64,
+    pub res: i32,
+    pub flags: u32,
+}
+
+/// Something that happened on a recorded ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Submit(SqeRecord),
+    Complete(CqeRecord),
+}
+
+/// An `Event`, with when it happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// Time since the recording started
+    pub time: Duration,
+    pub event: Event,
+}
+
+/// A recording being read back, iterating over its records in order
+#[derive(Debug)]
+pub struct Recording<R> {
+    reader: R,
+    header: Header,
+}
+
+impl Recording<BufReader<File>> {
+    /// Open the recording at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Recording::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> Recording<R> {
+    /// Read a recording from `reader`, starting with its header
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid("not a chakra recording"));
+        }
+
+        let version = read_u16(&mut reader)?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+
+        let header = Header {
+            sq_entries: read_u32(&mut reader)?,
+            cq_entries: read_u32(&mut reader)?,
+            flags: read_u32(&mut reader)?,
+            features: read_u32(&mut reader)?,
+        };
+
+        Ok(Recording { reader, header })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let r = &mut self.reader;
+
+        let mut tag = [0];
+        if r.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let time = Duration::from_nanos(read_u64(r)?);
+
+        let event = match tag[0] {
+            SUBMIT => Event::Submit(SqeRecord {
+                opcode: read_u8(r)?,
+                flags: read_u8(r)?,
+                ioprio: read_u16(r)?,
+                fd: read_u32(r)? as i32,
+                off: read_u64(r)?,
+                addr: read_u64(r)?,
+                len: read_u32(r)?,
+                op_flags: read_u32(r)?,
+                user_data: read_u64(r)?,
+                buf_index: read_u16(r)?,
+                personality: read_u16(r)?,
+                splice_fd_in: read_u32(r)? as i32,
+            }),
+            COMPLETE => Event::Complete(CqeRecord {
+                user_data: read_u64(r)?,
+                res: read_u32(r)? as i32,
+                flags: read_u32(r)?,
+            }),
+            tag => return Err(invalid(&format!("unknown record {}", tag))),
+        };
+
+        Ok(Some(Record { time, event }))
+    }
+}
+
+impl<R: Read> Iterator for Recording<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Writes the recording started by `IoRing::record`
+pub(crate) struct Recorder {
+    start: Instant,
+    inner: Mutex<Writer>,
+}
+
+struct Writer {
+    out: BufWriter<Box<dyn Write + Send>>,
+    /// The first write that failed, reported by `finish`
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Start a recording of the ring set up with `params`, writing its header
+    pub(crate) fn new(out: Box<dyn Write + Send>, params: &IoRingParams) -> io::Result<Self> {
+        let mut out = BufWriter::new(out);
+
+        out.write_all(&MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        for n in &[
+            params.sq_entries,
+            params.cq_entries,
+            params.flags.bits(),
+            params.features.bits(),
+        ] {
+            out.write_all(&n.to_le_bytes())?;
+        }
+
+        Ok(Recorder {
+            start: Instant::now(),
+            inner: Mutex::new(Writer { out, error: None }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Writer> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record `sqe` as it's submitted
+    pub(crate) fn submit(&self, sqe: &chakra_sys::io_uring_sqe) {
+        let sqe = SqeRecord::from_raw(sqe);
+
+        let mut buf = Vec::with_capacity(53);
+        buf.push(SUBMIT);
+        buf.extend_from_slice(&self.elapsed().to_le_bytes());
+        buf.push(sqe.opcode);
+        buf.push(sqe.flags);
+        buf.extend_from_slice(&sqe.ioprio.to_le_bytes());
+        buf.extend_from_slice(&sqe.fd.to_le_bytes());
+        buf.extend_from_slice(&sqe.off.to_le_bytes());
+        buf.extend_from_slice(&sqe.addr.to_le_bytes());
+        buf.extend_from_slice(&sqe.len.to_le_bytes());
+        buf.extend_from_slice(&sqe.op_flags.to_le_bytes());
+        buf.extend_from_slice(&sqe.user_data.to_le_bytes());
+        buf.extend_from_slice(&sqe.buf_index.to_le_bytes());
+        buf.extend_from_slice(&sqe.personality.to_le_bytes());
+        buf.extend_from_slice(&sqe.splice_fd_in.to_le_bytes());
+
+        self.write(&buf);
+    }
+
+    /// Record `cqe` as it's reaped
+    pub(crate) fn complete(&self, cqe: &Cqe) {
+        let mut buf = Vec::with_capacity(25);
+        buf.push(COMPLETE);
+        buf.extend_from_slice(&self.elapsed().to_le_bytes());
+        buf.extend_from_slice(&cqe.user_data().to_le_bytes());
+        buf.extend_from_slice(&cqe.raw_result().to_le_bytes());
+        buf.extend_from_slice(&cqe.flags().to_le_bytes());
+
+        self.write(&buf);
+    }
+
+    /// Flush the recording out, returning the first error writing it
+    pub(crate) fn finish(self) -> io::Result<()> {
+        let mut writer = self.inner.into_inner().unwrap_or_else(|e| e.into_inner());
+
+        match writer.error.take() {
+            Some(e) => Err(e),
+            None => writer.out.flush(),
+        }
+    }
+
+    fn elapsed(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+
+    fn write(&self, buf: &[u8]) {
+        let mut writer = self.lock();
+
+        if writer.error.is_none() {
+            if let Err(e) = writer.out.write_all(buf) {
+                writer.error = Some(e);
+            }
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(r: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
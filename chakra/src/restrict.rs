@@ -0,0 +1,91 @@
+use std::ptr;
+
+use chakra_sys::{io_uring_restriction, restriction_op, IoUringOp};
+
+use crate::{IoRing, Result, SqeFlags};
+
+/// What a ring may be used for once it's enabled, see
+/// `IoRing::register_restrictions`.
+///
+/// Everything that isn't allowed explicitly is denied: SQEs with other
+/// opcodes or flags complete with `EACCES`, and so do calls registering
+/// anything with other io_uring_register(2) opcodes.
+#[derive(Debug, Clone, Default)]
+pub struct Restrictions {
+    /// `IORING_RESTRICTION_*` opcodes, each with the opcode or flags it applies to
+    entries: Vec<(u16, u8)>,
+}
+
+impl Restrictions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the io_uring_register(2) opcode `opcode`, one of the
+    /// `chakra_sys::IORING_REGISTER_*` constants
+    pub fn register_op(self, opcode: u32) -> Self {
+        self.push(chakra_sys::IORING_RESTRICTION_REGISTER_OP, opcode as u8)
+    }
+
+    /// Allow SQEs with the opcode `op`
+    pub fn sqe_op(self, op: IoUringOp) -> Self {
+        self.push(chakra_sys::IORING_RESTRICTION_SQE_OP, op as u8)
+    }
+
+    /// Allow SQEs to be submitted with `flags`, on top of the required ones
+    pub fn sqe_flags_allowed(self, flags: SqeFlags) -> Self {
+        self.push(
+            chakra_sys::IORING_RESTRICTION_SQE_FLAGS_ALLOWED,
+            flags.bits(),
+        )
+    }
+
+    /// Require every SQE to be submitted with `flags`, e.g.
+    /// `IOSQE_FIXED_FILE` to limit the ring to registered files
+    pub fn sqe_flags_required(self, flags: SqeFlags) -> Self {
+        self.push(
+            chakra_sys::IORING_RESTRICTION_SQE_FLAGS_REQUIRED,
+            flags.bits(),
+        )
+    }
+
+    fn push(mut self, opcode: u32, arg: u8) -> Self {
+        self.entries.push((opcode as u16, arg));
+        self
+    }
+}
+
+impl IoRing {
+    /// Limit what the ring may be used for, with `IORING_REGISTER_RESTRICTIONS`.
+    ///
+    /// Only rings set up with `IoRingBuilder::disabled` can be restricted, and
+    /// only once, before `enable_rings`. Needs Linux 5.10.
+    pub fn register_restrictions(&mut self, restrictions: &Restrictions) -> Result<()> {
+        // The union fields are all the same byte, the opcode says which it is
+        let raw: Vec<_> = restrictions
+            .entries
+            .iter()
+            .map(|&(opcode, arg)| io_uring_restriction {
+                opcode,
+                restriction_op: restriction_op { register_op: arg },
+                resv: 0,
+                resv2: [0; 3],
+            })
+            .collect();
+
+        unsafe {
+            self.register(
+                chakra_sys::IORING_REGISTER_RESTRICTIONS,
+                raw.as_ptr() as *const _,
+                raw.len(),
+            )
+        }
+    }
+
+    /// Enable a ring set up with `IoRingBuilder::disabled`, with
+    /// `IORING_REGISTER_ENABLE_RINGS`. Until then entering it fails with
+    /// `EBADFD`.
+    pub fn enable_rings(&mut self) -> Result<()> {
+        unsafe { self.register(chakra_sys::IORING_REGISTER_ENABLE_RINGS, ptr::null(), 0) }
+    }
+}
@@ -1,11 +1,35 @@
 use bitflags::bitflags;
 
-use std::{io, mem::MaybeUninit};
-
-use crate::sqe::Sqe;
+use std::{
+    convert::TryInto,
+    io::Write,
+    os::unix::io::{AsRawFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    backend::{Backend, Kernel},
+    cqe::Cqe,
+    error::Error,
+    fault::Faults,
+    queue::{as_atomic, CompletionQueue, Hooks, SubmissionQueue, Submitter},
+    record::Recorder,
+    sqe::Sqe,
+    stats::Stats,
+    Result,
+};
 
 pub struct IoRing {
     ring: chakra_sys::io_uring,
+    params: IoRingParams,
+    /// Operations submitted whose completion hasn't been reaped yet
+    in_flight: AtomicU32,
+    /// `RingState::sq_dropped` as last accounted for in `in_flight`
+    sq_dropped: u32,
+    backend: Box<dyn Backend>,
+    hooks: Hooks,
 }
 
 bitflags! {
@@ -23,61 +47,449 @@ bitflags! {
 
 impl IoRing {
     /// Initialize the io_uring instance
-    pub fn init(entries: u32, flags: Flags) -> Result<Self, io::Error> {
-        let mut ring = MaybeUninit::uninit();
+    ///
+    /// This is a shorthand for `IoRingBuilder`, which gives control over the
+    /// rest of the setup parameters and returns the ones filled in by the kernel.
+    pub fn init(entries: u32, flags: Flags) -> Result<Self> {
+        let mut builder = IoRingBuilder::new().sq_entries(entries);
+        builder.params.flags |= flags;
+
+        builder.build().map(|(ring, _)| ring)
+    }
+
+    /// The parameters the kernel set the ring up with
+    pub fn params(&self) -> &IoRingParams {
+        &self.params
+    }
+
+    /// Split the ring into its submission queue, completion queue and the
+    /// submitter that enters the kernel on their behalf.
+    ///
+    /// Unlike the ring itself the halves are `Send`, so that one thread can
+    /// prepare and submit operations while another reaps their completions.
+    pub fn split(&mut self) -> (SubmissionQueue<'_>, CompletionQueue<'_>, Submitter<'_>) {
+        let IoRing {
+            ring,
+            params,
+            in_flight,
+            sq_dropped,
+            backend,
+            hooks,
+        } = self;
+
+        let submitter = Submitter::new(&**backend, &ring.io_uring_sq, params, hooks);
+        let sq_flags = unsafe { as_atomic(ring.io_uring_sq.kflags) };
+        let cq = CompletionQueue::new(
+            &ring.io_uring_cq,
+            &**backend,
+            sq_flags,
+            params,
+            in_flight,
+            hooks,
+        );
+        let sq = SubmissionQueue::new(&mut ring.io_uring_sq, params, in_flight, sq_dropped, hooks);
+
+        (sq, cq, submitter)
+    }
 
-        let res =
-            unsafe { chakra_sys::io_uring_queue_init(entries, ring.as_mut_ptr(), flags.bits()) };
+    fn completion_queue(&self) -> CompletionQueue<'_> {
+        let sq_flags = unsafe { as_atomic(self.ring.io_uring_sq.kflags) };
+
+        CompletionQueue::new(
+            &self.ring.io_uring_cq,
+            &*self.backend,
+            sq_flags,
+            &self.params,
+            &self.in_flight,
+            &self.hooks,
+        )
+    }
+
+    /// The fault injection rules of the ring, see `FaultRule`.
+    ///
+    /// Until this is first called, operations go to the backend untouched and
+    /// completions aren't matched against anything.
+    pub fn faults(&mut self) -> &Faults {
+        self.hooks.faults.get_or_insert_with(Faults::new)
+    }
 
-        if res != 0 {
-            return Err(io::Error::from_raw_os_error(res));
+    /// Record every SQE submitted and every completion reaped from now on to
+    /// `out`, see the `record` module. Any recording already in progress is
+    /// stopped first.
+    ///
+    /// SQEs are recorded as the application prepared them, before any
+    /// `faults` are applied, and completions as they were handed out.
+    pub fn record<W: Write + Send + 'static>(&mut self, out: W) -> Result<()> {
+        self.stop_recording()?;
+        self.hooks.recorder = Some(Recorder::new(Box::new(out), &self.params)?);
+        Ok(())
+    }
+
+    /// Stop recording, flushing out what has been recorded so far
+    pub fn stop_recording(&mut self) -> Result<()> {
+        match self.hooks.recorder.take() {
+            Some(recorder) => Ok(recorder.finish()?),
+            None => Ok(()),
         }
+    }
 
-        Ok(IoRing {
-            ring: unsafe { ring.assume_init() },
-        })
+    /// Get the next free SQE, or `None` if the submission queue is full.
+    ///
+    /// On `IORING_SETUP_IOPOLL` rings the returned SQE only accepts the
+    /// read and write opcodes that support polled completions.
+    pub fn get_sqe(&mut self) -> Option<Sqe> {
+        self.split().0.get_sqe()
     }
 
-    pub fn init_params(entries: u32, flags: Flags) -> Result<(Self, IoRingParams), io::Error> {
-        let mut ring = MaybeUninit::uninit();
-        let params = IoRingParams::new(flags).as_mut_ffi_ptr();
+    /// Submit all prepared SQEs to the kernel, returning the number submitted
+    pub fn submit(&mut self) -> Result<usize> {
+        self.submit_and_wait(0)
+    }
 
-        let res =
-            unsafe { chakra_sys::io_uring_queue_init_params(entries, ring.as_mut_ptr(), params) };
+    /// Submit all prepared SQEs and block until at least `wait_nr` completions are available
+    ///
+    /// On `IORING_SETUP_SQPOLL` rings the kernel thread picks up new entries by
+    /// itself, so the kernel is only entered to wake it up once it has gone idle,
+    /// or to wait for completions.
+    ///
+    /// If the kernel lacks `IORING_FEAT_SQPOLL_NONFIXED`, SQPOLL rings can only
+    /// be used with registered files. Pending SQEs referring to any other file
+    /// complete with `EBADF`.
+    ///
+    /// Without `IORING_FEAT_NODROP` the kernel throws away completions that
+    /// don't fit in the completion queue, so only as many SQEs are submitted as
+    /// there is room for completions. The rest stay pending for a later call,
+    /// and `Error::CompletionQueueFull` is returned if none could be published
+    /// and there is nothing else to submit or wait for.
+    pub fn submit_and_wait(&mut self, wait_nr: u32) -> Result<usize> {
+        let faulted = self.hooks.faults.is_some();
+        let (mut sq, mut cq, submitter) = self.split();
+
+        // On SQPOLL rings the kernel thread may consume what was just
+        // published straight away, so the count left unconsumed says nothing
+        if sq.publish() == 0 && wait_nr == 0 && sq.pending() > 0 && sq.sync()? == 0 {
+            return Err(Error::CompletionQueueFull);
+        }
 
-        if res != 0 {
-            return Err(io::Error::from_raw_os_error(res));
+        if wait_nr > 0 && faulted {
+            // Completions may be held back, so the kernel can't tell how
+            // many are ready
+            let submitted = submitter.submit()?;
+            cq.wait(wait_nr)?;
+            return Ok(submitted);
         }
 
-        let mut kernel_params: Option<IoRingParams> = None;
+        submitter.submit_and_wait(wait_nr)
+    }
+
+    /// Block until the SQPOLL thread has consumed entries from a full
+    /// submission queue, so that `get_sqe` can hand out a new one.
+    ///
+    /// Returns immediately if the ring isn't in SQPOLL mode or there is room.
+    pub fn sq_wait(&mut self) -> Result<()> {
+        let (mut sq, _, submitter) = self.split();
+
+        sq.sync()?;
+        submitter.sq_wait()
+    }
+
+    /// Block until a completion is available and reap it
+    pub fn wait_cqe(&mut self) -> Result<Cqe> {
+        self.get_cqe(1)?
+            .ok_or_else(|| Error::from_errno(-libc::EAGAIN))
+    }
+
+    /// Reap a completion if one is available, without blocking
+    pub fn peek_cqe(&mut self) -> Option<Cqe> {
+        self.get_cqe(0).ok().flatten()
+    }
 
+    /// Number of completions the kernel had to drop because the completion
+    /// queue was full.
+    ///
+    /// This stays at zero on kernels with `IORING_FEAT_NODROP` unless the
+    /// kernel runs out of memory buffering the overflow.
+    pub fn cq_dropped(&self) -> u32 {
+        self.completion_queue().dropped()
+    }
+
+    /// Take a snapshot of the state of the submission and completion queues
+    pub fn state(&self) -> RingState {
+        let sq = &self.ring.io_uring_sq;
+        let (sq_unconsumed, sq_space_left, sq_dropped, sq_flags) = unsafe {
+            let head = as_atomic(sq.khead).load(Ordering::Acquire);
+            let tail = as_atomic(sq.ktail).load(Ordering::Relaxed);
+
+            (
+                tail.wrapping_sub(head),
+                *sq.kring_entries - sq.sqe_tail.wrapping_sub(head),
+                as_atomic(sq.kdropped).load(Ordering::Relaxed),
+                as_atomic(sq.kflags).load(Ordering::Relaxed),
+            )
+        };
+        let cq = self.completion_queue();
+
+        RingState {
+            sq_pending: sq.sqe_tail.wrapping_sub(sq.sqe_head),
+            sq_unconsumed,
+            sq_space_left,
+            sq_dropped,
+            sq_needs_wakeup: sq_flags & chakra_sys::IORING_SQ_NEED_WAKEUP != 0,
+            cq_ready: cq.ready(),
+            cq_dropped: cq.dropped(),
+            cq_overflow_pending: cq.overflow_pending(),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counters and latency histograms of the ring since it was set up
+    pub fn stats(&self) -> Stats {
+        self.hooks
+            .stats
+            .snapshot(self.in_flight.load(Ordering::Relaxed))
+    }
+
+    /// Register `bufs` with the kernel, so that `Sqe::prep_read_fixed` and
+    /// `Sqe::prep_write_fixed` can refer to them by index without the kernel
+    /// having to map them on every operation.
+    ///
+    /// # Safety
+    ///
+    /// The memory described by `bufs` must stay valid until the buffers are
+    /// unregistered or the ring is dropped.
+    pub unsafe fn register_buffers(&mut self, bufs: &[libc::iovec]) -> Result<()> {
+        self.register(
+            chakra_sys::IORING_REGISTER_BUFFERS,
+            bufs.as_ptr() as *const _,
+            bufs.len(),
+        )
+    }
+
+    /// Unregister the buffers registered with `register_buffers`
+    pub fn unregister_buffers(&mut self) -> Result<()> {
+        unsafe { self.register(chakra_sys::IORING_UNREGISTER_BUFFERS, ptr::null(), 0) }
+    }
+
+    /// Register `fds` with the kernel, so that SQEs flagged with
+    /// `SqeFlags::IOSQE_FIXED_FILE` can refer to them by index in place of a
+    /// file descriptor. This saves taking a reference to the file on every
+    /// operation, and is required by SQPOLL rings on older kernels.
+    pub fn register_files(&mut self, fds: &[RawFd]) -> Result<()> {
         unsafe {
-            if let Some(out) = params.as_mut() {
-                kernel_params = Some((*out).into());
-            }
+            self.register(
+                chakra_sys::IORING_REGISTER_FILES,
+                fds.as_ptr() as *const _,
+                fds.len(),
+            )
         }
+    }
+
+    /// Unregister the files registered with `register_files`
+    pub fn unregister_files(&mut self) -> Result<()> {
+        unsafe { self.register(chakra_sys::IORING_UNREGISTER_FILES, ptr::null(), 0) }
+    }
 
-        if let Some(user_params) = kernel_params {
-            Ok((
-                IoRing {
-                    ring: unsafe { ring.assume_init() },
-                },
-                user_params,
-            ))
-        } else {
-            Err(io::Error::from_raw_os_error(0))
+    /// Have the kernel signal the eventfd `fd` whenever a completion is posted
+    pub fn register_eventfd(&mut self, fd: RawFd) -> Result<()> {
+        unsafe {
+            self.register(
+                chakra_sys::IORING_REGISTER_EVENTFD,
+                &fd as *const RawFd as *const _,
+                1,
+            )
         }
     }
 
-    pub fn get_sqe(&mut self) -> Option<Sqe> {
-        let sqe_ptr = unsafe { chakra_sys::io_uring_get_sqe(&mut self.ring as *mut _) };
+    /// Stop signalling the eventfd registered with `register_eventfd`
+    pub fn unregister_eventfd(&mut self) -> Result<()> {
+        unsafe { self.register(chakra_sys::IORING_UNREGISTER_EVENTFD, ptr::null(), 0) }
+    }
+
+    /// io_uring_register(2) through the backend, for the `register_*` methods
+    /// and `probe`
+    pub(crate) unsafe fn register(
+        &self,
+        opcode: u32,
+        arg: *const libc::c_void,
+        nr_args: usize,
+    ) -> Result<()> {
+        self.hooks.stats.syscall();
+        self.backend
+            .register(opcode, arg, nr_args as libc::c_uint)
+            .map(drop)
+    }
+
+    /// Iterate over the completions that are ready, handing them back to the
+    /// kernel all at once when the iterator is dropped.
+    pub fn completions(&mut self) -> CompletionQueue<'_> {
+        self.split().1
+    }
+
+    fn get_cqe(&mut self, wait_nr: u32) -> Result<Option<Cqe>> {
+        let mut cq = self.split().1;
 
-        Sqe::from_raw(sqe_ptr)
+        cq.wait(wait_nr)?;
+        Ok(cq.next())
+    }
+}
+
+/// A snapshot of the queues of an `IoRing`, see `IoRing::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingState {
+    /// SQEs prepared but not yet submitted
+    pub sq_pending: u32,
+    /// SQEs submitted that the kernel hasn't consumed yet
+    pub sq_unconsumed: u32,
+    /// SQEs that can still be handed out by `get_sqe`
+    pub sq_space_left: u32,
+    /// SQEs the kernel skipped because they were invalid
+    pub sq_dropped: u32,
+    /// Whether the SQPOLL thread has gone to sleep
+    pub sq_needs_wakeup: bool,
+    /// Completions waiting to be reaped
+    pub cq_ready: u32,
+    /// Completions the kernel dropped because the completion queue was full
+    pub cq_dropped: u32,
+    /// Whether the kernel is buffering completions that didn't fit in the
+    /// completion queue, they are flushed the next time one is reaped
+    pub cq_overflow_pending: bool,
+    /// Operations submitted whose completion hasn't been reaped yet
+    pub in_flight: u32,
+}
+
+impl Drop for IoRing {
+    fn drop(&mut self) {
+        unsafe { self.backend.exit(&mut self.ring) };
+    }
+}
+
+impl AsRawFd for IoRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ring.ring_fd
+    }
+}
+
+/// Builder for an `IoRing`, exposing the setup parameters of io_uring_setup(2).
+#[derive(Debug, Clone)]
+pub struct IoRingBuilder {
+    entries: u32,
+    params: IoRingParams,
+    track_latency: bool,
+}
+
+impl IoRingBuilder {
+    pub fn new() -> Self {
+        IoRingBuilder {
+            entries: 128,
+            params: IoRingParams::new(Flags::empty()),
+            track_latency: false,
+        }
+    }
+
+    /// Number of entries in the submission queue, rounded up to a power of two
+    pub fn sq_entries(mut self, entries: u32) -> Self {
+        self.entries = entries;
+        self
+    }
+
+    /// Number of entries in the completion queue, which otherwise defaults to
+    /// twice the submission queue size
+    pub fn cq_entries(mut self, entries: u32) -> Self {
+        self.params.cq_entries = entries;
+        self.params.flags |= Flags::IORING_SETUP_CQSIZE;
+        self
+    }
+
+    /// Have a kernel thread poll the submission queue, going to sleep after
+    /// `idle` without any submissions. The thread is bound to `cpu` if given.
+    pub fn sqpoll(mut self, idle: Duration, cpu: Option<u32>) -> Self {
+        self.params.flags |= Flags::IORING_SETUP_SQPOLL;
+        self.params.sq_thread_idle = idle.as_millis().try_into().unwrap_or(u32::MAX);
+
+        if let Some(cpu) = cpu {
+            self.params.flags |= Flags::IORING_SETUP_SQ_AFF;
+            self.params.sq_thread_cpu = cpu;
+        }
+
+        self
+    }
+
+    /// Busy-poll for completions instead of relying on interrupts. Only
+    /// usable with files opened with `O_DIRECT`.
+    pub fn iopoll(mut self) -> Self {
+        self.params.flags |= Flags::IORING_SETUP_IOPOLL;
+        self
+    }
+
+    /// Clamp queue sizes larger than the kernel maximum instead of failing
+    pub fn clamp(mut self) -> Self {
+        self.params.flags |= Flags::IORING_SETUP_CLAMP;
+        self
+    }
+
+    /// Share the async worker pool of an existing ring
+    pub fn attach_wq(mut self, ring: &IoRing) -> Self {
+        self.params.flags |= Flags::IORING_SETUP_ATTACH_WQ;
+        self.params.wq_fd = ring.as_raw_fd() as u32;
+        self
+    }
+
+    /// Create the ring disabled, so that restrictions can be registered with
+    /// `IoRing::register_restrictions` before it is enabled with
+    /// `IoRing::enable_rings`
+    pub fn disabled(mut self) -> Self {
+        self.params.flags |= Flags::IORING_SETUP_R_DISABLED;
+        self
+    }
+
+    /// Keep latency histograms by opcode in `IoRing::stats`, at the cost of a
+    /// lock taken for every SQE published and CQE reaped. At most as many
+    /// operations as fit in the completion queue are timed at once.
+    pub fn track_latency(mut self) -> Self {
+        self.track_latency = true;
+        self
+    }
+
+    /// Set up the ring, returning it along with the parameters as filled in
+    /// by the kernel
+    pub fn build(self) -> Result<(IoRing, IoRingParams)> {
+        self.build_with(Kernel::new())
+    }
+
+    /// Set up the ring on `backend` in place of the kernel, e.g. a
+    /// `backend::Sim` to run the operations in process
+    pub fn build_with<B: Backend>(self, mut backend: B) -> Result<(IoRing, IoRingParams)> {
+        let mut params = chakra_sys::io_uring_params::from(self.params);
+        let ring = backend.setup(self.entries, &mut params)?;
+        let params = IoRingParams::from(params);
+
+        let mut hooks = Hooks::default();
+        if self.track_latency {
+            hooks.stats.track_latency(params.cq_entries as usize);
+        }
+
+        Ok((
+            IoRing {
+                ring,
+                params,
+                in_flight: AtomicU32::new(0),
+                sq_dropped: 0,
+                backend: Box::new(backend),
+                hooks,
+            },
+            params,
+        ))
+    }
+}
+
+impl Default for IoRingBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 bitflags! {
-    /// FeatureFlags is returned by the kernel when calling `init_params`
+    /// FeatureFlags is returned by the kernel when calling `IoRingBuilder::build`
     #[derive(Default)]
     pub struct FeatureFlags: u32 {
         const IORING_FEAT_SINGLE_MMAP       = chakra_sys::IORING_FEAT_SINGLE_MMAP as u32;
@@ -120,10 +532,6 @@ impl IoRingParams {
             cq_off: IoCqringOffsets::new(),
         }
     }
-
-    fn as_mut_ffi_ptr(&mut self) -> *mut chakra_sys::io_uring_params {
-        &mut From::from(*self) as *mut _
-    }
 }
 
 impl From<IoRingParams> for chakra_sys::io_uring_params {
@@ -0,0 +1,111 @@
+/// A buffer that can be handed to the kernel for the duration of an operation.
+///
+/// Buffers are owned by the operation while it is in flight and handed back
+/// once it completes, so they can't be touched while the kernel uses them.
+///
+/// # Safety
+///
+/// The memory behind `stable_ptr` must stay valid and in place when the buffer
+/// itself is moved, which holds for heap allocations like `Vec<u8>`.
+pub unsafe trait IoBuf: Unpin + 'static {
+    /// Pointer to the start of the buffer
+    fn stable_ptr(&self) -> *const u8;
+
+    /// Number of initialized bytes, which is how much a write sends
+    fn bytes_init(&self) -> usize;
+
+    /// Total capacity, which is how much a read can fill
+    fn bytes_total(&self) -> usize;
+}
+
+/// A buffer the kernel can write into.
+///
+/// # Safety
+///
+/// Same as `IoBuf`, and `stable_mut_ptr` must point to `bytes_total` bytes.
+pub unsafe trait IoBufMut: IoBuf {
+    fn stable_mut_ptr(&mut self) -> *mut u8;
+
+    /// Mark the first `pos` bytes as initialized, never shrinking the buffer.
+    ///
+    /// # Safety
+    ///
+    /// The first `pos` bytes must have been initialized.
+    unsafe fn set_init(&mut self, pos: usize);
+}
+
+unsafe impl IoBuf for Vec<u8> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.capacity()
+    }
+}
+
+unsafe impl IoBufMut for Vec<u8> {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, pos: usize) {
+        if self.len() < pos {
+            self.set_len(pos);
+        }
+    }
+}
+
+unsafe impl IoBuf for Box<[u8]> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBufMut for Box<[u8]> {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, _pos: usize) {}
+}
+
+unsafe impl IoBuf for &'static [u8] {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBuf for &'static str {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
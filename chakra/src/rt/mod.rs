@@ -0,0 +1,331 @@
+//! Async operations on top of an `IoRing`.
+//!
+//! A `Driver` owns the ring and hands out an `Op` future for each operation it
+//! submits, with the slot the operation is tracked in and a generation, which
+//! tells the operations that took turns in that slot apart, as its `user_data`.
+//! Reaping completions with `Driver::dispatch` or `Driver::park` wakes the task
+//! waiting on each one.
+//!
+//! Operations take ownership of their buffers and give them back on
+//! completion. When an `Op` is dropped before it completes, its buffer is kept
+//! by the driver until the kernel is done with it and a cancellation is
+//! submitted in its place.
+//!
+//! The driver is single threaded, it's meant to be owned by one thread and
+//! driven from that thread's event loop.
+
+mod buf;
+mod op;
+mod ops;
+#[cfg(feature = "tokio")]
+mod tokio_driver;
+
+pub use buf::{IoBuf, IoBufMut};
+pub use op::Op;
+#[cfg(feature = "tokio")]
+pub use tokio_driver::TokioDriver;
+
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    marker::PhantomData,
+    rc::Rc,
+    task::Waker,
+};
+
+use slab::Slab;
+
+use crate::{Error, IoRing, Result, Sqe};
+
+use op::Lifecycle;
+
+/// `user_data` of the SQEs that don't belong to an `Op`, like cancellations.
+/// Their completions are skipped.
+const DETACHED: u64 = u64::MAX;
+
+/// The `user_data` of the operation in slot `index` of the given generation
+fn user_data(index: usize, generation: u32) -> u64 {
+    (u64::from(generation) << 32) | index as u64
+}
+
+/// The slot and generation of the operation submitted with `user_data`
+fn slot(user_data: u64) -> (usize, u32) {
+    (user_data as u32 as usize, (user_data >> 32) as u32)
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Driver>> = const { RefCell::new(None) };
+}
+
+/// The driver entered on this thread, which types like `fs::File` submit
+/// their operations to.
+///
+/// # Panics
+///
+/// If no driver was entered with `Driver::enter`, which `Runtime::block_on`
+/// does for its own.
+pub fn current() -> Driver {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .expect("no driver entered on this thread, see `Driver::enter`")
+}
+
+/// Drives the operations submitted to a ring.
+///
+/// Handles are cheap to clone and all refer to the same ring.
+#[derive(Clone)]
+pub struct Driver {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    ring: RefCell<IoRing>,
+    /// Operations by slot, along with the generation they were submitted in
+    ops: RefCell<Slab<(u32, Lifecycle)>>,
+    /// Generation of the next operation submitted, so that a completion or
+    /// cancellation meant for an earlier occupant of its slot can't be
+    /// mistaken for one of it
+    generation: Cell<u32>,
+    /// Woken when an SQE is prepared, for drivers that have to submit it
+    on_prepare: RefCell<Option<Waker>>,
+}
+
+impl Driver {
+    pub fn new(ring: IoRing) -> Self {
+        Driver {
+            inner: Rc::new(Inner {
+                ring: RefCell::new(ring),
+                ops: RefCell::new(Slab::new()),
+                generation: Cell::new(0),
+                on_prepare: RefCell::new(None),
+            }),
+        }
+    }
+
+    /// Make this the current driver of this thread until the guard is dropped
+    pub fn enter(&self) -> EnterGuard {
+        EnterGuard {
+            prev: CURRENT.with(|current| current.borrow_mut().replace(self.clone())),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Prepare an operation owning `data` and return the future resolving to
+    /// its result.
+    ///
+    /// `prep` fills in the SQE, pointing it at memory owned by `data`. That
+    /// memory must not move when `data` does, see `IoBuf`. The SQE is
+    /// submitted with the next call to `submit` or `park`.
+    pub fn submit_op<T, F>(&self, mut data: T, prep: F) -> Op<T>
+    where
+        T: 'static,
+        F: FnOnce(&mut Sqe, &mut T) -> Result<()>,
+    {
+        // Nothing may be borrowed while preparing, making room in the queue
+        // dispatches completions and the prepare waker may poll right away
+        let generation = self.inner.generation.get();
+        // Generations take the high half of the `user_data`, leaving the low
+        // half for far more slots than there can be operations in flight.
+        // Skipping the last one keeps clear of `DETACHED`.
+        self.inner
+            .generation
+            .set(generation.wrapping_add(1) % u32::MAX);
+        let index = self
+            .inner
+            .ops
+            .borrow_mut()
+            .insert((generation, Lifecycle::Submitted));
+        let user_data = user_data(index, generation);
+
+        match self.prep_sqe(user_data, |sqe| prep(sqe, &mut data)) {
+            Ok(()) => Op::new(self.clone(), user_data, data),
+            Err(e) => {
+                self.inner.ops.borrow_mut().remove(index);
+                Op::failed(self.clone(), e.into(), data)
+            }
+        }
+    }
+
+    /// Submit all prepared operations, returning how many the kernel consumed
+    pub fn submit(&self) -> io::Result<usize> {
+        Ok(self.inner.ring.borrow_mut().submit()?)
+    }
+
+    /// Submit all prepared operations and dispatch the completions that are
+    /// ready, without blocking.
+    ///
+    /// Unlike `submit`, the kernel not taking any SQEs for now isn't an error,
+    /// they're left queued for the next call once completions made room.
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let res = self.inner.ring.borrow_mut().submit();
+        match res {
+            Ok(_) => {}
+            Err(ref e) if is_backpressure(e) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        self.dispatch();
+
+        Ok(())
+    }
+
+    /// Reap the completions that are ready and wake the tasks waiting on them,
+    /// without blocking.
+    pub fn dispatch(&self) {
+        let mut wakers = Vec::new();
+
+        {
+            let mut ring = self.inner.ring.borrow_mut();
+            let mut ops = self.inner.ops.borrow_mut();
+
+            for cqe in ring.completions() {
+                let (index, generation) = match cqe.user_data() {
+                    DETACHED => continue,
+                    user_data => slot(user_data),
+                };
+
+                // The slot may have been taken over by another operation
+                // since, which this completion isn't meant for
+                match ops.get_mut(index) {
+                    Some((current, lifecycle)) if *current == generation => {
+                        let (remove, waker) = lifecycle.complete(cqe);
+                        if remove {
+                            ops.remove(index);
+                        }
+                        wakers.extend(waker);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // Wake once nothing is borrowed, in case a waker polls right away
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Submit all prepared operations and block until at least one completes,
+    /// then dispatch the completions.
+    ///
+    /// Returns straight away if no operations are in flight.
+    pub fn park(&self) -> io::Result<()> {
+        let wait_nr = if self.in_flight() > 0 { 1 } else { 0 };
+
+        let res = self.inner.ring.borrow_mut().submit_and_wait(wait_nr);
+        match res {
+            Ok(_) => {}
+            // The queue is held back until completions are reaped below
+            Err(ref e) if is_backpressure(e) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        self.dispatch();
+
+        Ok(())
+    }
+
+    /// Number of operations the kernel hasn't completed yet, including those
+    /// whose futures were dropped
+    pub fn in_flight(&self) -> usize {
+        self.inner
+            .ops
+            .borrow()
+            .iter()
+            .filter(|(_, (_, lifecycle))| !matches!(lifecycle, Lifecycle::Completed(_)))
+            .count()
+    }
+
+    /// Run `f` with the driver's ring
+    pub fn with_ring<R>(&self, f: impl FnOnce(&mut IoRing) -> R) -> R {
+        f(&mut self.inner.ring.borrow_mut())
+    }
+
+    /// Register `waker` to be woken the next time an SQE is prepared
+    #[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+    pub(crate) fn on_prepare(&self, waker: &Waker) {
+        *self.inner.on_prepare.borrow_mut() = Some(waker.clone());
+    }
+
+    /// Best effort cancellation of the operation submitted with `user_data`,
+    /// its completion still arrives either way.
+    fn cancel(&self, user_data: u64) {
+        let _ = self.prep_sqe(DETACHED, |sqe| sqe.prep_cancel(user_data));
+    }
+
+    /// Take an SQE, submitting what's queued if there's no room, and prepare it
+    /// with `prep`.
+    ///
+    /// If none of the queued SQEs can be submitted without risking a
+    /// completion queue overflow, this blocks until some complete and
+    /// dispatches them.
+    fn prep_sqe(&self, user_data: u64, prep: impl FnOnce(&mut Sqe) -> Result<()>) -> Result<()> {
+        self.fill_sqe(user_data, prep)?;
+
+        let waker = self.inner.on_prepare.borrow_mut().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+
+        Ok(())
+    }
+
+    fn fill_sqe(&self, user_data: u64, prep: impl FnOnce(&mut Sqe) -> Result<()>) -> Result<()> {
+        let mut ring = self.inner.ring.borrow_mut();
+
+        let mut sqe = loop {
+            if let Some(sqe) = ring.get_sqe() {
+                break sqe;
+            }
+
+            match ring.submit() {
+                // Only SQPOLL rings can still be full, until the kernel
+                // thread catches up
+                Ok(_) => ring.sq_wait()?,
+                Err(Error::CompletionQueueFull) => {
+                    ring.submit_and_wait(1)?;
+                    drop(ring);
+                    self.dispatch();
+                    ring = self.inner.ring.borrow_mut();
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        match prep(&mut sqe) {
+            Ok(()) => {
+                sqe.set_user_data(user_data);
+                Ok(())
+            }
+            Err(e) => {
+                sqe.discard(DETACHED);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Whether `e` only means that the kernel can't take more SQEs right now, and
+/// that reaping completions makes room
+fn is_backpressure(e: &Error) -> bool {
+    match e {
+        Error::CompletionQueueFull => true,
+        Error::Io(e) => matches!(
+            e.raw_os_error(),
+            Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::EBUSY)
+        ),
+        _ => false,
+    }
+}
+
+/// Restores the previous current driver when dropped, see `Driver::enter`.
+pub struct EnterGuard {
+    prev: Option<Driver>,
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.prev.take());
+    }
+}
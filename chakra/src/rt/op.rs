@@ -0,0 +1,155 @@
+use std::{
+    any::Any,
+    future::Future,
+    io, mem,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::Cqe;
+
+use super::{slot, Driver};
+
+/// State of an operation, tracked by the driver in the slot of its `user_data`.
+pub(crate) enum Lifecycle {
+    /// Submitted, but the future hasn't been polled yet
+    Submitted,
+    /// The future is waiting on the completion
+    Waiting(Waker),
+    /// The future was dropped, the resources it owned are kept here until the
+    /// kernel is done with them
+    Ignored(#[allow(dead_code)] Box<dyn Any>),
+    /// The completion arrived, the future picks it up on its next poll
+    Completed(Cqe),
+}
+
+impl Lifecycle {
+    /// Record the completion, returning whether the entry can be removed
+    /// along with the waker of the task waiting on it.
+    ///
+    /// A second completion for the same operation, e.g. from an SQE submitted
+    /// straight to the ring with the same `user_data`, is ignored.
+    pub(crate) fn complete(&mut self, cqe: Cqe) -> (bool, Option<Waker>) {
+        match mem::replace(self, Lifecycle::Completed(cqe)) {
+            Lifecycle::Submitted => (false, None),
+            Lifecycle::Waiting(waker) => (false, Some(waker)),
+            Lifecycle::Ignored(_) => (true, None),
+            Lifecycle::Completed(first) => {
+                *self = Lifecycle::Completed(first);
+                (false, None)
+            }
+        }
+    }
+}
+
+/// A submitted operation, resolving to its result and the resources it owned.
+///
+/// `T` holds whatever the kernel reads from or writes to while the operation
+/// is in flight. Dropping an unfinished `Op` requests its cancellation, and
+/// `T` is kept alive by the driver until the completion arrives.
+#[must_use = "operations are cancelled when dropped"]
+pub struct Op<T: 'static> {
+    driver: Driver,
+    /// `None` if the operation couldn't be submitted at all
+    user_data: Option<u64>,
+    data: Option<T>,
+    /// Set when the operation couldn't be submitted at all
+    error: Option<io::Error>,
+}
+
+// The data is never pinned, it's only moved out once the operation completes.
+impl<T: 'static> Unpin for Op<T> {}
+
+impl<T: 'static> Op<T> {
+    pub(crate) fn new(driver: Driver, user_data: u64, data: T) -> Self {
+        Op {
+            driver,
+            user_data: Some(user_data),
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    pub(crate) fn failed(driver: Driver, error: io::Error, data: T) -> Self {
+        Op {
+            driver,
+            user_data: None,
+            data: Some(data),
+            error: Some(error),
+        }
+    }
+
+    /// The `user_data` this operation was submitted with, or `None` if it
+    /// couldn't be submitted
+    pub fn user_data(&self) -> Option<u64> {
+        self.user_data
+    }
+
+    /// The driver's slot tracking this operation
+    fn index(&self) -> usize {
+        slot(self.user_data.expect("`Op` wasn't submitted")).0
+    }
+
+    fn take_data(&mut self) -> T {
+        self.data.take().expect("`Op` polled after completion")
+    }
+}
+
+impl<T: 'static> Future for Op<T> {
+    type Output = (io::Result<u32>, T);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if let Some(e) = this.error.take() {
+            return Poll::Ready((Err(e), this.take_data()));
+        }
+
+        let index = this.index();
+        let mut ops = this.driver.inner.ops.borrow_mut();
+        let (_, lifecycle) = ops.get_mut(index).expect("`Op` polled after completion");
+
+        match mem::replace(lifecycle, Lifecycle::Submitted) {
+            Lifecycle::Submitted | Lifecycle::Waiting(_) => {
+                *lifecycle = Lifecycle::Waiting(cx.waker().clone());
+                Poll::Pending
+            }
+            Lifecycle::Completed(cqe) => {
+                ops.remove(index);
+                drop(ops);
+
+                Poll::Ready((cqe.result().map_err(Into::into), this.take_data()))
+            }
+            Lifecycle::Ignored(_) => unreachable!("`Op` polled after being dropped"),
+        }
+    }
+}
+
+impl<T: 'static> Drop for Op<T> {
+    fn drop(&mut self) {
+        let data = match self.data.take() {
+            Some(data) if self.error.is_none() => data,
+            _ => return,
+        };
+
+        let index = self.index();
+        let mut ops = self.driver.inner.ops.borrow_mut();
+        let lifecycle = match ops.get_mut(index) {
+            Some((_, lifecycle)) => lifecycle,
+            None => return,
+        };
+
+        match lifecycle {
+            Lifecycle::Submitted | Lifecycle::Waiting(_) => {
+                *lifecycle = Lifecycle::Ignored(Box::new(data));
+                drop(ops);
+
+                self.driver.cancel(self.user_data.unwrap());
+            }
+            Lifecycle::Completed(_) => {
+                ops.remove(index);
+            }
+            Lifecycle::Ignored(_) => unreachable!("`Op` dropped twice"),
+        }
+    }
+}
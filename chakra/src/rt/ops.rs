@@ -0,0 +1,133 @@
+use std::{
+    future::Future,
+    io,
+    os::unix::io::{AsRawFd, RawFd},
+    slice,
+};
+
+use crate::{EpollEvent, EpollOp};
+
+use super::{Driver, IoBuf, IoBufMut};
+
+/// A raw file descriptor, so operations don't borrow the file they act on.
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Driver {
+    /// Read into `buf` from `io` at `offset`, filling up to `buf.bytes_total()`
+    /// bytes. The buffer is handed back with the bytes read marked initialized.
+    ///
+    /// An `offset` of `u64::MAX` reads from the file position on kernels with
+    /// `IORING_FEAT_RW_CUR_POS`. `io` has to stay open until the returned
+    /// future completes.
+    pub fn read_at<T, B>(
+        &self,
+        io: &T,
+        buf: B,
+        offset: u64,
+    ) -> impl Future<Output = (io::Result<u32>, B)>
+    where
+        T: AsRawFd,
+        B: IoBufMut,
+    {
+        self.read_into(io, buf, 0, offset)
+    }
+
+    /// Write the initialized bytes of `buf` to `io` at `offset`.
+    ///
+    /// An `offset` of `u64::MAX` writes at the file position on kernels with
+    /// `IORING_FEAT_RW_CUR_POS`. `io` has to stay open until the returned
+    /// future completes.
+    pub fn write_at<T, B>(
+        &self,
+        io: &T,
+        buf: B,
+        offset: u64,
+    ) -> impl Future<Output = (io::Result<u32>, B)>
+    where
+        T: AsRawFd,
+        B: IoBuf,
+    {
+        self.write_from(io, buf, 0, offset)
+    }
+
+    /// Like `read_at`, but filling `buf` from byte `start` onwards
+    pub(crate) fn read_into<T, B>(
+        &self,
+        io: &T,
+        buf: B,
+        start: usize,
+        offset: u64,
+    ) -> impl Future<Output = (io::Result<u32>, B)>
+    where
+        T: AsRawFd,
+        B: IoBufMut,
+    {
+        let fd = Fd(io.as_raw_fd());
+        let op = self.submit_op(buf, move |sqe, buf| {
+            let len = buf.bytes_total() - start;
+            let buf = unsafe { slice::from_raw_parts_mut(buf.stable_mut_ptr().add(start), len) };
+            sqe.prep_read(fd, buf, offset as usize)
+        });
+
+        async move {
+            let (res, mut buf) = op.await;
+            if let Ok(n) = res {
+                unsafe { buf.set_init(start + n as usize) };
+            }
+
+            (res, buf)
+        }
+    }
+
+    /// Like `write_at`, but writing the initialized bytes of `buf` from byte
+    /// `start` onwards
+    pub(crate) fn write_from<T, B>(
+        &self,
+        io: &T,
+        buf: B,
+        start: usize,
+        offset: u64,
+    ) -> impl Future<Output = (io::Result<u32>, B)>
+    where
+        T: AsRawFd,
+        B: IoBuf,
+    {
+        let fd = Fd(io.as_raw_fd());
+        self.submit_op(buf, move |sqe, buf| {
+            let len = buf.bytes_init() - start;
+            let buf = unsafe { slice::from_raw_parts(buf.stable_ptr().add(start), len) };
+            sqe.prep_write(fd, buf, offset as usize)
+        })
+    }
+
+    /// Add, modify or remove `fd` in the interest list of the epoll instance
+    /// `epfd`.
+    ///
+    /// `event` is kept with the operation until it completes and handed back
+    /// along with the result. Both descriptors have to stay open until the
+    /// returned future completes.
+    pub fn epoll_ctl<E, T>(
+        &self,
+        epfd: &E,
+        op: EpollOp,
+        fd: &T,
+        event: EpollEvent,
+    ) -> impl Future<Output = (io::Result<u32>, EpollEvent)>
+    where
+        E: AsRawFd,
+        T: AsRawFd,
+    {
+        let (epfd, fd) = (Fd(epfd.as_raw_fd()), Fd(fd.as_raw_fd()));
+        // The event is owned by the `Op`, which the driver keeps alive until
+        // the completion arrives even if the future is dropped
+        self.submit_op(event, move |sqe, event| {
+            sqe.prep_epoll_ctl(&epfd, op, &fd, event)
+        })
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::File,
+    future::Future,
+    io::{self, Read},
+    os::unix::io::{AsRawFd, FromRawFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::unix::AsyncFd;
+
+use crate::IoRing;
+
+use super::Driver;
+
+/// Drives a ring from within a tokio runtime.
+///
+/// An eventfd is registered with the ring so the kernel signals it whenever a
+/// completion is posted, and tokio's reactor waits on it through `AsyncFd`.
+/// `run` then reaps the completions and wakes the chakra futures waiting on
+/// them, and submits new operations as they are prepared.
+///
+/// The driver isn't `Send`, so it has to be run on a `tokio::task::LocalSet`
+/// along with the tasks using it:
+///
+/// ```no_run
+/// # async fn example(ring: chakra::IoRing) -> std::io::Result<()> {
+/// let driver = chakra::rt::TokioDriver::new(ring)?;
+/// let handle = driver.driver().clone();
+/// tokio::task::spawn_local(async move { driver.run().await });
+///
+/// let file = std::fs::File::open("/etc/hosts")?;
+/// let (res, buf) = handle.read_at(&file, Vec::with_capacity(4096), 0).await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TokioDriver {
+    driver: Driver,
+    eventfd: AsyncFd<File>,
+}
+
+impl TokioDriver {
+    /// Register an eventfd with `ring` and wrap it with tokio's reactor.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a tokio runtime.
+    pub fn new(mut ring: IoRing) -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let eventfd = unsafe { File::from_raw_fd(fd) };
+
+        ring.register_eventfd(eventfd.as_raw_fd())?;
+
+        Ok(TokioDriver {
+            driver: Driver::new(ring),
+            eventfd: AsyncFd::new(eventfd)?,
+        })
+    }
+
+    /// The driver operations are submitted to
+    pub fn driver(&self) -> &Driver {
+        &self.driver
+    }
+
+    /// Submit operations and dispatch completions until an error occurs.
+    ///
+    /// Operations the kernel can't take yet, e.g. because the completion queue
+    /// would overflow, are submitted once completions have made room.
+    pub async fn run(&self) -> io::Result<()> {
+        loop {
+            self.driver.flush()?;
+
+            Wait { driver: self }.await?;
+        }
+    }
+
+    /// Check for completions, clearing the eventfd once they have been signalled
+    fn poll_completions(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut guard = match self.eventfd.poll_read_ready(cx)? {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        let mut count = [0; 8];
+        match guard.try_io(|eventfd| eventfd.get_ref().read(&mut count)) {
+            Ok(Err(e)) => Poll::Ready(Err(e)),
+            Ok(Ok(_)) | Err(_) => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// Resolves when there are completions to dispatch or SQEs that can be
+/// submitted.
+struct Wait<'a> {
+    driver: &'a TokioDriver,
+}
+
+impl Future for Wait<'_> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let driver = self.driver;
+
+        driver.driver.on_prepare(cx.waker());
+        // SQEs held back until completions make room are left to the eventfd
+        if driver.driver.with_ring(|ring| ring.split().0.publishable()) > 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        driver.poll_completions(cx)
+    }
+}
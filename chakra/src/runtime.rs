@@ -0,0 +1,366 @@
+//! A single-threaded executor built around an `IoRing`.
+//!
+//! A `Runtime` runs the tasks spawned on it on the current thread, flushing
+//! the prepared SQEs after every round of polling. When no task is runnable the
+//! thread parks in `io_uring_enter` until an operation completes or a task is
+//! woken from another thread.
+//!
+//! For a thread-per-core setup, run one runtime per thread and set up the
+//! rings of all but the first with `Runtime::worker_builder`, so that they
+//! share a single pool of async workers.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    future::Future,
+    io::{self, Write},
+    os::unix::io::FromRawFd,
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+};
+
+use slab::Slab;
+
+use crate::{rt::Driver, IoRing, IoRingBuilder, Result};
+
+/// Task id of the future passed to `block_on`
+const MAIN: usize = usize::MAX;
+
+type LocalFuture = Pin<Box<dyn Future<Output = ()>>>;
+type WakeFuture = Pin<Box<dyn Future<Output = (io::Result<u32>, Vec<u8>)>>>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Local>>> = const { RefCell::new(None) };
+}
+
+/// Runs futures on the current thread, driving their operations on a ring.
+pub struct Runtime {
+    local: Rc<Local>,
+}
+
+struct Local {
+    driver: Driver,
+    /// Spawned tasks, a slot is empty while its task is being polled
+    tasks: RefCell<Slab<Option<Task>>>,
+    shared: Arc<Shared>,
+    /// Pending read on the eventfd, completing when a task is woken while the
+    /// thread is parked
+    unpark: RefCell<Option<WakeFuture>>,
+}
+
+struct Task {
+    future: LocalFuture,
+    waker: Waker,
+}
+
+/// The part of the runtime wakers can reach, possibly from other threads.
+struct Shared {
+    ready: Mutex<VecDeque<usize>>,
+    parked: AtomicBool,
+    eventfd: File,
+}
+
+impl Shared {
+    fn schedule(&self, id: usize) {
+        self.ready.lock().unwrap().push_back(id);
+
+        if self.parked.load(Ordering::SeqCst) {
+            let _ = (&self.eventfd).write(&1u64.to_ne_bytes());
+        }
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    shared: Arc<Shared>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.shared.schedule(self.id);
+    }
+}
+
+impl Runtime {
+    /// Create a runtime on a ring with the default parameters
+    pub fn new() -> Result<Self> {
+        Self::with_builder(IoRingBuilder::new())
+    }
+
+    /// Create a runtime on a ring set up by `builder`
+    pub fn with_builder(builder: IoRingBuilder) -> Result<Self> {
+        let (ring, _) = builder.build()?;
+        Self::with_ring(ring)
+    }
+
+    /// Create a runtime on a ring that has already been set up, e.g. on a
+    /// `backend::Sim`
+    pub fn with_ring(ring: IoRing) -> Result<Self> {
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if eventfd == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Runtime {
+            local: Rc::new(Local {
+                driver: Driver::new(ring),
+                tasks: RefCell::new(Slab::new()),
+                shared: Arc::new(Shared {
+                    ready: Mutex::new(VecDeque::new()),
+                    parked: AtomicBool::new(false),
+                    eventfd: unsafe { File::from_raw_fd(eventfd) },
+                }),
+                unpark: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// A builder for a ring sharing this runtime's async workers, for the
+    /// runtimes of other threads. It can be sent across threads, but the
+    /// rings have to be set up while this runtime is alive.
+    pub fn worker_builder(&self) -> IoRingBuilder {
+        self.local
+            .driver
+            .with_ring(|ring| IoRingBuilder::new().attach_wq(ring))
+    }
+
+    /// The driver operations are submitted to
+    pub fn driver(&self) -> &Driver {
+        &self.local.driver
+    }
+
+    /// Spawn a task onto this runtime, it starts running once `block_on` is called
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.local.spawn(future)
+    }
+
+    /// Run `future` to completion, along with the tasks spawned meanwhile.
+    ///
+    /// # Panics
+    ///
+    /// If the ring fails for good, e.g. because it was set up disabled and
+    /// never enabled. The kernel being unable to take more submissions for now
+    /// is waited out.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _enter = Enter::new(&self.local);
+        let _driver = self.local.driver.enter();
+        let local = &self.local;
+
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(TaskWaker {
+            id: MAIN,
+            shared: local.shared.clone(),
+        }));
+        local.shared.schedule(MAIN);
+
+        loop {
+            let ready: VecDeque<_> = std::mem::take(&mut *local.shared.ready.lock().unwrap());
+
+            for id in ready {
+                if id == MAIN {
+                    if let Poll::Ready(output) =
+                        future.as_mut().poll(&mut Context::from_waker(&waker))
+                    {
+                        return output;
+                    }
+                } else {
+                    local.poll_task(id);
+                }
+            }
+
+            if let Err(e) = local.tick() {
+                panic!("failed to drive the ring: {}", e);
+            }
+        }
+    }
+}
+
+impl Local {
+    fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let state = Rc::new(RefCell::new(JoinState {
+            output: None,
+            waker: None,
+        }));
+        let join = state.clone();
+
+        let future = async move {
+            let output = future.await;
+            let mut state = join.borrow_mut();
+            state.output = Some(output);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        };
+
+        let mut tasks = self.tasks.borrow_mut();
+        let entry = tasks.vacant_entry();
+        let id = entry.key();
+        entry.insert(Some(Task {
+            future: Box::pin(future),
+            waker: Waker::from(Arc::new(TaskWaker {
+                id,
+                shared: self.shared.clone(),
+            })),
+        }));
+        self.shared.schedule(id);
+
+        JoinHandle { state }
+    }
+
+    fn poll_task(&self, id: usize) {
+        // Wakes can outlive their task, or come in for a task that's running
+        let mut task = match self.tasks.borrow_mut().get_mut(id).and_then(Option::take) {
+            Some(task) => task,
+            None => return,
+        };
+
+        let poll = task
+            .future
+            .as_mut()
+            .poll(&mut Context::from_waker(&task.waker));
+
+        let mut tasks = self.tasks.borrow_mut();
+        match poll {
+            Poll::Ready(()) => {
+                tasks.remove(id);
+            }
+            Poll::Pending => tasks[id] = Some(task),
+        }
+    }
+
+    /// Flush the prepared SQEs and dispatch completions, parking the thread
+    /// if no task is runnable.
+    fn tick(&self) -> io::Result<()> {
+        if !self.shared.ready.lock().unwrap().is_empty() {
+            return self.driver.flush();
+        }
+
+        self.arm_unpark();
+
+        self.shared.parked.store(true, Ordering::SeqCst);
+        // Anything woken before `parked` was set didn't signal the eventfd
+        let res = if self.shared.ready.lock().unwrap().is_empty() {
+            self.driver.park()
+        } else {
+            self.driver.flush()
+        };
+        self.shared.parked.store(false, Ordering::SeqCst);
+
+        res
+    }
+
+    /// Make sure a read is pending on the eventfd, so that remote wakes
+    /// interrupt `park`.
+    fn arm_unpark(&self) {
+        let mut unpark = self.unpark.borrow_mut();
+
+        if let Some(read) = unpark.as_mut() {
+            match read.as_mut().poll(&mut Context::from_waker(&noop_waker())) {
+                Poll::Pending => return,
+                Poll::Ready(_) => {}
+            }
+        }
+
+        let read = self
+            .driver
+            .read_at(&self.shared.eventfd, Vec::with_capacity(8), 0);
+        *unpark = Some(Box::pin(read));
+    }
+}
+
+/// Sets the current runtime for the duration of `block_on`.
+struct Enter {
+    prev: Option<Rc<Local>>,
+}
+
+impl Enter {
+    fn new(local: &Rc<Local>) -> Self {
+        Enter {
+            prev: CURRENT.with(|current| current.borrow_mut().replace(local.clone())),
+        }
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.prev.take());
+    }
+}
+
+/// Spawn a task onto the current runtime.
+///
+/// # Panics
+///
+/// If called outside of `Runtime::block_on`.
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    with_current(|local| local.spawn(future))
+}
+
+/// The driver of the current runtime.
+///
+/// # Panics
+///
+/// If called outside of `Runtime::block_on`.
+pub fn driver() -> Driver {
+    with_current(|local| local.driver.clone())
+}
+
+fn with_current<R>(f: impl FnOnce(&Local) -> R) -> R {
+    CURRENT.with(|current| {
+        let current = current.borrow().clone();
+        f(&current.expect("must be called from within `Runtime::block_on`"))
+    })
+}
+
+/// Resolves to the output of a spawned task.
+///
+/// Dropping the handle detaches the task, it keeps running.
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+struct JoinState<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.borrow_mut();
+
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn noop_waker() -> Waker {
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    Waker::from(Arc::new(Noop))
+}
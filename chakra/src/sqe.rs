@@ -1,33 +1,807 @@
-use std::{convert::TryInto, os::unix::io::AsRawFd, ptr::NonNull};
+use bitflags::bitflags;
+use chakra_sys::IoUringOp;
+
+use crate::{error::Error, Result};
+
+use std::{
+    cmp,
+    convert::TryInto,
+    ffi::CStr,
+    fmt, io, mem,
+    net::Shutdown,
+    os::unix::io::{AsRawFd, RawFd},
+    ptr::NonNull,
+};
 
 pub struct Sqe {
     sqe: NonNull<chakra_sys::io_uring_sqe>,
+    iopoll: bool,
+}
+
+bitflags! {
+    /// Modifier flags applied to a single SQE
+    #[derive(Default)]
+    pub struct SqeFlags: u8 {
+        const IOSQE_FIXED_FILE      = chakra_sys::IOSQE_FIXED_FILE;
+        const IOSQE_IO_DRAIN        = chakra_sys::IOSQE_IO_DRAIN;
+        const IOSQE_IO_LINK         = chakra_sys::IOSQE_IO_LINK;
+        const IOSQE_IO_HARDLINK     = chakra_sys::IOSQE_IO_HARDLINK;
+        const IOSQE_ASYNC           = chakra_sys::IOSQE_ASYNC;
+        const IOSQE_BUFFER_SELECT   = chakra_sys::IOSQE_BUFFER_SELECT;
+    }
+}
+
+bitflags! {
+    /// Flags for `Sqe::prep_fsync`
+    #[derive(Default)]
+    pub struct FsyncFlags: u32 {
+        const IORING_FSYNC_DATASYNC = chakra_sys::IORING_FSYNC_DATASYNC;
+    }
+}
+
+bitflags! {
+    /// Flags accepted by splice(2) and tee(2)
+    #[derive(Default)]
+    pub struct SpliceFlags: u32 {
+        const SPLICE_F_MOVE         = libc::SPLICE_F_MOVE;
+        const SPLICE_F_NONBLOCK     = libc::SPLICE_F_NONBLOCK;
+        const SPLICE_F_MORE         = libc::SPLICE_F_MORE;
+        const SPLICE_F_GIFT         = libc::SPLICE_F_GIFT;
+    }
 }
 
 impl Sqe {
     pub fn from_raw(sqe_ptr: *mut chakra_sys::io_uring_sqe) -> Option<Self> {
-        NonNull::new(sqe_ptr).and_then(|sqe| Some(Sqe { sqe }))
+        Self::new(sqe_ptr, false)
+    }
+
+    pub(crate) fn new(sqe_ptr: *mut chakra_sys::io_uring_sqe, iopoll: bool) -> Option<Self> {
+        NonNull::new(sqe_ptr).map(|sqe| Sqe { sqe, iopoll })
+    }
+
+    /// Set the modifier flags for this SQE, replacing any set previously
+    pub fn set_flags(&mut self, flags: SqeFlags) {
+        unsafe { self.sqe.as_mut() }.flags = flags.bits();
+    }
+
+    /// Set the value handed back in the `user_data` of this SQE's completion
+    pub fn set_user_data(&mut self, user_data: u64) {
+        unsafe { self.sqe.as_mut() }.user_data = user_data;
+    }
+
+    /// Prepare a read of up to `buf.len()` bytes from `io` at `offset`.
+    ///
+    /// `buf` is written to when the operation executes, so it has to be kept
+    /// alive until the operation completes.
+    pub fn prep_read<T>(&mut self, io: T, buf: &mut [u8], offset: usize) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_READ,
+            io.as_raw_fd(),
+            buf.as_mut_ptr() as u64,
+            cmp::min(buf.len(), u32::MAX as usize) as u32,
+            offset as u64,
+        )
+    }
+
+    /// Prepare a write of up to `buf.len()` bytes to `io` at `offset`.
+    ///
+    /// `buf` is read from when the operation executes, so it has to be kept
+    /// alive until the operation completes.
+    pub fn prep_write<T>(&mut self, io: T, buf: &[u8], offset: usize) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_WRITE,
+            io.as_raw_fd(),
+            buf.as_ptr() as u64,
+            cmp::min(buf.len(), u32::MAX as usize) as u32,
+            offset as u64,
+        )
+    }
+
+    /// Prepare a read into `buf`, which has to lie within the registered buffer
+    /// `buf_index`, see `IoRing::register_buffers`
+    pub fn prep_read_fixed<T>(
+        &mut self,
+        io: T,
+        buf: &mut [u8],
+        offset: usize,
+        buf_index: u16,
+    ) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_READ_FIXED,
+            io.as_raw_fd(),
+            buf.as_mut_ptr() as u64,
+            cmp::min(buf.len(), u32::MAX as usize) as u32,
+            offset as u64,
+        )?;
+
+        unsafe { self.sqe.as_mut() }
+            .buf_index_padding
+            .personality
+            .buf_or_group = buf_index;
+
+        Ok(())
+    }
+
+    /// Prepare a write from `buf`, which has to lie within the registered
+    /// buffer `buf_index`, see `IoRing::register_buffers`
+    pub fn prep_write_fixed<T>(
+        &mut self,
+        io: T,
+        buf: &[u8],
+        offset: usize,
+        buf_index: u16,
+    ) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_WRITE_FIXED,
+            io.as_raw_fd(),
+            buf.as_ptr() as u64,
+            cmp::min(buf.len(), u32::MAX as usize) as u32,
+            offset as u64,
+        )?;
+
+        unsafe { self.sqe.as_mut() }
+            .buf_index_padding
+            .personality
+            .buf_or_group = buf_index;
+
+        Ok(())
+    }
+
+    /// Prepare a request handing `bufs` to the kernel as buffers of `buf_len`
+    /// bytes each, with ids counting up from `bid` in group `bgid`. Operations
+    /// selecting a buffer from the group pick one of them when they execute.
+    ///
+    /// `bufs` must stay valid until every buffer has been handed back by a
+    /// completion or removed. A `buf_len` of 0 is rejected.
+    pub fn prep_provide_buffers(
+        &mut self,
+        bufs: &mut [u8],
+        buf_len: u32,
+        bgid: u16,
+        bid: u16,
+    ) -> Result<()> {
+        if buf_len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "provided buffers can't be empty",
+            )
+            .into());
+        }
+        let nr = bufs.len() / buf_len as usize;
+
+        self.prep_rw(
+            IoUringOp::IORING_OP_PROVIDE_BUFFERS,
+            nr as RawFd,
+            bufs.as_mut_ptr() as u64,
+            buf_len,
+            u64::from(bid),
+        )?;
+
+        unsafe { self.sqe.as_mut() }
+            .buf_index_padding
+            .personality
+            .buf_or_group = bgid;
+
+        Ok(())
+    }
+
+    /// Prepare a recv(2) of up to `len` bytes from the socket `io` into a buffer
+    /// picked from group `bgid`, see `prep_provide_buffers`. The buffer id is
+    /// returned by `Cqe::buffer_id`.
+    ///
+    /// This sets `IOSQE_BUFFER_SELECT`, which a later `set_flags` has to keep.
+    pub fn prep_recv_provided<T>(&mut self, io: &T, len: u32, bgid: u16, flags: i32) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(IoUringOp::IORING_OP_RECV, io.as_raw_fd(), 0, len, 0)?;
+
+        let sqe = unsafe { self.sqe.as_mut() };
+        sqe.cmd_flags.msg_flags = flags as u32;
+        sqe.flags = SqeFlags::IOSQE_BUFFER_SELECT.bits();
+        sqe.buf_index_padding.personality.buf_or_group = bgid;
+
+        Ok(())
+    }
+
+    /// Prepare a request to cancel the operation submitted with `user_data`
+    pub fn prep_cancel(&mut self, user_data: u64) -> Result<()> {
+        self.prep_rw(IoUringOp::IORING_OP_ASYNC_CANCEL, -1, user_data, 0, 0)
+    }
+
+    /// Prepare an openat(2) of `path`, relative to `dirfd` unless it's absolute.
+    ///
+    /// `path` is read when the operation executes, so it has to be kept alive
+    /// until the operation completes.
+    pub fn prep_openat(&mut self, dirfd: RawFd, path: &CStr, flags: i32, mode: u32) -> Result<()> {
+        self.prep_rw(
+            IoUringOp::IORING_OP_OPENAT,
+            dirfd,
+            path.as_ptr() as u64,
+            mode,
+            0,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.open_flags = flags as u32;
+
+        Ok(())
+    }
+
+    /// Prepare a close(2) of `fd`
+    pub fn prep_close(&mut self, fd: RawFd) -> Result<()> {
+        self.prep_rw(IoUringOp::IORING_OP_CLOSE, fd, 0, 0, 0)
+    }
+
+    /// Prepare an fsync(2), or fdatasync(2) with `IORING_FSYNC_DATASYNC`
+    pub fn prep_fsync<T>(&mut self, io: &T, flags: FsyncFlags) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(IoUringOp::IORING_OP_FSYNC, io.as_raw_fd(), 0, 0, 0)?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.fsync_flags = flags.bits();
+
+        Ok(())
+    }
+
+    /// Prepare a fallocate(2) of `len` bytes of `io` starting at `offset`
+    pub fn prep_fallocate<T>(&mut self, io: &T, mode: i32, offset: u64, len: u64) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_FALLOCATE,
+            io.as_raw_fd(),
+            len,
+            mode as u32,
+            offset,
+        )
+    }
+
+    /// Prepare a statx(2) of `path` relative to `dirfd`, or of `dirfd` itself
+    /// with an empty path and `AT_EMPTY_PATH`.
+    ///
+    /// `path` and `statx` are used when the operation executes, so they have to
+    /// be kept alive until the operation completes.
+    pub fn prep_statx(
+        &mut self,
+        dirfd: RawFd,
+        path: &CStr,
+        flags: i32,
+        mask: u32,
+        statx: &mut libc::statx,
+    ) -> Result<()> {
+        self.prep_rw(
+            IoUringOp::IORING_OP_STATX,
+            dirfd,
+            path.as_ptr() as u64,
+            mask,
+            statx as *mut libc::statx as u64,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.statx_flags = flags as u32;
+
+        Ok(())
+    }
+
+    /// Prepare an accept4(2) on the listening socket `io`, storing the peer
+    /// address in `addr`.
+    ///
+    /// `addr` and `addrlen` are written when the operation completes, so they
+    /// have to be kept alive until then.
+    pub fn prep_accept<T>(
+        &mut self,
+        io: &T,
+        addr: &mut libc::sockaddr_storage,
+        addrlen: &mut libc::socklen_t,
+        flags: i32,
+    ) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        *addrlen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+        self.prep_rw(
+            IoUringOp::IORING_OP_ACCEPT,
+            io.as_raw_fd(),
+            addr as *mut _ as u64,
+            0,
+            addrlen as *mut _ as u64,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.accept_flags = flags as u32;
+
+        Ok(())
+    }
+
+    /// Prepare a connect(2) of the socket `io` to the `addrlen` bytes of `addr`.
+    ///
+    /// `addr` is read when the operation executes, so it has to be kept alive
+    /// until the operation completes.
+    pub fn prep_connect<T>(
+        &mut self,
+        io: &T,
+        addr: &libc::sockaddr_storage,
+        addrlen: libc::socklen_t,
+    ) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_CONNECT,
+            io.as_raw_fd(),
+            addr as *const _ as u64,
+            0,
+            u64::from(addrlen),
+        )
+    }
+
+    /// Prepare a send(2) of `buf` on the socket `io`, which has to be kept
+    /// alive until the operation completes
+    pub fn prep_send<T>(&mut self, io: &T, buf: &[u8], flags: i32) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_SEND,
+            io.as_raw_fd(),
+            buf.as_ptr() as u64,
+            cmp::min(buf.len(), u32::MAX as usize) as u32,
+            0,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.msg_flags = flags as u32;
+
+        Ok(())
+    }
+
+    /// Prepare a recv(2) into `buf` from the socket `io`, which has to be kept
+    /// alive until the operation completes
+    pub fn prep_recv<T>(&mut self, io: &T, buf: &mut [u8], flags: i32) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_RECV,
+            io.as_raw_fd(),
+            buf.as_mut_ptr() as u64,
+            cmp::min(buf.len(), u32::MAX as usize) as u32,
+            0,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.msg_flags = flags as u32;
+
+        Ok(())
     }
 
-    pub fn prep_read<T>(&mut self, io: T, buf: &mut [u8], offset: usize)
+    /// Prepare a sendmsg(2) on the socket `io`.
+    ///
+    /// `msg` and everything it points to are read when the operation executes,
+    /// so they have to be kept alive until the operation completes.
+    pub fn prep_sendmsg<T>(&mut self, io: &T, msg: &libc::msghdr, flags: i32) -> Result<()>
     where
         T: AsRawFd,
     {
-        let fd = io.as_raw_fd();
+        self.prep_rw(
+            IoUringOp::IORING_OP_SENDMSG,
+            io.as_raw_fd(),
+            msg as *const _ as u64,
+            1,
+            0,
+        )?;
 
-        let iovec = libc::iovec {
-            iov_base: buf.as_mut_ptr() as _,
-            iov_len: buf.len(),
+        unsafe { self.sqe.as_mut() }.cmd_flags.msg_flags = flags as u32;
+
+        Ok(())
+    }
+
+    /// Prepare a recvmsg(2) on the socket `io`.
+    ///
+    /// `msg` and everything it points to are written when the operation
+    /// completes, so they have to be kept alive until then.
+    pub fn prep_recvmsg<T>(&mut self, io: &T, msg: &mut libc::msghdr, flags: i32) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_RECVMSG,
+            io.as_raw_fd(),
+            msg as *mut _ as u64,
+            1,
+            0,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.msg_flags = flags as u32;
+
+        Ok(())
+    }
+
+    /// Prepare a shutdown(2) of one or both halves of the socket `io`
+    pub fn prep_shutdown<T>(&mut self, io: &T, how: Shutdown) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        let how = match how {
+            Shutdown::Read => libc::SHUT_RD,
+            Shutdown::Write => libc::SHUT_WR,
+            Shutdown::Both => libc::SHUT_RDWR,
         };
 
-        unsafe {
-            chakra_sys::io_uring_prep_readv(
-                self.sqe.as_ptr(),
-                fd,
-                &iovec as *const _,
-                1,
-                offset.try_into().unwrap(),
-            );
+        self.prep_rw(
+            IoUringOp::IORING_OP_SHUTDOWN,
+            io.as_raw_fd(),
+            0,
+            how as u32,
+            0,
+        )
+    }
+
+    /// Prepare a splice(2) moving data between two file descriptors, at least
+    /// one of which must be a pipe.
+    pub fn prep_splice(&mut self, splice: Splice) -> Result<()> {
+        let Splice {
+            fd_in,
+            off_in,
+            fd_out,
+            off_out,
+            len,
+            flags,
+        } = splice;
+
+        self.prep_rw(
+            IoUringOp::IORING_OP_SPLICE,
+            fd_out,
+            0,
+            len,
+            off_out.unwrap_or(u64::MAX),
+        )?;
+
+        let sqe = unsafe { self.sqe.as_mut() };
+        sqe.addr_off.splice_off_in = off_in.unwrap_or(u64::MAX);
+        sqe.cmd_flags.splice_flags = flags;
+        sqe.buf_index_padding.personality.splice_fd_in = fd_in;
+
+        Ok(())
+    }
+
+    /// Prepare a tee(2) duplicating data from one pipe into another without
+    /// consuming it.
+    pub fn prep_tee(&mut self, tee: Tee) -> Result<()> {
+        let Tee {
+            fd_in,
+            fd_out,
+            len,
+            flags,
+        } = tee;
+
+        self.prep_rw(IoUringOp::IORING_OP_TEE, fd_out, 0, len, 0)?;
+
+        let sqe = unsafe { self.sqe.as_mut() };
+        sqe.addr_off.splice_off_in = 0;
+        sqe.cmd_flags.splice_flags = flags;
+        sqe.buf_index_padding.personality.splice_fd_in = fd_in;
+
+        Ok(())
+    }
+
+    /// Prepare a posix_fadvise(2) hint for `len` bytes of `io` starting at `offset`
+    pub fn prep_fadvise<T>(
+        &mut self,
+        io: &T,
+        offset: u64,
+        len: u32,
+        advice: FileAdvice,
+    ) -> Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.prep_rw(IoUringOp::IORING_OP_FADVISE, io.as_raw_fd(), 0, len, offset)?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.fadvise_advice = advice as u32;
+
+        Ok(())
+    }
+
+    /// Prepare a madvise(2) hint for the pages backing `mapping`.
+    ///
+    /// `mapping` must start on a page boundary and stay mapped until the
+    /// operation completes. It's borrowed mutably because `MemAdvice::DontNeed`
+    /// and `MemAdvice::Free` may throw its contents away. The kernel takes at
+    /// most `u32::MAX` bytes, longer mappings are rejected.
+    pub fn prep_madvise(&mut self, mapping: &mut [u8], advice: MemAdvice) -> Result<()> {
+        let len = mapping.len().try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "madvise(2) takes at most u32::MAX bytes",
+            )
+        })?;
+
+        self.prep_rw(
+            IoUringOp::IORING_OP_MADVISE,
+            -1,
+            mapping.as_mut_ptr() as u64,
+            len,
+            0,
+        )?;
+
+        unsafe { self.sqe.as_mut() }.cmd_flags.fadvise_advice = advice as u32;
+
+        Ok(())
+    }
+
+    /// Prepare an epoll_ctl(2) call adding, modifying or removing `fd` in the
+    /// interest list of `epfd`.
+    ///
+    /// `event` is read when the operation executes, so like the buffers of the
+    /// other operations it has to be kept alive until the operation completes.
+    /// Moving it is fine, see `EpollEvent`.
+    pub fn prep_epoll_ctl<E, T>(
+        &mut self,
+        epfd: &E,
+        op: EpollOp,
+        fd: &T,
+        event: &EpollEvent,
+    ) -> Result<()>
+    where
+        E: AsRawFd,
+        T: AsRawFd,
+    {
+        self.prep_rw(
+            IoUringOp::IORING_OP_EPOLL_CTL,
+            epfd.as_raw_fd(),
+            event.as_ptr() as u64,
+            op as u32,
+            fd.as_raw_fd() as u64,
+        )
+    }
+
+    /// Fill in the fields common to every opcode, clearing everything else.
+    fn prep_rw(
+        &mut self,
+        op: IoUringOp,
+        fd: RawFd,
+        addr: u64,
+        len: u32,
+        offset: u64,
+    ) -> Result<()> {
+        self.check_opcode(op)?;
+        self.fill_rw(op, fd, addr, len, offset);
+
+        Ok(())
+    }
+
+    /// Prepare an operation that does nothing, completing with a result of 0
+    pub fn prep_nop(&mut self) -> Result<()> {
+        self.prep_rw(IoUringOp::IORING_OP_NOP, -1, 0, 0, 0)
+    }
+
+    /// Turn an SQE that was taken from the queue but couldn't be prepared into
+    /// a no-op, so that whatever it held before isn't submitted again.
+    pub(crate) fn discard(&mut self, user_data: u64) {
+        self.fill_rw(IoUringOp::IORING_OP_NOP, -1, 0, 0, 0);
+        self.set_user_data(user_data);
+    }
+
+    fn fill_rw(&mut self, op: IoUringOp, fd: RawFd, addr: u64, len: u32, offset: u64) {
+        let sqe = unsafe { self.sqe.as_mut() };
+
+        sqe.opcode = op as u8;
+        sqe.flags = 0;
+        sqe.ioprio = 0;
+        sqe.fd = fd;
+        sqe.file_off.off = offset;
+        sqe.addr_off.addr = addr;
+        sqe.len = len;
+        sqe.cmd_flags.rw_flags = 0;
+        sqe.user_data = 0;
+        sqe.buf_index_padding.pad2 = [0; 3];
+    }
+
+    /// IOPOLL rings can only complete operations that go through the block
+    /// layer's polling support, the kernel fails everything else.
+    fn check_opcode(&self, op: IoUringOp) -> Result<()> {
+        use IoUringOp::*;
+
+        match op {
+            _ if !self.iopoll => Ok(()),
+            IORING_OP_READV
+            | IORING_OP_WRITEV
+            | IORING_OP_READ_FIXED
+            | IORING_OP_WRITE_FIXED
+            | IORING_OP_READ
+            | IORING_OP_WRITE => Ok(()),
+            _ => Err(Error::IopollUnsupported(op)),
         }
     }
 }
+
+/// Builder for a splice operation, see `Sqe::prep_splice`.
+///
+/// Offsets are only valid for the side that isn't a pipe; leaving one unset
+/// uses (and advances) the file position instead.
+#[derive(Debug, Clone, Copy)]
+pub struct Splice {
+    fd_in: RawFd,
+    off_in: Option<u64>,
+    fd_out: RawFd,
+    off_out: Option<u64>,
+    len: u32,
+    flags: u32,
+}
+
+impl Splice {
+    pub fn new<I, O>(input: &I, output: &O, len: u32) -> Self
+    where
+        I: AsRawFd,
+        O: AsRawFd,
+    {
+        Splice {
+            fd_in: input.as_raw_fd(),
+            off_in: None,
+            fd_out: output.as_raw_fd(),
+            off_out: None,
+            len,
+            flags: 0,
+        }
+    }
+
+    /// Read the input starting at `offset`
+    pub fn off_in(mut self, offset: u64) -> Self {
+        self.off_in = Some(offset);
+        self
+    }
+
+    /// Write the output starting at `offset`
+    pub fn off_out(mut self, offset: u64) -> Self {
+        self.off_out = Some(offset);
+        self
+    }
+
+    /// Use the file registered with the ring at `index` as the input
+    pub fn fixed_in(mut self, index: u32) -> Self {
+        self.fd_in = index as RawFd;
+        self.flags |= chakra_sys::SPLICE_F_FD_IN_FIXED;
+        self
+    }
+
+    pub fn flags(mut self, flags: SpliceFlags) -> Self {
+        self.flags = (self.flags & chakra_sys::SPLICE_F_FD_IN_FIXED) | flags.bits();
+        self
+    }
+}
+
+/// Builder for a tee operation, see `Sqe::prep_tee`.
+#[derive(Debug, Clone, Copy)]
+pub struct Tee {
+    fd_in: RawFd,
+    fd_out: RawFd,
+    len: u32,
+    flags: u32,
+}
+
+impl Tee {
+    pub fn new<I, O>(input: &I, output: &O, len: u32) -> Self
+    where
+        I: AsRawFd,
+        O: AsRawFd,
+    {
+        Tee {
+            fd_in: input.as_raw_fd(),
+            fd_out: output.as_raw_fd(),
+            len,
+            flags: 0,
+        }
+    }
+
+    /// Use the pipe registered with the ring at `index` as the input
+    pub fn fixed_in(mut self, index: u32) -> Self {
+        self.fd_in = index as RawFd;
+        self.flags |= chakra_sys::SPLICE_F_FD_IN_FIXED;
+        self
+    }
+
+    pub fn flags(mut self, flags: SpliceFlags) -> Self {
+        self.flags = (self.flags & chakra_sys::SPLICE_F_FD_IN_FIXED) | flags.bits();
+        self
+    }
+}
+
+/// Access pattern hints for `Sqe::prep_fadvise`, see posix_fadvise(2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileAdvice {
+    Normal = libc::POSIX_FADV_NORMAL as isize,
+    Sequential = libc::POSIX_FADV_SEQUENTIAL as isize,
+    Random = libc::POSIX_FADV_RANDOM as isize,
+    WillNeed = libc::POSIX_FADV_WILLNEED as isize,
+    DontNeed = libc::POSIX_FADV_DONTNEED as isize,
+    NoReuse = libc::POSIX_FADV_NOREUSE as isize,
+}
+
+/// Access pattern hints for `Sqe::prep_madvise`, see madvise(2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemAdvice {
+    Normal = libc::MADV_NORMAL as isize,
+    Sequential = libc::MADV_SEQUENTIAL as isize,
+    Random = libc::MADV_RANDOM as isize,
+    WillNeed = libc::MADV_WILLNEED as isize,
+    DontNeed = libc::MADV_DONTNEED as isize,
+    Free = libc::MADV_FREE as isize,
+}
+
+/// Operations for `Sqe::prep_epoll_ctl`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpollOp {
+    Add = libc::EPOLL_CTL_ADD as isize,
+    Modify = libc::EPOLL_CTL_MOD as isize,
+    Delete = libc::EPOLL_CTL_DEL as isize,
+}
+
+bitflags! {
+    /// Event masks for `EpollEvent`, see epoll_ctl(2)
+    #[derive(Default)]
+    pub struct EpollFlags: u32 {
+        const EPOLLIN           = libc::EPOLLIN as u32;
+        const EPOLLOUT          = libc::EPOLLOUT as u32;
+        const EPOLLRDHUP        = libc::EPOLLRDHUP as u32;
+        const EPOLLPRI          = libc::EPOLLPRI as u32;
+        const EPOLLERR          = libc::EPOLLERR as u32;
+        const EPOLLHUP          = libc::EPOLLHUP as u32;
+        const EPOLLET           = libc::EPOLLET as u32;
+        const EPOLLONESHOT      = libc::EPOLLONESHOT as u32;
+        const EPOLLWAKEUP       = libc::EPOLLWAKEUP as u32;
+        const EPOLLEXCLUSIVE    = libc::EPOLLEXCLUSIVE as u32;
+    }
+}
+
+/// The event registered by an epoll_ctl operation.
+///
+/// The event lives on the heap so that its address, which is what the SQE
+/// points to, stays the same when the `EpollEvent` itself is moved. It still
+/// has to outlive the operation, see `Sqe::prep_epoll_ctl`.
+pub struct EpollEvent {
+    event: Box<libc::epoll_event>,
+}
+
+impl EpollEvent {
+    pub fn new(events: EpollFlags, data: u64) -> Self {
+        EpollEvent {
+            event: Box::new(libc::epoll_event {
+                events: events.bits(),
+                u64: data,
+            }),
+        }
+    }
+
+    pub fn events(&self) -> EpollFlags {
+        EpollFlags::from_bits_truncate(self.event.events)
+    }
+
+    pub fn data(&self) -> u64 {
+        self.event.u64
+    }
+
+    fn as_ptr(&self) -> *const libc::epoll_event {
+        &*self.event as *const _
+    }
+}
+
+impl fmt::Debug for EpollEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EpollEvent")
+            .field("events", &self.events())
+            .field("data", &self.data())
+            .finish()
+    }
+}
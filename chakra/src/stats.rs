@@ -0,0 +1,374 @@
+//! Counters and latency histograms of a ring, see `IoRing::stats`.
+//!
+//! With the `metrics` feature everything is also reported to the `metrics`
+//! crate as it happens, to whichever recorder is installed:
+//!
+//! * `chakra_sqes_submitted_total`, `chakra_cqes_reaped_total`,
+//!   `chakra_syscalls_total`, `chakra_sq_full_total`,
+//!   `chakra_cq_overflows_total` and `chakra_cancellations_total` counters
+//! * a `chakra_in_flight` gauge
+//! * a `chakra_op_latency_seconds` histogram, labelled with the `op`
+//!
+//! The metrics of all rings in the process are reported together. A ring
+//! registers its metrics when it's set up, so rings set up before the recorder
+//! is installed never report anything.
+//!
+//! Latencies are only tracked, and `chakra_op_latency_seconds` reported, for
+//! rings set up with `IoRingBuilder::track_latency`, as it takes a lock for
+//! every SQE and CQE.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use chakra_sys::IoUringOp;
+
+use crate::cqe::Cqe;
+
+/// Number of buckets of a `Histogram`
+const BUCKETS: usize = 32;
+
+/// What a ring has been up to since it was set up, see `IoRing::stats`
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// SQEs published to the kernel
+    pub sqes_submitted: u64,
+    /// Completions handed out
+    pub cqes_reaped: u64,
+    /// Calls to io_uring_enter(2) and io_uring_register(2)
+    pub syscalls: u64,
+    /// Times `get_sqe` came back empty handed because the submission queue was full
+    pub sq_full: u64,
+    /// Times the kernel was found holding on to completions that didn't fit in
+    /// the completion queue
+    pub cq_overflows: u64,
+    /// Completions of operations that were cancelled, failing with `ECANCELED`
+    pub cancellations: u64,
+    /// Operations submitted whose completion hasn't been reaped yet
+    pub in_flight: u32,
+    /// Time from submission to the completion being reaped, by opcode. Empty
+    /// unless the ring was set up with `IoRingBuilder::track_latency`.
+    pub latency: HashMap<IoUringOp, Histogram>,
+}
+
+/// A histogram of latencies, in buckets whose bounds double from 1µs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    /// In nanoseconds
+    sum: u64,
+    /// In nanoseconds
+    max: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: [0; BUCKETS],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        let bucket = (64 - micros.leading_zeros()) as usize;
+        let nanos = latency.as_nanos() as u64;
+
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    /// Number of latencies recorded
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        self.sum.checked_div(self.count).map(Duration::from_nanos)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// The upper bound of the bucket the `q` quantile falls in, e.g. 0.99 for
+    /// the 99th percentile
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets()
+            .find(|&(_, count)| {
+                seen += count;
+                seen >= rank
+            })
+            .map(|(bound, _)| bound.min(self.max()))
+    }
+
+    /// The non-empty buckets, as the upper bound of the latencies in each
+    /// and how many there were
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(i, &count)| (Duration::from_micros(1 << i), count))
+    }
+}
+
+/// Collects the `Stats` of a ring as its SQEs and CQEs go through the queues
+#[derive(Default)]
+pub(crate) struct StatsCollector {
+    sqes_submitted: AtomicU64,
+    cqes_reaped: AtomicU64,
+    syscalls: AtomicU64,
+    sq_full: AtomicU64,
+    cq_overflows: AtomicU64,
+    cancellations: AtomicU64,
+    /// Only there if latencies are tracked
+    latency: Option<Mutex<Latency>>,
+    #[cfg(feature = "metrics")]
+    exporter: exporter::Exporter,
+}
+
+struct Latency {
+    /// Sequence number, opcode and submission time of the operations in
+    /// flight, by `user_data`
+    in_flight: HashMap<u64, VecDeque<(u64, u8, Instant)>>,
+    /// `user_data` of the operations in `in_flight`, by sequence number, so the
+    /// oldest one can be found without going through all of them
+    order: BTreeMap<u64, u64>,
+    /// Sequence number of the next operation submitted
+    next: u64,
+    /// Most operations tracked at once
+    limit: usize,
+    histograms: HashMap<u8, Histogram>,
+}
+
+impl Latency {
+    fn submit(&mut self, sqe: &chakra_sys::io_uring_sqe) {
+        // Completions that are never reaped, e.g. of SQEs the kernel dropped,
+        // would otherwise pile up
+        if self.order.len() >= self.limit {
+            self.forget_oldest();
+        }
+
+        let seq = self.next;
+        self.next += 1;
+        self.in_flight.entry(sqe.user_data).or_default().push_back((
+            seq,
+            sqe.opcode,
+            Instant::now(),
+        ));
+        self.order.insert(seq, sqe.user_data);
+    }
+
+    fn reap(&mut self, user_data: u64) -> Option<(u8, Duration)> {
+        let (seq, opcode, at) = self.pop(user_data)?;
+        self.order.remove(&seq);
+
+        let elapsed = at.elapsed();
+        self.histograms
+            .entry(opcode)
+            .or_insert_with(Histogram::new)
+            .record(elapsed);
+        Some((opcode, elapsed))
+    }
+
+    fn forget_oldest(&mut self) {
+        let oldest = self
+            .order
+            .iter()
+            .next()
+            .map(|(&seq, &user_data)| (seq, user_data));
+        if let Some((seq, user_data)) = oldest {
+            self.order.remove(&seq);
+            // Each `user_data`'s operations are queued in the order they were
+            // submitted, so the oldest of them all is at the front of its queue
+            self.pop(user_data);
+        }
+    }
+
+    /// Stop tracking the oldest operation in flight with `user_data`
+    fn pop(&mut self, user_data: u64) -> Option<(u64, u8, Instant)> {
+        let ops = self.in_flight.get_mut(&user_data)?;
+        let op = ops.pop_front();
+        if ops.is_empty() {
+            self.in_flight.remove(&user_data);
+        }
+        op
+    }
+}
+
+impl StatsCollector {
+    /// Track the latency of up to `limit` operations in flight at once, the
+    /// oldest being forgotten beyond that
+    pub(crate) fn track_latency(&mut self, limit: usize) {
+        self.latency = Some(Mutex::new(Latency {
+            in_flight: HashMap::new(),
+            order: BTreeMap::new(),
+            next: 0,
+            limit: limit.max(1),
+            histograms: HashMap::new(),
+        }));
+    }
+
+    fn latency(&self) -> Option<MutexGuard<'_, Latency>> {
+        let latency = self.latency.as_ref()?;
+        Some(latency.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// `sqe` is being published
+    pub(crate) fn submit(&self, sqe: &chakra_sys::io_uring_sqe) {
+        self.sqes_submitted.fetch_add(1, Ordering::Relaxed);
+        if let Some(mut latency) = self.latency() {
+            latency.submit(sqe);
+        }
+
+        #[cfg(feature = "metrics")]
+        self.exporter.submit();
+    }
+
+    /// `cqe` is being handed out
+    pub(crate) fn reap(&self, cqe: &Cqe) {
+        self.cqes_reaped.fetch_add(1, Ordering::Relaxed);
+        if cqe.raw_result() == -libc::ECANCELED {
+            self.cancellations.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let elapsed = self
+            .latency()
+            .and_then(|mut latency| latency.reap(cqe.user_data()));
+
+        #[cfg(feature = "metrics")]
+        self.exporter
+            .reap(cqe.raw_result() == -libc::ECANCELED, elapsed);
+        #[cfg(not(feature = "metrics"))]
+        let _ = elapsed;
+    }
+
+    /// The kernel is about to be entered, or resources registered
+    pub(crate) fn syscall(&self) {
+        self.syscalls.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        self.exporter.syscalls.increment(1);
+    }
+
+    /// `get_sqe` found the submission queue full
+    pub(crate) fn sq_full(&self) {
+        self.sq_full.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        self.exporter.sq_full.increment(1);
+    }
+
+    /// Completions are waiting in the kernel's overflow list
+    pub(crate) fn cq_overflow(&self) {
+        self.cq_overflows.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "metrics")]
+        self.exporter.cq_overflows.increment(1);
+    }
+
+    pub(crate) fn snapshot(&self, in_flight: u32) -> Stats {
+        let latency = match self.latency() {
+            Some(latency) => latency
+                .histograms
+                .iter()
+                .filter_map(|(&opcode, &histogram)| {
+                    let op = IoUringOp::ALL.get(opcode as usize)?;
+                    Some((*op, histogram))
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        Stats {
+            sqes_submitted: self.sqes_submitted.load(Ordering::Relaxed),
+            cqes_reaped: self.cqes_reaped.load(Ordering::Relaxed),
+            syscalls: self.syscalls.load(Ordering::Relaxed),
+            sq_full: self.sq_full.load(Ordering::Relaxed),
+            cq_overflows: self.cq_overflows.load(Ordering::Relaxed),
+            cancellations: self.cancellations.load(Ordering::Relaxed),
+            in_flight,
+            latency,
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+mod exporter {
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use chakra_sys::IoUringOp;
+    use metrics::{Counter, Gauge, Histogram};
+
+    /// Handles to the metrics of a ring, registered up front
+    pub(super) struct Exporter {
+        sqes_submitted: Counter,
+        cqes_reaped: Counter,
+        pub(super) syscalls: Counter,
+        pub(super) sq_full: Counter,
+        pub(super) cq_overflows: Counter,
+        cancellations: Counter,
+        in_flight: Gauge,
+        /// Latency histograms by opcode, registered as opcodes show up
+        latency: Mutex<HashMap<u8, Histogram>>,
+    }
+
+    impl Default for Exporter {
+        fn default() -> Self {
+            Exporter {
+                sqes_submitted: metrics::counter!("chakra_sqes_submitted_total"),
+                cqes_reaped: metrics::counter!("chakra_cqes_reaped_total"),
+                syscalls: metrics::counter!("chakra_syscalls_total"),
+                sq_full: metrics::counter!("chakra_sq_full_total"),
+                cq_overflows: metrics::counter!("chakra_cq_overflows_total"),
+                cancellations: metrics::counter!("chakra_cancellations_total"),
+                in_flight: metrics::gauge!("chakra_in_flight"),
+                latency: Mutex::new(HashMap::new()),
+            }
+        }
+    }
+
+    impl Exporter {
+        pub(super) fn submit(&self) {
+            self.sqes_submitted.increment(1);
+            self.in_flight.increment(1.0);
+        }
+
+        pub(super) fn reap(&self, cancelled: bool, latency: Option<(u8, Duration)>) {
+            self.cqes_reaped.increment(1);
+            self.in_flight.decrement(1.0);
+            if cancelled {
+                self.cancellations.increment(1);
+            }
+
+            if let Some((opcode, latency)) = latency {
+                let mut histograms = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+                let histogram = histograms.entry(opcode).or_insert_with(|| {
+                    let op = match IoUringOp::ALL.get(opcode as usize) {
+                        Some(op) => format!("{:?}", op),
+                        None => opcode.to_string(),
+                    };
+                    metrics::histogram!("chakra_op_latency_seconds", "op" => op)
+                });
+                histogram.record(latency.as_secs_f64());
+            }
+        }
+    }
+}
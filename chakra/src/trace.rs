@@ -0,0 +1,168 @@
+//! `tracing` spans and events for the operations of a ring, with the
+//! `tracing` feature.
+//!
+//! Every operation gets an `op` span with the `chakra` target at debug level,
+//! created when its SQE is handed out by `get_sqe` and closed once its
+//! completion is reaped. The fields of the SQE are recorded when it's
+//! submitted, the result and latency when it completes:
+//!
+//! * `opcode`, `fd`, `len`, `off` and `user_data` of the SQE
+//! * `result`, the non-negative result of the CQE, or `errno` if it failed
+//! * `latency_us`, the microseconds from `get_sqe` to the completion being reaped
+//!
+//! The ring also emits events for each batch of SQEs submitted, at trace
+//! level, SQPOLL thread wakeups, at debug level, and completion queue
+//! overflows, as warnings. Without the feature all of this compiles to nothing.
+
+#[cfg(feature = "tracing")]
+pub(crate) use enabled::*;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::*;
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::{Mutex, MutexGuard},
+        time::Instant,
+    };
+
+    use tracing::{field, Span};
+
+    use crate::cqe::Cqe;
+
+    /// The spans of the operations of a ring
+    #[derive(Debug, Default)]
+    pub(crate) struct Tracer {
+        inner: Mutex<Inner>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Inner {
+        /// Spans of SQEs handed out but not submitted, by SQ slot
+        prepared: HashMap<u32, (Span, Instant)>,
+        /// Spans of submitted operations, by `user_data`
+        in_flight: HashMap<u64, VecDeque<(Span, Instant)>>,
+    }
+
+    impl Tracer {
+        fn lock(&self) -> MutexGuard<'_, Inner> {
+            self.inner.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        /// Open the span of the SQE in `slot`, just handed out
+        pub(crate) fn prepare(&self, slot: u32) {
+            let span = tracing::debug_span!(
+                target: "chakra",
+                "op",
+                opcode = field::Empty,
+                fd = field::Empty,
+                len = field::Empty,
+                off = field::Empty,
+                user_data = field::Empty,
+                result = field::Empty,
+                errno = field::Empty,
+                latency_us = field::Empty,
+            );
+
+            let mut inner = self.lock();
+            if span.is_disabled() {
+                inner.prepared.remove(&slot);
+            } else {
+                inner.prepared.insert(slot, (span, Instant::now()));
+            }
+        }
+
+        /// Record the fields of the SQE in `slot` as it's submitted
+        pub(crate) fn submit(&self, slot: u32, sqe: &chakra_sys::io_uring_sqe) {
+            let mut inner = self.lock();
+
+            let (span, prepared) = match inner.prepared.remove(&slot) {
+                Some(prepared) => prepared,
+                None => return,
+            };
+
+            match chakra_sys::IoUringOp::ALL.get(sqe.opcode as usize) {
+                Some(op) => span.record("opcode", field::debug(op)),
+                None => span.record("opcode", sqe.opcode),
+            };
+            span.record("fd", sqe.fd);
+            span.record("len", sqe.len);
+            span.record("off", unsafe { sqe.file_off.off });
+            span.record("user_data", sqe.user_data);
+
+            inner
+                .in_flight
+                .entry(sqe.user_data)
+                .or_default()
+                .push_back((span, prepared));
+        }
+
+        /// Record the result of `cqe` as it's reaped, closing its span
+        pub(crate) fn complete(&self, cqe: &Cqe) {
+            let mut inner = self.lock();
+
+            let spans = match inner.in_flight.get_mut(&cqe.user_data()) {
+                Some(spans) => spans,
+                None => return,
+            };
+            let (span, prepared) = match spans.pop_front() {
+                Some(span) => span,
+                None => return,
+            };
+            if spans.is_empty() {
+                inner.in_flight.remove(&cqe.user_data());
+            }
+
+            match cqe.raw_result() {
+                res if res < 0 => span.record("errno", -res),
+                res => span.record("result", res),
+            };
+            span.record("latency_us", prepared.elapsed().as_micros() as u64);
+        }
+    }
+
+    /// A batch of SQEs was handed to the kernel
+    pub(crate) fn submitted(to_submit: u32, submitted: usize, wait_nr: u32) {
+        tracing::trace!(target: "chakra", to_submit, submitted, wait_nr, "submit");
+    }
+
+    /// The SQPOLL thread had gone idle and is being woken up
+    pub(crate) fn sqpoll_wakeup() {
+        tracing::debug!(target: "chakra", "waking up the SQPOLL thread");
+    }
+
+    /// The completion queue overflowed, with `dropped` completions lost so far
+    pub(crate) fn cq_overflow(dropped: u32) {
+        tracing::warn!(target: "chakra", dropped, "completion queue overflowed");
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use crate::cqe::Cqe;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct Tracer;
+
+    impl Tracer {
+        #[inline]
+        pub(crate) fn prepare(&self, _slot: u32) {}
+
+        #[inline]
+        pub(crate) fn submit(&self, _slot: u32, _sqe: &chakra_sys::io_uring_sqe) {}
+
+        #[inline]
+        pub(crate) fn complete(&self, _cqe: &Cqe) {}
+    }
+
+    #[inline]
+    pub(crate) fn submitted(_to_submit: u32, _submitted: usize, _wait_nr: u32) {}
+
+    #[inline]
+    pub(crate) fn sqpoll_wakeup() {}
+
+    #[inline]
+    pub(crate) fn cq_overflow(_dropped: u32) {}
+}
@@ -0,0 +1,59 @@
+//! Helpers shared by the integration tests.
+
+// Each test binary only uses some of these
+#![allow(dead_code)]
+
+use std::{env, fs, path::PathBuf};
+
+use chakra::{backend::Sim, IoRing, IoRingBuilder};
+
+/// A ring with 16 SQ entries on a clone of `sim`
+pub fn ring(sim: &Sim) -> IoRing {
+    IoRingBuilder::new()
+        .sq_entries(16)
+        .build_with(sim.clone())
+        .unwrap()
+        .0
+}
+
+/// Prepare a NOP for each of `user_data`, without submitting them
+pub fn nops(ring: &mut IoRing, user_data: impl IntoIterator<Item = u64>) {
+    for user_data in user_data {
+        let mut sqe = ring.get_sqe().unwrap();
+        sqe.prep_nop().unwrap();
+        sqe.set_user_data(user_data);
+    }
+}
+
+/// Wait for `n` completions, as their `user_data` and result, with errors as
+/// negative errno values
+pub fn reap(ring: &mut IoRing, n: usize) -> Vec<(u64, i32)> {
+    (0..n)
+        .map(|_| {
+            let cqe = ring.wait_cqe().unwrap();
+            let res = match cqe.result() {
+                Ok(n) => n as i32,
+                Err(e) => -e.raw_os_error().unwrap(),
+            };
+            (cqe.user_data(), res)
+        })
+        .collect()
+}
+
+/// A path in the temporary directory, unique to `name` and this process
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("chakra-test-{}-{}", name, std::process::id()))
+}
+
+/// A file holding `data` open for reading and writing, already unlinked
+pub fn tempfile(name: &str, data: &[u8]) -> fs::File {
+    let path = temp_path(name);
+    fs::write(&path, data).unwrap();
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    file
+}
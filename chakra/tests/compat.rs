@@ -0,0 +1,145 @@
+//! `compat::Compat` driven through the `futures-io` traits, on a runtime over
+//! the simulated backend.
+
+mod common;
+
+use std::{
+    future::poll_fn,
+    io::{Read, Write},
+    os::unix::{fs::FileExt, io::AsRawFd, net},
+    pin::Pin,
+};
+
+use chakra::{
+    backend::Sim,
+    compat::{Compat, OwnedIo},
+    fs::File,
+    net::UnixStream,
+    runtime::Runtime,
+    Fault, FaultRule, IoRing,
+};
+use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+use common::{ring, tempfile};
+
+fn runtime(ring: IoRing) -> Runtime {
+    Runtime::with_ring(ring).unwrap()
+}
+
+async fn read<T: OwnedIo>(compat: &mut Compat<T>, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    let n = poll_fn(|cx| Pin::new(&mut *compat).poll_read(cx, &mut buf))
+        .await
+        .unwrap();
+    buf.truncate(n);
+    buf
+}
+
+async fn write<T: OwnedIo>(compat: &mut Compat<T>, data: &[u8]) -> usize {
+    poll_fn(|cx| Pin::new(&mut *compat).poll_write(cx, data))
+        .await
+        .unwrap()
+}
+
+#[test]
+fn partial_reads() {
+    let (a, mut peer) = net::UnixStream::pair().unwrap();
+    peer.write_all(b"hello world").unwrap();
+    drop(peer);
+
+    runtime(ring(&Sim::new())).block_on(async {
+        let mut compat = Compat::with_capacity(4, UnixStream::from_std(a));
+
+        // Reads are at most the capacity, and smaller ones are served from
+        // what's buffered
+        assert_eq!(read(&mut compat, 16).await, b"hell");
+        assert_eq!(read(&mut compat, 2).await, b"o ");
+        assert_eq!(read(&mut compat, 16).await, b"wo");
+
+        let buf = poll_fn(|cx| {
+            let buf = Pin::new(&mut compat).poll_fill_buf(cx);
+            buf.map_ok(<[u8]>::to_vec)
+        })
+        .await
+        .unwrap();
+        assert_eq!(buf, b"rld");
+        Pin::new(&mut compat).consume(1);
+        assert_eq!(read(&mut compat, 16).await, b"ld");
+
+        assert_eq!(read(&mut compat, 16).await, b"");
+    });
+}
+
+#[test]
+fn partial_writes() {
+    let file = tempfile("compat-writes", b"");
+    let check = file.try_clone().unwrap();
+    let mut ring = ring(&Sim::new());
+    ring.faults()
+        .inject(FaultRule::new(Fault::Short(3)).fd(file.as_raw_fd()));
+
+    runtime(ring).block_on(async {
+        let mut compat = Compat::with_capacity(8, File::from_std(file));
+
+        // Only as much as fits in the buffer is accepted
+        assert_eq!(write(&mut compat, b"0123456789").await, 8);
+        // Waits for the short writes of the first 8 bytes
+        assert_eq!(write(&mut compat, b"89").await, 2);
+        poll_fn(|cx| Pin::new(&mut compat).poll_flush(cx))
+            .await
+            .unwrap();
+    });
+
+    let mut buf = [0; 16];
+    let n = check.read_at(&mut buf, 0).unwrap();
+    assert_eq!(&buf[..n], b"0123456789");
+}
+
+#[test]
+fn flush_and_close() {
+    let (a, mut peer) = net::UnixStream::pair().unwrap();
+
+    runtime(ring(&Sim::new())).block_on(async {
+        let mut compat = Compat::new(UnixStream::from_std(a));
+
+        // Nothing to flush
+        poll_fn(|cx| Pin::new(&mut compat).poll_flush(cx))
+            .await
+            .unwrap();
+
+        assert_eq!(write(&mut compat, b"flushed").await, 7);
+        poll_fn(|cx| Pin::new(&mut compat).poll_flush(cx))
+            .await
+            .unwrap();
+        let mut buf = [0; 7];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"flushed");
+
+        assert_eq!(write(&mut compat, b" closed").await, 7);
+        poll_fn(|cx| Pin::new(&mut compat).poll_close(cx))
+            .await
+            .unwrap();
+    });
+
+    let mut rest = Vec::new();
+    peer.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, b" closed");
+}
+
+#[test]
+fn zero_capacity() {
+    let (a, mut peer) = net::UnixStream::pair().unwrap();
+
+    runtime(ring(&Sim::new())).block_on(async {
+        let mut compat = Compat::with_capacity(0, UnixStream::from_std(a));
+
+        assert_eq!(write(&mut compat, b"xy").await, 1);
+        poll_fn(|cx| Pin::new(&mut compat).poll_flush(cx))
+            .await
+            .unwrap();
+    });
+
+    let mut buf = [0; 1];
+    peer.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"x");
+}
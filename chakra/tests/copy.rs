@@ -0,0 +1,94 @@
+//! `copy::sendfile` on the simulated backend, into a Unix socket.
+
+mod common;
+
+use std::{
+    io::{self, Read},
+    os::unix::net::UnixStream,
+    thread,
+};
+
+use chakra::{backend::Sim, copy};
+
+use common::{nops, reap, ring, tempfile};
+
+/// `len` bytes that don't repeat every pipe chunk
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Run `sendfile` of `range` of a file holding `data`, returning its result
+/// and everything that came out the other end of the socket
+fn sendfile(data: &[u8], range: std::ops::Range<u64>) -> (io::Result<u64>, Vec<u8>) {
+    let file = tempfile("sendfile", data);
+    let (socket, mut peer) = UnixStream::pair().unwrap();
+    let reader = thread::spawn(move || {
+        let mut received = Vec::new();
+        peer.read_to_end(&mut received).unwrap();
+        received
+    });
+
+    let mut ring = ring(&Sim::new());
+    let res = copy::sendfile(&mut ring, &file, &socket, range);
+    drop(socket);
+
+    (res, reader.join().unwrap())
+}
+
+#[test]
+fn chunks() {
+    let data = data(150 * 1024);
+
+    let (res, received) = sendfile(&data, 0..data.len() as u64);
+    assert_eq!(res.unwrap(), data.len() as u64);
+    assert!(received == data);
+}
+
+#[test]
+fn range() {
+    let data = data(4096);
+
+    let (res, received) = sendfile(&data, 100..1100);
+    assert_eq!(res.unwrap(), 1000);
+    assert_eq!(received, &data[100..1100]);
+}
+
+#[test]
+fn past_the_end() {
+    let data = data(4096);
+
+    let (res, received) = sendfile(&data, 4000..8000);
+    assert_eq!(res.unwrap(), 96);
+    assert_eq!(received, &data[4000..]);
+}
+
+#[test]
+fn other_completions() {
+    let file = tempfile("other", b"data");
+    let (socket, _peer) = UnixStream::pair().unwrap();
+    let mut ring = ring(&Sim::new());
+
+    nops(&mut ring, Some(7));
+    ring.submit().unwrap();
+
+    let err = copy::sendfile(&mut ring, &file, &socket, 0..4).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn queue_full() {
+    let file = tempfile("queue-full", b"data");
+    let (socket, _peer) = UnixStream::pair().unwrap();
+    let mut ring = ring(&Sim::new());
+
+    // Room for only one of the linked pair
+    nops(&mut ring, 0..15);
+
+    let err = copy::sendfile(&mut ring, &file, &socket, 0..4).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+
+    // Nothing was left behind to link into what's submitted next
+    assert_eq!(ring.submit().unwrap(), 15);
+    reap(&mut ring, 15);
+    assert!(ring.peek_cqe().is_none());
+}
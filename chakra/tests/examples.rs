@@ -0,0 +1,224 @@
+//! Runs the example binaries end to end.
+//!
+//! `cargo test` builds the examples next to this test, in
+//! `target/<profile>/examples`. Every test is skipped when io_uring isn't
+//! available, e.g. in a container whose seccomp profile blocks it.
+
+mod common;
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    process::{Child, ChildStdout, Command, Output, Stdio},
+};
+
+use chakra::{Flags, IoRing};
+
+use common::temp_path;
+
+fn supported() -> bool {
+    match IoRing::init(1, Flags::empty()) {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("skipping, io_uring is unavailable: {}", e);
+            false
+        }
+    }
+}
+
+fn example(name: &str) -> Command {
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+
+    Command::new(path.join("examples").join(name))
+}
+
+fn run(mut command: Command) -> Output {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// An empty scratch directory for the test `name`, at its `temp_path`
+fn temp_dir(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// Bytes that don't repeat on block boundaries, so misplaced blocks show up
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+/// A server example, killed when dropped
+struct Server {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl Server {
+    fn spawn(mut command: Command) -> Self {
+        let mut child = command.stdout(Stdio::piped()).spawn().unwrap();
+        let addr = Self::listening_addr(child.stdout.take().unwrap());
+
+        Server { child, addr }
+    }
+
+    fn listening_addr(stdout: ChildStdout) -> SocketAddr {
+        let mut line = String::new();
+        BufReader::new(stdout).read_line(&mut line).unwrap();
+
+        line.trim()
+            .strip_prefix("listening on ")
+            .unwrap_or_else(|| panic!("unexpected output: {:?}", line))
+            .parse()
+            .unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn cp() {
+    copy_file("cp");
+}
+
+#[test]
+fn fs_copy() {
+    copy_file("fs_copy");
+}
+
+/// Copy a file with the example `name`, which takes the arguments of `cp`
+fn copy_file(name: &str) {
+    if !supported() {
+        return;
+    }
+    let dir = temp_dir(name);
+    let source = dir.join("source");
+    let destination = dir.join("destination");
+
+    // Not a multiple of the block size, so the last block is a short one
+    let data = contents(3 * 4096 + 123);
+    fs::write(&source, &data).unwrap();
+    fs::set_permissions(&source, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let mut command = example(name);
+    command
+        .args(["--depth", "2", "--block-size", "4096"])
+        .arg(&source)
+        .arg(&destination);
+    run(command);
+
+    assert_eq!(fs::read(&destination).unwrap(), data);
+    let mode = fs::metadata(&destination).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cat() {
+    if !supported() {
+        return;
+    }
+    let dir = temp_dir("cat");
+    let first = dir.join("first");
+    let second = dir.join("second");
+    let empty = dir.join("empty");
+
+    // Spans several batches of the example's registered buffers
+    let first_data = contents(600 * 1024 + 17);
+    let second_data = b"second file\n".to_vec();
+    fs::write(&first, &first_data).unwrap();
+    fs::write(&second, &second_data).unwrap();
+    fs::write(&empty, b"").unwrap();
+
+    let mut command = example("cat");
+    command.arg(&first).arg(&empty).arg(&second);
+    let output = run(command);
+
+    assert_eq!(output.stdout, [first_data, second_data].concat());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn echo() {
+    if !supported() {
+        return;
+    }
+    let server = Server::spawn(example("echo"));
+
+    let mut clients: Vec<_> = (0..4)
+        .map(|_| TcpStream::connect(server.addr).unwrap())
+        .collect();
+
+    for (i, client) in clients.iter_mut().enumerate() {
+        // Larger than a provided buffer, so it's echoed in several pieces
+        let data = contents(10_000 + i);
+        client.write_all(&data).unwrap();
+
+        let mut echoed = vec![0; data.len()];
+        client.read_exact(&mut echoed).unwrap();
+        assert_eq!(echoed, data);
+    }
+}
+
+#[test]
+fn http() {
+    if !supported() {
+        return;
+    }
+    let dir = temp_dir("http");
+    let data = contents(200 * 1024 + 5);
+    fs::write(dir.join("index.html"), &data).unwrap();
+    fs::create_dir(dir.join("sub")).unwrap();
+
+    let mut command = example("http");
+    command.arg(&dir).arg("127.0.0.1:0");
+    let server = Server::spawn(command);
+
+    let get = |target: &str| {
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    };
+
+    let response = get("/index.html");
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        data.len()
+    );
+    assert_eq!(&response[..header.len()], header.as_bytes());
+    assert_eq!(&response[header.len()..], &data[..]);
+
+    for target in &["/missing", "/sub", "/../index.html"] {
+        let response = get(target);
+        assert!(
+            response.starts_with(b"HTTP/1.1 404 Not Found\r\n"),
+            "{}: {}",
+            target,
+            String::from_utf8_lossy(&response)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Faults injected into operations on the simulated backend.
+
+mod common;
+
+use std::{
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use chakra::{
+    backend::{Execution, Sim},
+    runtime::Runtime,
+    Fault, FaultRule, IoUringOp, SqeFlags,
+};
+
+use common::{reap, ring, tempfile};
+
+#[test]
+fn errno_by_opcode() {
+    let file = tempfile("errno", b"data");
+    let mut ring = ring(&Sim::new().execution(Execution::Inline));
+    let mut buf = [0; 4];
+
+    ring.faults().inject(
+        FaultRule::new(Fault::Errno(libc::EIO))
+            .opcode(IoUringOp::IORING_OP_READ)
+            .times(1),
+    );
+
+    for user_data in 1..=2 {
+        let mut sqe = ring.get_sqe().unwrap();
+        sqe.prep_read(file.as_raw_fd(), &mut buf, 0).unwrap();
+        sqe.set_user_data(user_data);
+        ring.submit().unwrap();
+    }
+
+    assert_eq!(reap(&mut ring, 2), [(1, -libc::EIO), (2, 4)]);
+}
+
+#[test]
+fn errno_breaks_link() {
+    let mut ring = ring(&Sim::new().execution(Execution::Inline));
+
+    ring.faults()
+        .inject(FaultRule::new(Fault::Errno(libc::EAGAIN)).user_data(|user_data| user_data == 1));
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_nop().unwrap();
+    sqe.set_flags(SqeFlags::IOSQE_IO_LINK);
+    sqe.set_user_data(1);
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_nop().unwrap();
+    sqe.set_user_data(2);
+    ring.submit().unwrap();
+
+    assert_eq!(
+        reap(&mut ring, 2),
+        [(1, -libc::EAGAIN), (2, -libc::ECANCELED)]
+    );
+}
+
+#[test]
+fn short_reads() {
+    let file = tempfile("short", b"0123456789");
+    let mut ring = ring(&Sim::new());
+
+    ring.faults()
+        .inject(FaultRule::new(Fault::Short(3)).fd(file.as_raw_fd()));
+
+    let runtime = Runtime::with_ring(ring).unwrap();
+    runtime.block_on(async {
+        let file = chakra::fs::File::from_std(file);
+
+        let (res, buf) = file.read_at(Vec::with_capacity(10), 0).await;
+        assert_eq!(res.unwrap(), 3);
+        assert_eq!(buf, b"012");
+
+        let (res, buf) = file.read_exact_at(Vec::with_capacity(10), 0).await;
+        res.unwrap();
+        assert_eq!(buf, b"0123456789");
+    });
+}
+
+#[test]
+fn delay() {
+    let mut ring = ring(&Sim::new().execution(Execution::Inline));
+    let delay = Duration::from_millis(50);
+
+    ring.faults()
+        .inject(FaultRule::new(Fault::Delay(delay)).user_data(|user_data| user_data == 1));
+
+    for user_data in 1..=2 {
+        let mut sqe = ring.get_sqe().unwrap();
+        sqe.prep_nop().unwrap();
+        sqe.set_user_data(user_data);
+    }
+    let start = Instant::now();
+    ring.submit().unwrap();
+
+    assert_eq!(reap(&mut ring, 1), [(2, 0)]);
+    assert!(ring.peek_cqe().is_none());
+    assert_eq!(reap(&mut ring, 1), [(1, 0)]);
+    assert!(start.elapsed() >= delay);
+}
@@ -0,0 +1,159 @@
+//! `fs::File` on a runtime over the simulated backend.
+
+mod common;
+
+use std::{
+    fs,
+    future::Future,
+    io::{self, Seek, SeekFrom},
+    os::unix::io::AsRawFd,
+    path::Path,
+};
+
+use chakra::{
+    backend::Sim,
+    fs::{File, OpenOptions},
+    runtime::Runtime,
+    Fault, FaultRule, IoRing,
+};
+
+use common::{ring, temp_path, tempfile};
+
+fn block_on<F: Future>(ring: IoRing, future: F) -> F::Output {
+    Runtime::with_ring(ring).unwrap().block_on(future)
+}
+
+async fn open_err(options: &OpenOptions, path: &Path) -> io::ErrorKind {
+    match options.open(path).await {
+        Ok(_) => panic!("{:?} opened {}", options, path.display()),
+        Err(e) => e.kind(),
+    }
+}
+
+#[test]
+fn open_options() {
+    let path = temp_path("open-options");
+    let _ = fs::remove_file(&path);
+
+    block_on(ring(&Sim::new()), async {
+        // Combinations std rejects are rejected before anything is opened
+        let invalid = [
+            OpenOptions::new(),
+            OpenOptions::new().read(true).create(true).clone(),
+            OpenOptions::new().append(true).truncate(true).clone(),
+        ];
+        for options in &invalid {
+            assert_eq!(open_err(options, &path).await, io::ErrorKind::InvalidInput);
+        }
+        let read = OpenOptions::new().read(true).clone();
+        assert_eq!(open_err(&read, &path).await, io::ErrorKind::NotFound);
+
+        let mut create_new = OpenOptions::new();
+        create_new.write(true).create_new(true).mode(0o600);
+        let file = create_new.open(&path).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().mode(), 0o600);
+        let (res, _) = file.write_all_at(b"hello".to_vec(), 0).await;
+        res.unwrap();
+        assert_eq!(
+            open_err(&create_new, &path).await,
+            io::ErrorKind::AlreadyExists
+        );
+
+        // Appending ignores the offset
+        let file = OpenOptions::new().append(true).open(&path).await.unwrap();
+        let (res, _) = file.write_at(b" world".to_vec(), 0).await;
+        assert_eq!(res.unwrap(), 6);
+        assert_eq!(fs::read(&path).unwrap(), b"hello world");
+
+        let file = File::open(&path).await.unwrap();
+        let (res, _) = file.write_at(b"x".to_vec(), 0).await;
+        assert_eq!(res.unwrap_err().raw_os_error(), Some(libc::EBADF));
+
+        let file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .await
+            .unwrap();
+        assert!(file.metadata().await.unwrap().is_empty());
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn short_transfers() {
+    let file = tempfile("short-transfers", b"");
+    let mut ring = ring(&Sim::new());
+    ring.faults()
+        .inject(FaultRule::new(Fault::Short(3)).fd(file.as_raw_fd()));
+
+    block_on(ring, async {
+        let file = File::from_std(file);
+
+        let (res, buf) = file.write_all_at(b"0123456789".to_vec(), 2).await;
+        res.unwrap();
+        assert_eq!(buf, b"0123456789");
+
+        let (res, buf) = file.read_exact_at(Vec::with_capacity(10), 2).await;
+        res.unwrap();
+        assert_eq!(buf, b"0123456789");
+
+        // The file ends 2 bytes short, after the bytes there are were read
+        let (res, buf) = file.read_exact_at(Vec::with_capacity(12), 2).await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(buf, b"0123456789");
+    });
+}
+
+#[test]
+fn cursor() {
+    let mut file = tempfile("cursor", b"0123456789");
+    file.seek(SeekFrom::Start(2)).unwrap();
+
+    block_on(ring(&Sim::new()), async {
+        let file = File::from_std(file);
+
+        for expected in [&b"234"[..], b"567"] {
+            let (res, buf) = file.read(Vec::with_capacity(3)).await;
+            assert_eq!(res.unwrap(), 3);
+            assert_eq!(buf, expected);
+        }
+
+        let (res, _) = file.write(b"xyz".to_vec()).await;
+        assert_eq!(res.unwrap(), 3);
+        let (res, _) = file.read(Vec::with_capacity(3)).await;
+        assert_eq!(res.unwrap(), 0);
+
+        // Positional reads don't move the cursor
+        let (res, buf) = file.read_at(Vec::with_capacity(16), 0).await;
+        assert_eq!(res.unwrap(), 11);
+        assert_eq!(buf, b"01234567xyz");
+        let (res, _) = file.read(Vec::with_capacity(3)).await;
+        assert_eq!(res.unwrap(), 0);
+    });
+}
+
+#[test]
+fn set_len() {
+    let file = tempfile("set-len", b"0123456789");
+
+    block_on(ring(&Sim::new()), async {
+        let file = File::from_std(file);
+
+        file.set_len(16).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 16);
+        let (res, buf) = file.read_exact_at(Vec::with_capacity(16), 0).await;
+        res.unwrap();
+        assert_eq!(buf, b"0123456789\0\0\0\0\0\0");
+
+        file.set_len(4).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 4);
+        let (res, buf) = file.read_at(Vec::with_capacity(16), 0).await;
+        assert_eq!(res.unwrap(), 4);
+        assert_eq!(buf, b"0123");
+
+        file.set_len(4).await.unwrap();
+        assert_eq!(file.metadata().await.unwrap().len(), 4);
+    });
+}
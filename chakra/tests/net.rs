@@ -0,0 +1,146 @@
+//! Sockets over loopback, on a runtime over the simulated backend.
+
+mod common;
+
+use std::{
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr},
+    os::unix::net,
+};
+
+use chakra::{
+    backend::Sim,
+    net::{TcpListener, TcpStream, UdpSocket, UnixListener, UnixStream},
+    runtime::{self, Runtime},
+};
+
+use common::{ring, temp_path};
+
+fn runtime() -> Runtime {
+    Runtime::with_ring(ring(&Sim::new())).unwrap()
+}
+
+fn localhost() -> SocketAddr {
+    (Ipv4Addr::LOCALHOST, 0).into()
+}
+
+#[test]
+fn tcp() {
+    runtime().block_on(async {
+        let listener = TcpListener::bind(localhost()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = runtime::spawn_local(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            assert_eq!(stream.peer_addr().unwrap(), peer);
+
+            let (res, buf) = stream.read(Vec::with_capacity(16)).await;
+            assert_eq!(res.unwrap(), 4);
+            assert_eq!(buf, b"ping");
+
+            let (res, _) = stream.write_all(b"pong".to_vec()).await;
+            res.unwrap();
+            stream.shutdown(Shutdown::Write).await.unwrap();
+            peer
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+
+        let (res, _) = stream.write(b"ping".to_vec()).await;
+        assert_eq!(res.unwrap(), 4);
+
+        let (res, buf) = stream.read(Vec::with_capacity(16)).await;
+        assert_eq!(res.unwrap(), 4);
+        assert_eq!(buf, b"pong");
+        // The server shut down its end
+        let (res, _) = stream.read(Vec::with_capacity(16)).await;
+        assert_eq!(res.unwrap(), 0);
+
+        assert_eq!(server.await, stream.local_addr().unwrap());
+    });
+}
+
+#[test]
+fn udp() {
+    runtime().block_on(async {
+        for ip in [Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()] {
+            let a = match UdpSocket::bind(SocketAddr::new(ip, 0)) {
+                Ok(socket) => socket,
+                // No IPv6 loopback, e.g. in a container
+                Err(_) if ip.is_ipv6() => continue,
+                Err(e) => panic!("binding to {}: {}", ip, e),
+            };
+            let b = UdpSocket::bind(SocketAddr::new(ip, 0)).unwrap();
+            let b_addr = b.local_addr().unwrap();
+
+            let (res, _) = a.send_to(b"datagram".to_vec(), b_addr).await;
+            assert_eq!(res.unwrap(), 8);
+
+            let (res, buf) = b.recv_from(Vec::with_capacity(64)).await;
+            let (n, from) = res.unwrap();
+            assert_eq!(n, 8);
+            assert_eq!(buf, b"datagram");
+            assert_eq!(from, a.local_addr().unwrap());
+
+            // A connected socket only talks to its peer
+            b.connect(from).await.unwrap();
+            let (res, _) = b.send(b"reply".to_vec()).await;
+            assert_eq!(res.unwrap(), 5);
+            let (res, buf) = a.recv_from(Vec::with_capacity(64)).await;
+            assert_eq!(res.unwrap(), (5, b_addr));
+            assert_eq!(buf, b"reply");
+        }
+    });
+}
+
+#[test]
+fn unix_pair() {
+    runtime().block_on(async {
+        let (a, b) = net::UnixStream::pair().unwrap();
+        let (a, b) = (UnixStream::from_std(a), UnixStream::from_std(b));
+
+        let (res, _) = a.write_all(b"hello".to_vec()).await;
+        res.unwrap();
+        a.shutdown(Shutdown::Write).await.unwrap();
+
+        let (res, buf) = b.read(Vec::with_capacity(16)).await;
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(buf, b"hello");
+        let (res, _) = b.read(Vec::with_capacity(16)).await;
+        assert_eq!(res.unwrap(), 0);
+    });
+}
+
+#[test]
+fn unix_listener() {
+    let path = temp_path("unix.sock");
+    let _ = fs::remove_file(&path);
+
+    runtime().block_on(async {
+        let listener = UnixListener::bind(&path).unwrap();
+        assert_eq!(
+            listener.local_addr().unwrap().as_pathname(),
+            Some(path.as_path())
+        );
+
+        let server = runtime::spawn_local(async move {
+            let stream = listener.accept().await.unwrap();
+            let (res, buf) = stream.read(Vec::with_capacity(16)).await;
+            assert_eq!(res.unwrap(), 2);
+            buf
+        });
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        assert_eq!(
+            stream.peer_addr().unwrap().as_pathname(),
+            Some(path.as_path())
+        );
+        let (res, _) = stream.write_all(b"hi".to_vec()).await;
+        res.unwrap();
+
+        assert_eq!(server.await, b"hi");
+    });
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,145 @@
+//! Recording ring traffic on the simulated backend and replaying it.
+
+mod common;
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+};
+
+use chakra::{
+    backend::{Order, Replay, Sim},
+    record::{CqeRecord, Event, Recording},
+    IoRing, IoRingBuilder, IoUringOp, SqeFlags,
+};
+
+use common::{temp_path, tempfile};
+
+/// Write `data` at 0 and read it back with a linked read, reaping both
+fn write_then_read(ring: &mut IoRing, file: &fs::File, data: &[u8], buf: &mut [u8]) -> Vec<i32> {
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_write(file.as_raw_fd(), data, 0).unwrap();
+    sqe.set_flags(SqeFlags::IOSQE_IO_LINK);
+    sqe.set_user_data(1);
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_read(file.as_raw_fd(), buf, 0).unwrap();
+    sqe.set_user_data(2);
+    ring.submit_and_wait(2).unwrap();
+
+    (1..=2)
+        .map(|user_data| {
+            let cqe = ring.wait_cqe().unwrap();
+            assert_eq!(cqe.user_data(), user_data);
+            cqe.result().unwrap() as i32
+        })
+        .collect()
+}
+
+fn record(name: &str) -> PathBuf {
+    let path = temp_path(name);
+    let file = tempfile(&format!("{}-data", name), b"");
+    let (mut ring, _) = IoRingBuilder::new()
+        .sq_entries(8)
+        .build_with(Sim::new().order(Order::Submission))
+        .unwrap();
+    let mut buf = [0; 5];
+
+    ring.record(io::BufWriter::new(fs::File::create(&path).unwrap()))
+        .unwrap();
+    assert_eq!(
+        write_then_read(&mut ring, &file, b"hello", &mut buf),
+        [5, 5]
+    );
+    ring.stop_recording().unwrap();
+
+    path
+}
+
+#[test]
+fn record_events() {
+    let path = record("events");
+    let recording = Recording::open(&path).unwrap();
+
+    assert_eq!(recording.header().sq_entries, 8);
+
+    let records = recording.collect::<io::Result<Vec<_>>>().unwrap();
+    assert!(records.windows(2).all(|r| r[0].time <= r[1].time));
+
+    let events: Vec<_> = records.into_iter().map(|record| record.event).collect();
+    match events[..] {
+        [Event::Submit(write), Event::Submit(read), Event::Complete(first), Event::Complete(second)] =>
+        {
+            assert_eq!(write.op(), Some(IoUringOp::IORING_OP_WRITE));
+            assert_eq!(write.flags, SqeFlags::IOSQE_IO_LINK.bits());
+            assert_eq!((write.user_data, write.len), (1, 5));
+            assert_eq!(read.op(), Some(IoUringOp::IORING_OP_READ));
+            assert_eq!((read.user_data, read.off), (2, 0));
+
+            let done = |user_data| CqeRecord {
+                user_data,
+                res: 5,
+                flags: 0,
+            };
+            assert_eq!((first, second), (done(1), done(2)));
+        }
+        _ => panic!("unexpected events {:?}", events),
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay() {
+    let path = record("replay");
+    let replay = Replay::new(Recording::open(&path).unwrap()).unwrap();
+    let (mut ring, _) = IoRingBuilder::new()
+        .sq_entries(8)
+        .build_with(replay.clone())
+        .unwrap();
+
+    // Nothing is executed, the file is left empty and the buffer untouched
+    let file = tempfile("replay-data", b"");
+    let mut buf = [0; 5];
+    assert_eq!(
+        write_then_read(&mut ring, &file, b"hello", &mut buf),
+        [5, 5]
+    );
+    assert_eq!(buf, [0; 5]);
+    assert_eq!(file.metadata().unwrap().len(), 0);
+    assert_eq!(replay.remaining(), 0);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn replay_diverges() {
+    let path = record("diverges");
+    let (mut ring, _) = IoRingBuilder::new()
+        .sq_entries(8)
+        .build_with(Replay::new(Recording::open(&path).unwrap()).unwrap())
+        .unwrap();
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_nop().unwrap();
+    sqe.set_user_data(1);
+    let err = io::Error::from(ring.submit().unwrap_err());
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn not_a_recording() {
+    let path = temp_path("garbage");
+    fs::File::create(&path)
+        .unwrap()
+        .write_all(b"not a recording at all")
+        .unwrap();
+
+    let err = Recording::open(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,538 @@
+//! Rings on the simulated backend, which run everywhere, io_uring or not.
+
+mod common;
+
+use std::{
+    fs,
+    future::Future,
+    io::Write,
+    os::unix::io::{AsRawFd, FromRawFd},
+    os::unix::net::UnixStream,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use chakra::{
+    backend::{Execution, Order, Sim},
+    fs::OpenOptions,
+    net, rt,
+    runtime::{self, Runtime},
+    EpollEvent, EpollFlags, EpollOp, Error, IoRingBuilder, IoUringOp, MemAdvice, Restrictions, Sqe,
+    SqeFlags,
+};
+
+use common::{nops, reap, ring, temp_path, tempfile};
+
+#[test]
+fn reverse_order() {
+    let sim = Sim::new().order(Order::Reverse);
+    let mut ring = ring(&sim);
+
+    nops(&mut ring, 0..4);
+    ring.submit().unwrap();
+
+    let order: Vec<_> = reap(&mut ring, 4).into_iter().map(|(u, _)| u).collect();
+    assert_eq!(order, [3, 2, 1, 0]);
+}
+
+#[test]
+fn shuffle_is_repeatable() {
+    let run = |seed| {
+        let sim = Sim::new().order(Order::Shuffle(seed));
+        let mut ring = ring(&sim);
+
+        nops(&mut ring, 0..16);
+        ring.submit().unwrap();
+        reap(&mut ring, 16)
+            .into_iter()
+            .map(|(u, _)| u)
+            .collect::<Vec<_>>()
+    };
+
+    let order = run(7);
+    assert_eq!(order, run(7));
+    assert_ne!(order, (0..16).collect::<Vec<_>>());
+}
+
+#[test]
+fn manual_release() {
+    let sim = Sim::new().order(Order::Manual).execution(Execution::Inline);
+    let mut ring = ring(&sim);
+
+    nops(&mut ring, 1..=2);
+    ring.submit().unwrap();
+    assert!(ring.peek_cqe().is_none());
+    assert_eq!(sim.held(), [1, 2]);
+
+    assert!(sim.release(2));
+    assert_eq!(ring.peek_cqe().unwrap().user_data(), 2);
+    assert!(ring.peek_cqe().is_none());
+
+    sim.release_all();
+    assert_eq!(ring.peek_cqe().unwrap().user_data(), 1);
+    assert!(!sim.release(1));
+}
+
+#[test]
+fn read_write() {
+    let file = tempfile("rw", b"");
+    let sim = Sim::new().order(Order::Submission);
+    let mut ring = ring(&sim);
+    let data = b"simulated io_uring";
+    let mut buf = [0; 18];
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_write(file.as_raw_fd(), data, 4).unwrap();
+    sqe.set_flags(SqeFlags::IOSQE_IO_LINK);
+    sqe.set_user_data(1);
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_read(file.as_raw_fd(), &mut buf, 4).unwrap();
+    sqe.set_user_data(2);
+    ring.submit_and_wait(2).unwrap();
+
+    assert_eq!(reap(&mut ring, 2), [(1, 18), (2, 18)]);
+    assert_eq!(&buf, data);
+}
+
+#[test]
+fn short_read_breaks_link() {
+    let file = tempfile("short", b"");
+    let sim = Sim::new().execution(Execution::Inline);
+    let mut ring = ring(&sim);
+    let mut buf = [0; 8];
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_read(file.as_raw_fd(), &mut buf, 0).unwrap();
+    sqe.set_flags(SqeFlags::IOSQE_IO_LINK);
+    sqe.set_user_data(1);
+    nops(&mut ring, Some(2));
+    ring.submit().unwrap();
+
+    assert_eq!(reap(&mut ring, 2), [(1, 0), (2, -libc::ECANCELED)]);
+}
+
+#[test]
+fn cancel_blocked_recv() {
+    let (socket, _peer) = UnixStream::pair().unwrap();
+    let sim = Sim::new();
+    let mut ring = ring(&sim);
+    let mut buf = [0; 8];
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_recv(&socket, &mut buf, 0).unwrap();
+    sqe.set_user_data(1);
+    ring.submit().unwrap();
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_cancel(1).unwrap();
+    sqe.set_user_data(2);
+    ring.submit().unwrap();
+
+    let mut cqes = reap(&mut ring, 2);
+    cqes.sort_unstable();
+    assert_eq!(cqes, [(1, -libc::ECANCELED), (2, 0)]);
+}
+
+#[test]
+fn madvise_dontneed() {
+    let sim = Sim::new().execution(Execution::Inline);
+    let mut ring = ring(&sim);
+
+    let len = 4096;
+    let mapping = unsafe {
+        let addr = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(addr, libc::MAP_FAILED);
+        std::slice::from_raw_parts_mut(addr as *mut u8, len)
+    };
+    mapping.fill(0xaa);
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_madvise(mapping, MemAdvice::DontNeed).unwrap();
+    sqe.set_user_data(1);
+    ring.submit().unwrap();
+
+    assert_eq!(reap(&mut ring, 1), [(1, 0)]);
+    // Anonymous private pages read back as zeroes once they're dropped
+    assert!(mapping.iter().all(|&b| b == 0));
+
+    unsafe { libc::munmap(mapping.as_mut_ptr() as *mut _, len) };
+}
+
+#[test]
+fn epoll_ctl() {
+    let (socket, mut peer) = UnixStream::pair().unwrap();
+    let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+    assert!(epfd >= 0);
+    let epoll = unsafe { fs::File::from_raw_fd(epfd) };
+    let runtime = Runtime::with_ring(ring(&Sim::new())).unwrap();
+
+    let event = runtime.block_on(async {
+        let event = EpollEvent::new(EpollFlags::EPOLLIN, 42);
+        let (res, event) = rt::current()
+            .epoll_ctl(&epoll, EpollOp::Add, &socket, event)
+            .await;
+        res.unwrap();
+        event
+    });
+    assert_eq!(event.data(), 42);
+
+    peer.write_all(b"x").unwrap();
+    let mut ready = [libc::epoll_event { events: 0, u64: 0 }; 1];
+    let n = unsafe { libc::epoll_wait(epoll.as_raw_fd(), ready.as_mut_ptr(), 1, 1000) };
+    assert_eq!(n, 1);
+    assert_eq!({ ready[0].u64 }, 42);
+}
+
+#[test]
+fn restrictions() {
+    let (mut ring, _) = IoRingBuilder::new()
+        .sq_entries(16)
+        .disabled()
+        .build_with(Sim::new().execution(Execution::Inline))
+        .unwrap();
+
+    let err = ring.submit().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EBADFD));
+
+    ring.register_restrictions(
+        &Restrictions::new()
+            .sqe_op(IoUringOp::IORING_OP_NOP)
+            .register_op(chakra_sys::IORING_REGISTER_PROBE),
+    )
+    .unwrap();
+    ring.enable_rings().unwrap();
+
+    nops(&mut ring, Some(1));
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_read(-1, &mut [0; 4], 0).unwrap();
+    sqe.set_user_data(2);
+    ring.submit().unwrap();
+    assert_eq!(reap(&mut ring, 2), [(1, 0), (2, -libc::EACCES)]);
+
+    let err = ring.register_files(&[0]).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+    assert!(ring.probe().is_ok());
+}
+
+#[test]
+fn provided_buffers() {
+    let (socket, mut peer) = UnixStream::pair().unwrap();
+    let mut ring = ring(&Sim::new().execution(Execution::Inline));
+    let mut bufs = [0; 16];
+
+    let mut sqe = ring.get_sqe().unwrap();
+    assert!(sqe.prep_provide_buffers(&mut bufs, 0, 1, 0).is_err());
+    sqe.prep_provide_buffers(&mut bufs, 8, 1, 0).unwrap();
+    sqe.set_user_data(1);
+
+    peer.write_all(b"hello").unwrap();
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_recv_provided(&socket, 0, 1, 0).unwrap();
+    sqe.set_user_data(2);
+    ring.submit().unwrap();
+
+    assert_eq!(reap(&mut ring, 1), [(1, 0)]);
+    let cqe = ring.wait_cqe().unwrap();
+    assert_eq!(cqe.result().unwrap(), 5);
+    let start = usize::from(cqe.buffer_id().unwrap()) * 8;
+    assert_eq!(&bufs[start..start + 5], b"hello");
+}
+
+#[test]
+fn probe() {
+    let probe = ring(&Sim::new()).probe().unwrap();
+
+    assert!(probe.is_supported(IoUringOp::IORING_OP_READ));
+    assert!(!probe.is_supported(IoUringOp::IORING_OP_TIMEOUT));
+}
+
+#[test]
+fn iopoll() {
+    let file = tempfile("iopoll", b"polled");
+    let (mut ring, _) = IoRingBuilder::new()
+        .sq_entries(16)
+        .iopoll()
+        .build_with(Sim::new())
+        .unwrap();
+
+    let mut sqe = ring.get_sqe().unwrap();
+    let err = sqe.prep_nop().unwrap_err();
+    assert!(matches!(
+        err,
+        Error::IopollUnsupported(IoUringOp::IORING_OP_NOP)
+    ));
+    sqe.prep_read(file.as_raw_fd(), &mut [0; 0], 0).unwrap();
+
+    let mut buf = [0; 6];
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_read(file.as_raw_fd(), &mut buf, 0).unwrap();
+    sqe.set_user_data(1);
+    ring.submit().unwrap();
+
+    // Completions are polled for by entering the kernel
+    assert_eq!(reap(&mut ring, 2), [(0, 0), (1, 6)]);
+    assert_eq!(&buf, b"polled");
+}
+
+#[test]
+fn split_across_threads() {
+    let mut ring = ring(&Sim::new());
+    let (mut sq, mut cq, submitter) = ring.split();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            for user_data in 0..4 {
+                let mut sqe = sq.get_sqe().unwrap();
+                sqe.prep_nop().unwrap();
+                sqe.set_user_data(user_data);
+            }
+            sq.sync().unwrap();
+            submitter.submit().unwrap();
+        });
+
+        let reaped = scope.spawn(move || {
+            let mut reaped = Vec::new();
+            while reaped.len() < 4 {
+                cq.wait(1).unwrap();
+                reaped.extend(cq.by_ref().map(|cqe| cqe.user_data()));
+            }
+            reaped
+        });
+        let mut reaped = reaped.join().unwrap();
+        reaped.sort_unstable();
+        assert_eq!(reaped, [0, 1, 2, 3]);
+    });
+}
+
+#[test]
+fn held_back_submissions() {
+    let sim = Sim::new().nodrop(false).execution(Execution::Inline);
+    let mut ring = ring(&sim);
+    assert_eq!(ring.params().cq_entries, 32);
+
+    nops(&mut ring, 0..16);
+    assert_eq!(ring.submit().unwrap(), 16);
+    nops(&mut ring, 16..28);
+    assert_eq!(ring.submit().unwrap(), 12);
+
+    // Only as many are submitted as there is room for completions
+    nops(&mut ring, 28..40);
+    assert_eq!(ring.submit().unwrap(), 4);
+    assert_eq!(ring.state().in_flight, 32);
+
+    reap(&mut ring, 8);
+    assert_eq!(ring.submit().unwrap(), 8);
+
+    let reaped = reap(&mut ring, 32);
+    assert!(reaped.iter().map(|&(u, _)| u).eq(8..40));
+    assert_eq!(ring.cq_dropped(), 0);
+}
+
+#[test]
+fn completion_queue_full() {
+    let sim = Sim::new().nodrop(false).execution(Execution::Inline);
+    let mut ring = ring(&sim);
+
+    nops(&mut ring, 0..16);
+    ring.submit().unwrap();
+    nops(&mut ring, 16..32);
+    ring.submit().unwrap();
+
+    nops(&mut ring, Some(32));
+    assert!(matches!(ring.submit(), Err(Error::CompletionQueueFull)));
+    // Waiting for completions is still possible
+    assert_eq!(ring.submit_and_wait(1).unwrap(), 0);
+
+    reap(&mut ring, 1);
+    assert_eq!(ring.submit().unwrap(), 1);
+    reap(&mut ring, 32);
+    assert_eq!(ring.cq_dropped(), 0);
+}
+
+#[test]
+fn overflow_flush() {
+    let mut ring = ring(&Sim::new().execution(Execution::Inline));
+
+    for batch in 0..3 {
+        nops(&mut ring, batch * 16..(batch + 1) * 16);
+        assert_eq!(ring.submit().unwrap(), 16);
+    }
+    let state = ring.state();
+    assert_eq!((state.cq_ready, state.in_flight), (32, 48));
+    assert!(state.cq_overflow_pending);
+
+    // The completions that didn't fit are moved into the queue once it's
+    // drained, in order
+    let reaped = reap(&mut ring, 48);
+    assert!(reaped.iter().map(|&(u, _)| u).eq(0..48));
+    assert!(!ring.state().cq_overflow_pending);
+    assert!(ring.stats().cq_overflows > 0);
+    assert_eq!(ring.cq_dropped(), 0);
+}
+
+#[test]
+fn runtime() {
+    let path = temp_path("runtime");
+    let runtime = Runtime::with_ring(ring(&Sim::new())).unwrap();
+
+    runtime.block_on(async {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .await
+            .unwrap();
+
+        let (res, _) = file.write_all_at(b"hello".to_vec(), 0).await;
+        res.unwrap();
+        let (res, buf) = file.read_at(Vec::with_capacity(5), 0).await;
+        assert_eq!(res.unwrap(), 5);
+        assert_eq!(buf, b"hello");
+
+        file.close().await.unwrap();
+    });
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn runtime_full_queue() {
+    let file = tempfile("full-queue", &[7; 64]);
+    let runtime = Runtime::with_ring(ring(&Sim::new())).unwrap();
+
+    runtime.block_on(async {
+        let driver = rt::current();
+        // More operations than the 16 entries of the SQ
+        let ops: Vec<_> = (0..40)
+            .map(|i| driver.read_at(&file, Vec::with_capacity(1), i))
+            .collect();
+
+        for op in ops {
+            let (res, buf) = op.await;
+            assert_eq!(res.unwrap(), 1);
+            assert_eq!(buf, [7]);
+        }
+    });
+}
+
+#[test]
+fn runtime_backpressure() {
+    let runtime = Runtime::with_ring(ring(&Sim::new().nodrop(false))).unwrap();
+    let pairs: Vec<_> = (0..33).map(|_| UnixStream::pair().unwrap()).collect();
+
+    runtime.block_on(async {
+        let tasks: Vec<_> = pairs
+            .iter()
+            .map(|(socket, _)| {
+                let socket = net::UnixStream::from_std(socket.try_clone().unwrap());
+                runtime::spawn_local(async move {
+                    let (res, buf) = socket.read(Vec::with_capacity(1)).await;
+                    assert_eq!(res.unwrap(), 1);
+                    buf
+                })
+            })
+            .collect();
+
+        // The recvs fill the CQ and the last one is held back, while this
+        // task stays runnable
+        yield_now().await;
+        yield_now().await;
+
+        for (_, peer) in &pairs {
+            (&*peer).write_all(b"x").unwrap();
+        }
+        for task in tasks {
+            assert_eq!(task.await, b"x");
+        }
+    });
+}
+
+/// Let the other runnable tasks run before continuing
+async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[test]
+fn driver_stray_completion() {
+    let (ring, _) = IoRingBuilder::new()
+        .sq_entries(16)
+        .build_with(Sim::new().execution(Execution::Inline))
+        .unwrap();
+    let file = tempfile("stray", b"data");
+    let driver = rt::Driver::new(ring);
+
+    let mut op = driver.submit_op(vec![0; 4], |sqe, buf| {
+        sqe.prep_read(file.as_raw_fd(), buf, 0)
+    });
+    // Another completion with the same `user_data`, which is ignored
+    driver.with_ring(|ring| nops(ring, op.user_data()));
+    driver.submit().unwrap();
+    driver.dispatch();
+    assert_eq!(driver.in_flight(), 0);
+
+    let mut cx = Context::from_waker(Waker::noop());
+    match Pin::new(&mut op).poll(&mut cx) {
+        Poll::Ready((res, buf)) => {
+            assert_eq!(res.unwrap(), 4);
+            assert_eq!(buf, b"data");
+        }
+        Poll::Pending => panic!("the read has completed"),
+    }
+}
+
+#[test]
+fn driver_slot_reuse() {
+    let (socket, mut peer) = UnixStream::pair().unwrap();
+    let driver = rt::Driver::new(ring(&Sim::new()));
+    let recv = |sqe: &mut Sqe, buf: &mut Vec<u8>| sqe.prep_recv(&socket, buf, 0);
+
+    let first = driver.submit_op(vec![0; 4], recv);
+    driver.submit().unwrap();
+    let first_user_data = first.user_data().unwrap();
+    drop(first);
+
+    // The recv completes before its cancellation is even submitted, and the
+    // next operation takes over its slot
+    peer.write_all(b"one").unwrap();
+    driver.with_ring(|ring| ring.split().1.wait(1)).unwrap();
+    driver.dispatch();
+    assert_eq!(driver.in_flight(), 0);
+
+    let mut second = driver.submit_op(vec![0; 4], recv);
+    let second_user_data = second.user_data().unwrap();
+    assert_eq!(second_user_data as u32, first_user_data as u32);
+    assert_ne!(second_user_data, first_user_data);
+
+    // Submits the stale cancellation along with the second recv, which it
+    // must not hit
+    driver.submit().unwrap();
+    peer.write_all(b"two").unwrap();
+
+    let mut cx = Context::from_waker(Waker::noop());
+    let (res, buf) = loop {
+        if let Poll::Ready(done) = Pin::new(&mut second).poll(&mut cx) {
+            break done;
+        }
+        driver.park().unwrap();
+    };
+    assert_eq!(res.unwrap(), 3);
+    assert_eq!(&buf[..3], b"two");
+}
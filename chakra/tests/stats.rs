@@ -0,0 +1,135 @@
+//! Counters and latency histograms of rings on the simulated backend.
+
+mod common;
+
+use std::{io::Write, os::unix::net::UnixStream};
+
+use chakra::{backend::Sim, IoRing, IoRingBuilder, IoUringOp};
+
+use common::{nops, reap, ring};
+
+#[test]
+fn counters() {
+    let mut ring = ring(&Sim::new());
+
+    nops(&mut ring, 0..16);
+    assert!(ring.get_sqe().is_none());
+
+    ring.submit().unwrap();
+    let stats = ring.stats();
+    assert_eq!((stats.sqes_submitted, stats.cqes_reaped), (16, 0));
+    assert_eq!((stats.sq_full, stats.in_flight), (1, 16));
+    assert_eq!(stats.syscalls, 1);
+
+    reap(&mut ring, 16);
+    let stats = ring.stats();
+    assert_eq!((stats.cqes_reaped, stats.in_flight), (16, 0));
+    assert_eq!((stats.cancellations, stats.cq_overflows), (0, 0));
+}
+
+#[test]
+fn cancellations() {
+    let (socket, _peer) = UnixStream::pair().unwrap();
+    let mut ring = ring(&Sim::new());
+    let mut buf = [0; 8];
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_recv(&socket, &mut buf, 0).unwrap();
+    sqe.set_user_data(1);
+    ring.submit().unwrap();
+
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_cancel(1).unwrap();
+    sqe.set_user_data(2);
+    ring.submit().unwrap();
+
+    reap(&mut ring, 2);
+
+    let stats = ring.stats();
+    assert_eq!((stats.sqes_submitted, stats.cqes_reaped), (2, 2));
+    assert_eq!(stats.cancellations, 1);
+    assert_eq!(stats.in_flight, 0);
+}
+
+fn timed_ring(sim: &Sim) -> IoRing {
+    IoRingBuilder::new()
+        .sq_entries(16)
+        .track_latency()
+        .build_with(sim.clone())
+        .unwrap()
+        .0
+}
+
+#[test]
+fn latency() {
+    let mut ring = timed_ring(&Sim::new());
+
+    for user_data in 0..5 {
+        let mut sqe = ring.get_sqe().unwrap();
+        sqe.prep_nop().unwrap();
+        // Operations sharing a `user_data` are matched up in order
+        sqe.set_user_data(user_data % 2);
+    }
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_read(-1, &mut [0; 4], 0).unwrap();
+    ring.submit().unwrap();
+
+    reap(&mut ring, 6);
+
+    let stats = ring.stats();
+    assert_eq!(stats.latency.len(), 2);
+    assert_eq!(stats.latency[&IoUringOp::IORING_OP_READ].count(), 1);
+
+    let nop = &stats.latency[&IoUringOp::IORING_OP_NOP];
+    assert_eq!(nop.count(), 5);
+    assert_eq!(nop.buckets().map(|(_, count)| count).sum::<u64>(), 5);
+    assert!(nop
+        .buckets()
+        .all(|(bound, _)| bound.as_micros().is_power_of_two()));
+    assert!(nop.mean().unwrap() <= nop.max());
+    assert!(nop.quantile(0.5).unwrap() <= nop.quantile(1.0).unwrap());
+    assert!(nop.quantile(1.0).unwrap() <= nop.max());
+}
+
+#[test]
+fn latency_untracked() {
+    let mut ring = ring(&Sim::new());
+
+    nops(&mut ring, 0..4);
+    ring.submit().unwrap();
+    reap(&mut ring, 4);
+
+    let stats = ring.stats();
+    assert_eq!(stats.cqes_reaped, 4);
+    assert!(stats.latency.is_empty());
+}
+
+#[test]
+fn latency_limit() {
+    let (socket, mut peer) = UnixStream::pair().unwrap();
+    let mut ring = timed_ring(&Sim::new());
+    let cq_entries = ring.params().cq_entries as u64;
+    let mut buf = [0; 8];
+
+    // Forgotten once a completion queue's worth of operations after it are in
+    // flight, before it completes
+    let mut sqe = ring.get_sqe().unwrap();
+    sqe.prep_recv(&socket, &mut buf, 0).unwrap();
+    sqe.set_user_data(u64::MAX);
+    ring.submit().unwrap();
+
+    for batch in 0..cq_entries / 16 {
+        nops(&mut ring, (0..16).map(|i| batch * 16 + i));
+        ring.submit().unwrap();
+    }
+    reap(&mut ring, cq_entries as usize);
+
+    peer.write_all(b"late").unwrap();
+    reap(&mut ring, 1);
+
+    let stats = ring.stats();
+    assert_eq!(stats.in_flight, 0);
+    let nop = &stats.latency[&IoUringOp::IORING_OP_NOP];
+    assert_eq!(nop.count(), cq_entries);
+    assert!(!stats.latency.contains_key(&IoUringOp::IORING_OP_RECV));
+}
@@ -0,0 +1,72 @@
+//! `rt::TokioDriver` on a current thread tokio runtime, with the `tokio`
+//! feature, on the simulated backend.
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use std::{io::Write, os::unix::net::UnixStream};
+
+use chakra::{backend::Sim, rt::TokioDriver};
+use tokio::task::{self, LocalSet};
+
+use common::{ring, tempfile};
+
+#[tokio::test(flavor = "current_thread")]
+async fn read_file() {
+    let file = tempfile("tokio", b"hello");
+
+    let local = LocalSet::new();
+    local
+        .run_until(async {
+            let driver = TokioDriver::new(ring(&Sim::new())).unwrap();
+            let handle = driver.driver().clone();
+            task::spawn_local(async move { driver.run().await });
+
+            for offset in 0..2 {
+                let (res, buf) = handle.read_at(&file, Vec::with_capacity(8), offset).await;
+                assert_eq!(res.unwrap(), 5 - offset as u32);
+                assert_eq!(buf, &b"hello"[offset as usize..]);
+            }
+        })
+        .await;
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn backpressure() {
+    let pairs: Vec<_> = (0..33).map(|_| UnixStream::pair().unwrap()).collect();
+
+    let local = LocalSet::new();
+    local
+        .run_until(async {
+            let ring = ring(&Sim::new().nodrop(false));
+            let driver = TokioDriver::new(ring).unwrap();
+            let handle = driver.driver().clone();
+
+            // One more recv than the 32 entries of the CQ, which is held back
+            let ops: Vec<_> = pairs
+                .iter()
+                .map(|(socket, _)| {
+                    handle.submit_op(vec![0; 1], move |sqe, buf| sqe.prep_recv(socket, buf, 0))
+                })
+                .collect();
+            assert_eq!(handle.with_ring(|ring| ring.state().sq_pending), 1);
+
+            let run = task::spawn_local(async move { driver.run().await });
+            // The driver waits for completions instead of spinning
+            for _ in 0..4 {
+                task::yield_now().await;
+            }
+            assert!(!run.is_finished());
+
+            for (_, peer) in &pairs {
+                (&*peer).write_all(b"x").unwrap();
+            }
+            for op in ops {
+                let (res, buf) = op.await;
+                assert_eq!(res.unwrap(), 1);
+                assert_eq!(buf, b"x");
+            }
+            run.abort();
+        })
+        .await;
+}
@@ -0,0 +1,136 @@
+//! Spans and events emitted with the `tracing` feature, on the simulated
+//! backend.
+#![cfg(feature = "tracing")]
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chakra::{
+    backend::{Execution, Sim},
+    IoRingBuilder,
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+/// The fields recorded on every span, and the messages of every event
+#[derive(Default)]
+struct Collected {
+    spans: HashMap<u64, HashMap<String, String>>,
+    closed: Vec<u64>,
+    events: Vec<String>,
+}
+
+#[derive(Clone, Default)]
+struct Collector {
+    next_id: Arc<AtomicU64>,
+    collected: Arc<Mutex<Collected>>,
+}
+
+struct Fields<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for Collector {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.target() == "chakra"
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut collected = self.collected.lock().unwrap();
+        attrs.record(&mut Fields(collected.spans.entry(id).or_default()));
+        span::Id::from_u64(id)
+    }
+
+    fn record(&self, id: &span::Id, values: &span::Record<'_>) {
+        let mut collected = self.collected.lock().unwrap();
+        values.record(&mut Fields(
+            collected.spans.entry(id.into_u64()).or_default(),
+        ));
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = HashMap::new();
+        event.record(&mut Fields(&mut fields));
+        self.collected
+            .lock()
+            .unwrap()
+            .events
+            .push(fields.remove("message").unwrap_or_default());
+    }
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+
+    fn try_close(&self, id: span::Id) -> bool {
+        self.collected.lock().unwrap().closed.push(id.into_u64());
+        true
+    }
+}
+
+#[test]
+fn op_spans() {
+    let collector = Collector::default();
+    let (mut ring, _) = IoRingBuilder::new()
+        .sq_entries(4)
+        .build_with(Sim::new().execution(Execution::Inline))
+        .unwrap();
+
+    tracing::subscriber::with_default(collector.clone(), || {
+        let mut sqe = ring.get_sqe().unwrap();
+        sqe.prep_nop().unwrap();
+        sqe.set_user_data(7);
+        let mut sqe = ring.get_sqe().unwrap();
+        sqe.prep_read(-1, &mut [0; 4], 16).unwrap();
+        sqe.set_user_data(8);
+        ring.submit().unwrap();
+
+        for _ in 0..2 {
+            ring.wait_cqe().unwrap();
+        }
+    });
+
+    let collected = collector.collected.lock().unwrap();
+    let span = |user_data: &str| {
+        collected
+            .spans
+            .iter()
+            .find(|(_, fields)| fields.get("user_data").map(String::as_str) == Some(user_data))
+            .map(|(&id, fields)| (id, fields))
+            .unwrap()
+    };
+
+    let (nop, fields) = span("7");
+    assert_eq!(fields["opcode"], "IORING_OP_NOP");
+    assert_eq!(fields["result"], "0");
+    assert!(fields.contains_key("latency_us"));
+    assert!(collected.closed.contains(&nop));
+
+    let (read, fields) = span("8");
+    assert_eq!(fields["opcode"], "IORING_OP_READ");
+    assert_eq!(
+        (&*fields["fd"], &*fields["len"], &*fields["off"]),
+        ("-1", "4", "16")
+    );
+    assert_eq!(fields["errno"], libc::EBADF.to_string());
+    assert!(!fields.contains_key("result"));
+    assert!(collected.closed.contains(&read));
+
+    assert!(collected.events.iter().any(|message| message == "submit"));
+}